@@ -0,0 +1,673 @@
+use k8s_openapi::api::core::v1::Service;
+use k8s_openapi::api::networking::v1::Ingress;
+use kube::ResourceExt;
+use log::warn;
+use pingress_config::{
+    CircuitBreaker, ClientAuthMode, HashKey, HealthCheck, LoadBalancing, OcspStapling,
+    OutlierDetection, Probe, Protocol, RetryOn, RetryPolicy, Timeouts, TlsVersion,
+};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// Load balancing algorithm for every backend of the Ingress.
+///
+/// `round-robin` (default), `weighted-round-robin` (see [ENDPOINT_WEIGHTS]),
+/// `least-connections`, `random-two-choices` or `consistent-hash`
+const LOAD_BALANCING: &str = "pingress.kinorca.com/load-balancing";
+
+/// Weights of the endpoints for `weighted-round-robin`, set on the Service, by the node or the
+/// zone of the endpoints (e.g. `zone-a=3,zone-b=1`). Other endpoints weigh 1.
+const ENDPOINT_WEIGHTS: &str = "pingress.kinorca.com/endpoint-weights";
+
+/// Hash key for `consistent-hash`: `header:<name>`, `cookie:<name>` or `client-ip` (default)
+const HASH_KEY: &str = "pingress.kinorca.com/hash-key";
+
+/// Active health check of every backend of the Ingress: `tcp` or `http`
+const HEALTH_CHECK: &str = "pingress.kinorca.com/health-check";
+
+/// Request path of the `http` health check (default: `/`)
+const HEALTH_CHECK_PATH: &str = "pingress.kinorca.com/health-check-path";
+
+/// Health check interval (e.g. `10s`)
+const HEALTH_CHECK_INTERVAL: &str = "pingress.kinorca.com/health-check-interval";
+
+/// Health check timeout (e.g. `500ms`)
+const HEALTH_CHECK_TIMEOUT: &str = "pingress.kinorca.com/health-check-timeout";
+
+/// Consecutive successes to mark an endpoint healthy
+const HEALTHY_THRESHOLD: &str = "pingress.kinorca.com/healthy-threshold";
+
+/// Consecutive failures to mark an endpoint unhealthy
+const UNHEALTHY_THRESHOLD: &str = "pingress.kinorca.com/unhealthy-threshold";
+
+/// Consecutive 5xx responses or connect failures to eject an endpoint.
+/// Enables outlier detection.
+const OUTLIER_CONSECUTIVE_ERRORS: &str = "pingress.kinorca.com/outlier-consecutive-errors";
+
+/// Duration of the first ejection (e.g. `30s`), doubled on every ejection in a row
+const OUTLIER_BASE_EJECTION_TIME: &str = "pingress.kinorca.com/outlier-base-ejection-time";
+
+/// Longest ejection (e.g. `5m`)
+const OUTLIER_MAX_EJECTION_TIME: &str = "pingress.kinorca.com/outlier-max-ejection-time";
+
+/// Concurrent requests to each backend. Enables the circuit breaker.
+const MAX_REQUESTS: &str = "pingress.kinorca.com/max-requests";
+
+/// Concurrent requests waiting for a connection to each backend. Enables the circuit breaker.
+const MAX_PENDING_REQUESTS: &str = "pingress.kinorca.com/max-pending-requests";
+
+/// Comma separated conditions to retry a request: `connect-failure`, `gateway-error` or `reset`.
+/// Enables retries.
+const RETRY_ON: &str = "pingress.kinorca.com/retry-on";
+
+/// Attempts of a request including the first one
+const RETRY_ATTEMPTS: &str = "pingress.kinorca.com/retry-attempts";
+
+/// Delay before the first retry (e.g. `25ms`), doubled on every retry
+const RETRY_BACKOFF: &str = "pingress.kinorca.com/retry-backoff";
+
+/// Longest delay between retries (e.g. `250ms`)
+const RETRY_MAX_BACKOFF: &str = "pingress.kinorca.com/retry-max-backoff";
+
+/// `true` (default) to retry on an endpoint that was not tried yet
+const RETRY_DIFFERENT_ENDPOINT: &str = "pingress.kinorca.com/retry-different-endpoint";
+
+/// `true` to retry methods that are not idempotent (e.g. `POST`) too
+const RETRY_NON_IDEMPOTENT: &str = "pingress.kinorca.com/retry-non-idempotent";
+
+/// Concurrent retries as a percentage of the concurrent requests of a route
+const RETRY_BUDGET_PERCENT: &str = "pingress.kinorca.com/retry-budget-percent";
+
+/// Timeout to connect to a backend (e.g. `1s`)
+const CONNECT_TIMEOUT: &str = "pingress.kinorca.com/connect-timeout";
+
+/// Longest wait for data from a backend (e.g. `60s`)
+const READ_TIMEOUT: &str = "pingress.kinorca.com/read-timeout";
+
+/// Longest wait to send data to a backend (e.g. `60s`)
+const WRITE_TIMEOUT: &str = "pingress.kinorca.com/write-timeout";
+
+/// How long an unused connection to a backend is kept open for reuse (e.g. `90s`)
+const IDLE_TIMEOUT: &str = "pingress.kinorca.com/idle-timeout";
+
+/// Deadline of a whole request, retries included (e.g. `30s`)
+const REQUEST_TIMEOUT: &str = "pingress.kinorca.com/request-timeout";
+
+/// Longest wait for data from the backend on an upgraded connection like a WebSocket (e.g. `60m`)
+const UPGRADE_IDLE_TIMEOUT: &str = "pingress.kinorca.com/upgrade-idle-timeout";
+
+/// Protocol spoken to the backends: `HTTP` (default), `HTTPS`, `H2`, `H2C`, `GRPC` or `GRPCS`
+const BACKEND_PROTOCOL: &str = "pingress.kinorca.com/backend-protocol";
+
+/// Server name sent to and verified on TLS backends, instead of the host of the request
+const BACKEND_TLS_SNI: &str = "pingress.kinorca.com/backend-tls-sni";
+
+/// Secret in the namespace of the Ingress whose `ca.crt` verifies the TLS backends
+const BACKEND_TLS_CA_SECRET: &str = "pingress.kinorca.com/backend-tls-ca-secret";
+
+/// `kubernetes.io/tls` Secret in the namespace of the Ingress, presented to the TLS backends
+const BACKEND_TLS_CLIENT_SECRET: &str = "pingress.kinorca.com/backend-tls-client-secret";
+
+/// Whether HTTP/2 is advertised to clients of the hosts over TLS (default `true`)
+const HTTP2: &str = "pingress.kinorca.com/http2";
+
+/// `true` to staple OCSP responses fetched from the responder of the certificates of the hosts
+const OCSP_STAPLING: &str = "pingress.kinorca.com/ocsp-stapling";
+
+/// URL of the OCSP responder, instead of the one of the certificates
+const OCSP_RESPONDER: &str = "pingress.kinorca.com/ocsp-responder";
+
+/// Lowest TLS version accepted from the clients of the hosts:
+/// `TLSv1.0`, `TLSv1.1`, `TLSv1.2` or `TLSv1.3`
+const TLS_MIN_VERSION: &str = "pingress.kinorca.com/tls-min-version";
+
+/// OpenSSL cipher list offered to the clients of the hosts over TLS 1.2 and below
+const TLS_CIPHERS: &str = "pingress.kinorca.com/tls-ciphers";
+
+/// Colon separated curves of the key exchange over TLS 1.2 and below (e.g. `X25519:P-256`)
+const TLS_CURVES: &str = "pingress.kinorca.com/tls-curves";
+
+/// Secret in the namespace of the Ingress whose `ca.crt` verifies the client certificates.
+/// Enables client certificate authentication on the hosts.
+const CLIENT_AUTH_SECRET: &str = "pingress.kinorca.com/client-auth-secret";
+
+/// `required` (default) to reject clients without a valid certificate, or `optional`
+const CLIENT_AUTH: &str = "pingress.kinorca.com/client-auth";
+
+/// Header carrying the subject of the client certificate to the backends
+/// (default: `X-Client-Subject`)
+const CLIENT_SUBJECT_HEADER: &str = "pingress.kinorca.com/client-subject-header";
+
+pub(in crate::controller::common) fn load_balancing(ingress: &Ingress) -> LoadBalancing {
+    let Some(value) = ingress.annotations().get(LOAD_BALANCING) else {
+        return LoadBalancing::default();
+    };
+
+    match value.as_str() {
+        "round-robin" => LoadBalancing::RoundRobin,
+        "weighted-round-robin" => LoadBalancing::WeightedRoundRobin,
+        "least-connections" => LoadBalancing::LeastConnections,
+        "random-two-choices" => LoadBalancing::RandomTwoChoices,
+        "consistent-hash" => LoadBalancing::ConsistentHash {
+            key: hash_key(ingress),
+        },
+        _ => {
+            warn_invalid(ingress, LOAD_BALANCING, value);
+            LoadBalancing::default()
+        }
+    }
+}
+
+fn hash_key(ingress: &Ingress) -> HashKey {
+    let value = ingress
+        .annotations()
+        .get(HASH_KEY)
+        .map(String::as_str)
+        .unwrap_or("client-ip");
+
+    match value.split_once(':') {
+        Some(("header", name)) if !name.is_empty() => HashKey::Header(name.to_string()),
+        Some(("cookie", name)) if !name.is_empty() => HashKey::Cookie(name.to_string()),
+        _ if value == "client-ip" => HashKey::ClientIp,
+        _ => {
+            warn_invalid(ingress, HASH_KEY, value);
+            HashKey::ClientIp
+        }
+    }
+}
+
+pub(in crate::controller::common) fn endpoint_weights(service: &Service) -> HashMap<String, u16> {
+    parse(service, ENDPOINT_WEIGHTS, |v| {
+        v.split(',')
+            .map(|w| {
+                let (key, weight) = w.split_once('=')?;
+                let weight = u16::from_str(weight.trim()).ok().filter(|w| *w > 0)?;
+                Some((key.trim().to_string(), weight))
+            })
+            .collect()
+    })
+    .unwrap_or_default()
+}
+
+pub(in crate::controller::common) fn health_check(ingress: &Ingress) -> Option<HealthCheck> {
+    let probe = match ingress.annotations().get(HEALTH_CHECK)?.as_str() {
+        "tcp" => Probe::Tcp,
+        "http" => Probe::Http {
+            path: ingress
+                .annotations()
+                .get(HEALTH_CHECK_PATH)
+                .cloned()
+                .unwrap_or("/".to_string()),
+        },
+        value => {
+            warn_invalid(ingress, HEALTH_CHECK, value);
+            return None;
+        }
+    };
+
+    let mut health_check = HealthCheck::new(probe);
+    if let Some(v) = parse(ingress, HEALTH_CHECK_INTERVAL, parse_duration_ms) {
+        health_check.interval_ms = v;
+    }
+    if let Some(v) = parse(ingress, HEALTH_CHECK_TIMEOUT, parse_duration_ms) {
+        health_check.timeout_ms = v;
+    }
+    if let Some(v) = parse(ingress, HEALTHY_THRESHOLD, |v| usize::from_str(v).ok()) {
+        health_check.healthy_threshold = v;
+    }
+    if let Some(v) = parse(ingress, UNHEALTHY_THRESHOLD, |v| usize::from_str(v).ok()) {
+        health_check.unhealthy_threshold = v;
+    }
+
+    Some(health_check)
+}
+
+pub(in crate::controller::common) fn outlier_detection(
+    ingress: &Ingress,
+) -> Option<OutlierDetection> {
+    let mut outlier_detection = OutlierDetection {
+        consecutive_errors: parse(ingress, OUTLIER_CONSECUTIVE_ERRORS, |v| {
+            usize::from_str(v).ok().filter(|v| *v > 0)
+        })?,
+        ..OutlierDetection::default()
+    };
+    if let Some(v) = parse(ingress, OUTLIER_BASE_EJECTION_TIME, parse_duration_ms) {
+        outlier_detection.base_ejection_ms = v;
+    }
+    if let Some(v) = parse(ingress, OUTLIER_MAX_EJECTION_TIME, parse_duration_ms) {
+        outlier_detection.max_ejection_ms = v;
+    }
+
+    Some(outlier_detection)
+}
+
+pub(in crate::controller::common) fn circuit_breaker(ingress: &Ingress) -> Option<CircuitBreaker> {
+    let max_requests = parse(ingress, MAX_REQUESTS, |v| usize::from_str(v).ok());
+    let max_pending = parse(ingress, MAX_PENDING_REQUESTS, |v| usize::from_str(v).ok());
+    if max_requests.is_none() && max_pending.is_none() {
+        return None;
+    }
+
+    let mut circuit_breaker = CircuitBreaker::default();
+    if let Some(v) = max_requests {
+        circuit_breaker.max_requests = v;
+    }
+    if let Some(v) = max_pending {
+        circuit_breaker.max_pending = v;
+    }
+
+    Some(circuit_breaker)
+}
+
+pub(in crate::controller::common) fn retry_policy(ingress: &Ingress) -> Option<RetryPolicy> {
+    let mut retry_policy = RetryPolicy {
+        retry_on: parse(ingress, RETRY_ON, |v| {
+            v.split(',')
+                .map(|c| match c.trim() {
+                    "connect-failure" => Some(RetryOn::ConnectFailure),
+                    "gateway-error" => Some(RetryOn::GatewayError),
+                    "reset" => Some(RetryOn::Reset),
+                    _ => None,
+                })
+                .collect()
+        })?,
+        ..RetryPolicy::default()
+    };
+    if let Some(v) = parse(ingress, RETRY_ATTEMPTS, |v| usize::from_str(v).ok()) {
+        retry_policy.attempts = v;
+    }
+    if let Some(v) = parse(ingress, RETRY_BACKOFF, parse_duration_ms) {
+        retry_policy.backoff_ms = v;
+    }
+    if let Some(v) = parse(ingress, RETRY_MAX_BACKOFF, parse_duration_ms) {
+        retry_policy.max_backoff_ms = v;
+    }
+    if let Some(v) = parse(ingress, RETRY_DIFFERENT_ENDPOINT, |v| {
+        bool::from_str(v).ok()
+    }) {
+        retry_policy.different_endpoint = v;
+    }
+    if let Some(v) = parse(ingress, RETRY_NON_IDEMPOTENT, |v| bool::from_str(v).ok()) {
+        retry_policy.non_idempotent = v;
+    }
+    if let Some(v) = parse(ingress, RETRY_BUDGET_PERCENT, |v| usize::from_str(v).ok()) {
+        retry_policy.budget.percent = v;
+    }
+
+    Some(retry_policy)
+}
+
+pub(in crate::controller::common) fn timeouts(ingress: &Ingress) -> Timeouts {
+    Timeouts {
+        connect_ms: parse(ingress, CONNECT_TIMEOUT, parse_duration_ms),
+        read_ms: parse(ingress, READ_TIMEOUT, parse_duration_ms),
+        write_ms: parse(ingress, WRITE_TIMEOUT, parse_duration_ms),
+        idle_ms: parse(ingress, IDLE_TIMEOUT, parse_duration_ms),
+        total_ms: parse(ingress, REQUEST_TIMEOUT, parse_duration_ms),
+        upgrade_idle_ms: parse(ingress, UPGRADE_IDLE_TIMEOUT, parse_duration_ms),
+    }
+}
+
+pub(in crate::controller::common) fn backend_protocol(ingress: &Ingress) -> Protocol {
+    parse(ingress, BACKEND_PROTOCOL, |v| match v {
+        "HTTP" => Some(Protocol::Http),
+        "HTTPS" => Some(Protocol::Https),
+        "H2" => Some(Protocol::H2),
+        "H2C" => Some(Protocol::H2c),
+        "GRPC" => Some(Protocol::Grpc),
+        "GRPCS" => Some(Protocol::Grpcs),
+        _ => None,
+    })
+    .unwrap_or_default()
+}
+
+pub(in crate::controller::common) fn backend_tls_sni(ingress: &Ingress) -> Option<String> {
+    ingress.annotations().get(BACKEND_TLS_SNI).cloned()
+}
+
+pub(in crate::controller::common) fn backend_tls_ca_secret(ingress: &Ingress) -> Option<String> {
+    ingress.annotations().get(BACKEND_TLS_CA_SECRET).cloned()
+}
+
+pub(in crate::controller::common) fn backend_tls_client_secret(
+    ingress: &Ingress,
+) -> Option<String> {
+    ingress
+        .annotations()
+        .get(BACKEND_TLS_CLIENT_SECRET)
+        .cloned()
+}
+
+pub(in crate::controller::common) fn http2(ingress: &Ingress) -> bool {
+    parse(ingress, HTTP2, |v| bool::from_str(v).ok()).unwrap_or(true)
+}
+
+pub(in crate::controller::common) fn ocsp_stapling(ingress: &Ingress) -> Option<OcspStapling> {
+    parse(ingress, OCSP_STAPLING, |v| bool::from_str(v).ok())?.then(|| OcspStapling::Fetch {
+        responder: ingress.annotations().get(OCSP_RESPONDER).cloned(),
+    })
+}
+
+pub(in crate::controller::common) fn tls_min_version(ingress: &Ingress) -> Option<TlsVersion> {
+    parse(ingress, TLS_MIN_VERSION, |v| match v {
+        "TLSv1.0" => Some(TlsVersion::Tls10),
+        "TLSv1.1" => Some(TlsVersion::Tls11),
+        "TLSv1.2" => Some(TlsVersion::Tls12),
+        "TLSv1.3" => Some(TlsVersion::Tls13),
+        _ => None,
+    })
+}
+
+pub(in crate::controller::common) fn tls_ciphers(ingress: &Ingress) -> Option<String> {
+    ingress.annotations().get(TLS_CIPHERS).cloned()
+}
+
+pub(in crate::controller::common) fn tls_curves(ingress: &Ingress) -> Option<String> {
+    ingress.annotations().get(TLS_CURVES).cloned()
+}
+
+pub(in crate::controller::common) fn client_auth_secret(ingress: &Ingress) -> Option<String> {
+    ingress.annotations().get(CLIENT_AUTH_SECRET).cloned()
+}
+
+pub(in crate::controller::common) fn client_auth_mode(ingress: &Ingress) -> ClientAuthMode {
+    parse(ingress, CLIENT_AUTH, |v| match v {
+        "required" => Some(ClientAuthMode::Required),
+        "optional" => Some(ClientAuthMode::Optional),
+        _ => None,
+    })
+    .unwrap_or_default()
+}
+
+pub(in crate::controller::common) fn client_subject_header(ingress: &Ingress) -> Option<String> {
+    parse(ingress, CLIENT_SUBJECT_HEADER, |v| {
+        let valid = !v.is_empty() && v.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+        valid.then(|| v.to_string())
+    })
+}
+
+fn parse<T>(
+    resource: &impl ResourceExt,
+    key: &str,
+    parser: impl Fn(&str) -> Option<T>,
+) -> Option<T> {
+    let value = resource.annotations().get(key)?;
+    let parsed = parser(value.as_str());
+    if parsed.is_none() {
+        warn_invalid(resource, key, value);
+    }
+    parsed
+}
+
+fn warn_invalid(resource: &impl ResourceExt, key: &str, value: &str) {
+    warn!(
+        "Invalid {key} '{value}' on {}/{}",
+        resource.namespace().unwrap_or_default(),
+        resource.name_any()
+    );
+}
+
+/// Parse a duration such as `500ms`, `10s` or `1m` into milliseconds. A bare number is seconds.
+fn parse_duration_ms(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => value.split_at(i),
+        None => (value, "s"),
+    };
+    let number = u64::from_str(number).ok()?;
+    match unit {
+        "ms" => Some(number),
+        "s" => number.checked_mul(1_000),
+        "m" => number.checked_mul(60_000),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+pub(in crate::controller::common) mod tests {
+    use crate::controller::common::annotations::{
+        endpoint_weights, hash_key, load_balancing, parse_duration_ms, retry_policy,
+        ENDPOINT_WEIGHTS, HASH_KEY, LOAD_BALANCING, RETRY_ATTEMPTS, RETRY_BACKOFF,
+        RETRY_NON_IDEMPOTENT, RETRY_ON,
+    };
+    use k8s_openapi::api::core::v1::Service;
+    use k8s_openapi::api::networking::v1::Ingress;
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+    use log::{Level, LevelFilter, Log, Metadata, Record};
+    use pingress_config::{HashKey, LoadBalancing, RetryOn, RetryPolicy};
+    use std::cell::RefCell;
+    use std::collections::{BTreeMap, HashMap};
+
+    thread_local! {
+        static WARNINGS: RefCell<Option<Vec<String>>> = const { RefCell::new(None) };
+    }
+
+    /// Records the warnings of the threads calling [warnings]
+    struct Capture;
+
+    impl Log for Capture {
+        fn enabled(&self, metadata: &Metadata) -> bool {
+            metadata.level() <= Level::Warn
+        }
+
+        fn log(&self, record: &Record) {
+            if self.enabled(record.metadata()) {
+                WARNINGS.with_borrow_mut(|w| {
+                    if let Some(w) = w.as_mut() {
+                        w.push(record.args().to_string());
+                    }
+                });
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    static CAPTURE: Capture = Capture;
+
+    /// Result of the function, with the warnings it logs
+    pub(in crate::controller::common) fn warnings<T>(f: impl FnOnce() -> T) -> (T, Vec<String>) {
+        // Only the first test sets the logger
+        let _ = log::set_logger(&CAPTURE);
+        log::set_max_level(LevelFilter::Warn);
+        WARNINGS.set(Some(Vec::new()));
+        let result = f();
+        (result, WARNINGS.take().unwrap_or_default())
+    }
+
+    /// Ingress `default/app` with the annotations
+    pub(in crate::controller::common) fn annotated(annotations: &[(&str, &str)]) -> Ingress {
+        Ingress {
+            metadata: ObjectMeta {
+                name: Some("app".to_string()),
+                namespace: Some("default".to_string()),
+                annotations: Some(
+                    annotations
+                        .iter()
+                        .map(|(k, v)| (k.to_string(), v.to_string()))
+                        .collect::<BTreeMap<_, _>>(),
+                ),
+                ..ObjectMeta::default()
+            },
+            ..Ingress::default()
+        }
+    }
+
+    #[test]
+    fn hash_keys() {
+        let cases = [
+            (None, HashKey::ClientIp, false),
+            (Some("client-ip"), HashKey::ClientIp, false),
+            (
+                Some("header:x-user"),
+                HashKey::Header("x-user".to_string()),
+                false,
+            ),
+            (
+                Some("cookie:session"),
+                HashKey::Cookie("session".to_string()),
+                false,
+            ),
+            (Some("header:"), HashKey::ClientIp, true),
+            (Some("query:id"), HashKey::ClientIp, true),
+            (Some("client_ip"), HashKey::ClientIp, true),
+        ];
+        for (value, expected, invalid) in cases {
+            let annotations: Vec<_> = value.map(|v| (HASH_KEY, v)).into_iter().collect();
+            let (key, logged) = warnings(|| hash_key(&annotated(&annotations)));
+            assert_eq!(key, expected, "{value:?}");
+            if invalid {
+                assert_eq!(
+                    logged,
+                    [format!(
+                        "Invalid {HASH_KEY} '{}' on default/app",
+                        value.unwrap()
+                    )]
+                );
+            } else {
+                assert!(logged.is_empty(), "{value:?}: {logged:?}");
+            }
+        }
+
+        let ingress = annotated(&[(LOAD_BALANCING, "consistent-hash"), (HASH_KEY, "cookie:id")]);
+        assert_eq!(
+            load_balancing(&ingress),
+            LoadBalancing::ConsistentHash {
+                key: HashKey::Cookie("id".to_string())
+            }
+        );
+    }
+
+    #[test]
+    fn weights() {
+        let service = |weights: &str| Service {
+            metadata: ObjectMeta {
+                name: Some("app".to_string()),
+                namespace: Some("default".to_string()),
+                annotations: Some(BTreeMap::from([(
+                    ENDPOINT_WEIGHTS.to_string(),
+                    weights.to_string(),
+                )])),
+                ..ObjectMeta::default()
+            },
+            ..Service::default()
+        };
+
+        let (parsed, logged) = warnings(|| endpoint_weights(&service("pod-a=3, pod-b = 1")));
+        assert_eq!(
+            parsed,
+            HashMap::from([("pod-a".to_string(), 3), ("pod-b".to_string(), 1)])
+        );
+        assert!(logged.is_empty(), "{logged:?}");
+        assert_eq!(endpoint_weights(&Service::default()), HashMap::new());
+
+        // A malformed entry rejects the whole map
+        for value in [
+            "pod-a=3,pod-b",
+            "pod-a=0",
+            "pod-a=-1",
+            "pod-a=65536",
+            "pod-a=heavy",
+            "pod-a=3;pod-b=1",
+            "",
+        ] {
+            let (parsed, logged) = warnings(|| endpoint_weights(&service(value)));
+            assert_eq!(parsed, HashMap::new(), "{value}");
+            assert_eq!(
+                logged,
+                [format!(
+                    "Invalid {ENDPOINT_WEIGHTS} '{value}' on default/app"
+                )]
+            );
+        }
+    }
+
+    #[test]
+    fn durations() {
+        let cases = [
+            ("500ms", Some(500)),
+            ("10s", Some(10_000)),
+            ("1m", Some(60_000)),
+            (" 30 ", Some(30_000)),
+            ("0", Some(0)),
+            ("1h", None),
+            ("-1s", None),
+            ("1.5s", None),
+            ("ms", None),
+            ("", None),
+            ("18446744073709551615ms", Some(u64::MAX)),
+            ("18446744073709551615s", None),
+            ("18446744073709551616ms", None),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(parse_duration_ms(value), expected, "{value}");
+        }
+    }
+
+    #[test]
+    fn retry_policies() {
+        let cases = [
+            (vec![], None),
+            (
+                vec![(RETRY_ON, "connect-failure, gateway-error")],
+                Some(RetryPolicy {
+                    retry_on: vec![RetryOn::ConnectFailure, RetryOn::GatewayError],
+                    ..RetryPolicy::default()
+                }),
+            ),
+            (
+                vec![
+                    (RETRY_ON, "reset"),
+                    (RETRY_ATTEMPTS, "5"),
+                    (RETRY_BACKOFF, "10ms"),
+                    (RETRY_NON_IDEMPOTENT, "true"),
+                ],
+                Some(RetryPolicy {
+                    retry_on: vec![RetryOn::Reset],
+                    attempts: 5,
+                    backoff_ms: 10,
+                    non_idempotent: true,
+                    ..RetryPolicy::default()
+                }),
+            ),
+            // Settings without retry-on do not enable retries
+            (vec![(RETRY_ATTEMPTS, "5")], None),
+        ];
+        for (annotations, expected) in cases {
+            let (policy, logged) = warnings(|| retry_policy(&annotated(&annotations)));
+            assert_eq!(policy, expected, "{annotations:?}");
+            assert!(logged.is_empty(), "{annotations:?}: {logged:?}");
+        }
+    }
+
+    #[test]
+    fn invalid_retry_policies() {
+        // An unknown condition rejects the whole policy
+        let (policy, logged) =
+            warnings(|| retry_policy(&annotated(&[(RETRY_ON, "connect-failure,teapot")])));
+        assert_eq!(policy, None);
+        assert_eq!(
+            logged,
+            [format!(
+                "Invalid {RETRY_ON} 'connect-failure,teapot' on default/app"
+            )]
+        );
+
+        // Other invalid settings keep their default
+        let (policy, logged) = warnings(|| {
+            retry_policy(&annotated(&[
+                (RETRY_ON, "reset"),
+                (RETRY_ATTEMPTS, "many"),
+                (RETRY_BACKOFF, "-1s"),
+            ]))
+        });
+        assert_eq!(
+            policy,
+            Some(RetryPolicy {
+                retry_on: vec![RetryOn::Reset],
+                ..RetryPolicy::default()
+            })
+        );
+        assert_eq!(logged.len(), 2, "{logged:?}");
+    }
+}
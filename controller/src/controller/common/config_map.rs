@@ -0,0 +1,72 @@
+use crate::controller::common::endpoints::resolve_endpoints;
+use crate::controller::common::ingresses::{default_tls_file, GetFromIngresses};
+use crate::controller::common::resources::resolve_resources;
+use crate::controller::common::secrets::PublishedTls;
+use crate::controller::common::{
+    secret_path, ProxyContext, CONFIG_KEY, CONFIG_MAP_NAME, FIELD_MANAGER,
+};
+use k8s_openapi::api::core::v1::ConfigMap;
+use k8s_openapi::api::networking::v1::Ingress;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use kube::api::{DeleteParams, Patch, PatchParams};
+use kube::Api;
+use pingress_config::Tls;
+use std::collections::BTreeMap;
+
+pub(in crate::controller) async fn apply_config_map(
+    ctx: &impl ProxyContext,
+    ingresses: &[Ingress],
+    tls: &PublishedTls,
+) -> Result<(), kube::Error> {
+    let mut config = ingresses.config();
+    // Hosts without a valid TLS Secret are served in plain text only
+    for rule in config.rules.iter_mut() {
+        if rule.host.as_ref().is_none_or(|h| !tls.hosts.contains(h)) {
+            rule.tls = None;
+        }
+    }
+    config.default_tls = tls.default.then(|| {
+        Tls::new(
+            secret_path(default_tls_file("key").as_str()),
+            secret_path(default_tls_file("crt").as_str()),
+        )
+    });
+    config.not_found_body = ctx.not_found_body().map(str::to_string);
+    resolve_endpoints(ctx.client(), &mut config).await?;
+    resolve_resources(ctx.client(), &mut config).await?;
+
+    let config = serde_json::to_string(&config).map_err(kube::Error::SerdeError)?;
+
+    let config_map = ConfigMap {
+        metadata: ObjectMeta {
+            name: Some(CONFIG_MAP_NAME.to_string()),
+            namespace: Some(ctx.namespace().to_string()),
+            labels: ctx.manifest_labels(),
+            ..ObjectMeta::default()
+        },
+        data: Some(BTreeMap::from([(CONFIG_KEY.to_string(), config)])),
+        ..ConfigMap::default()
+    };
+
+    let api: Api<ConfigMap> = Api::namespaced(ctx.client(), ctx.namespace());
+    api.patch(
+        CONFIG_MAP_NAME,
+        &PatchParams::apply(FIELD_MANAGER),
+        &Patch::Apply(config_map),
+    )
+    .await?;
+
+    Ok(())
+}
+
+pub(in crate::controller) async fn cleanup_config_map(
+    ctx: &impl ProxyContext,
+) -> Result<(), kube::Error> {
+    let api: Api<ConfigMap> = Api::namespaced(ctx.client(), ctx.namespace());
+    if api.get_opt(CONFIG_MAP_NAME).await?.is_some() {
+        api.delete(CONFIG_MAP_NAME, &DeleteParams::default())
+            .await?;
+    }
+
+    Ok(())
+}
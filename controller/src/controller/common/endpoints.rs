@@ -0,0 +1,526 @@
+use crate::controller::common::annotations::endpoint_weights;
+use k8s_openapi::api::core::v1::{Service, ServicePort, ServiceSpec};
+use k8s_openapi::api::discovery::v1::EndpointSlice;
+use kube::api::ListParams;
+use kube::{Api, Client};
+use log::warn;
+use pingress_config::{Backend, Endpoint, PingressConfiguration, Port};
+use std::collections::{HashMap, HashSet};
+
+/// Fill each Service backend with the ready endpoints from its EndpointSlices, weighted by the
+/// annotation of the Service.
+///
+/// Named ports are resolved to their number. The rules of a backend whose named port is not
+/// found in its Service are dropped. Services of type ExternalName become
+/// [Backend::ExternalName].
+pub(in crate::controller) async fn resolve_endpoints(
+    client: Client,
+    config: &mut PingressConfiguration,
+) -> Result<(), kube::Error> {
+    let keys: HashSet<ServiceKey> = config
+        .rules
+        .iter()
+        .map(|r| &r.backend)
+        .chain(config.default_backend.as_ref())
+        .filter_map(service_key)
+        .collect();
+    let mut resolved = HashMap::new();
+    for key in keys {
+        let (namespace, name, port) = &key;
+        let es = service_endpoints(client.clone(), namespace, name, port).await?;
+        resolved.insert(key, es);
+    }
+
+    fill_backends(config, &resolved);
+    Ok(())
+}
+
+/// Namespace, name and port of the Service of a backend
+type ServiceKey = (String, String, Port);
+
+fn service_key(backend: &Backend) -> Option<ServiceKey> {
+    let Backend::Service {
+        name,
+        namespace,
+        port,
+        ..
+    } = backend
+    else {
+        return None;
+    };
+    Some((namespace.clone(), name.clone(), port.clone()))
+}
+
+/// Fill the Service backends with their resolved port, dropping the rules of the unresolved ones
+fn fill_backends(
+    config: &mut PingressConfiguration,
+    resolved: &HashMap<ServiceKey, Option<ResolvedPort>>,
+) {
+    let backends = config
+        .rules
+        .iter_mut()
+        .map(|r| &mut r.backend)
+        .chain(config.default_backend.as_mut());
+    for backend in backends {
+        let Some(resolved) = service_key(backend).and_then(|key| resolved.get(&key)) else {
+            continue;
+        };
+        let Backend::Service {
+            name,
+            namespace,
+            port,
+            endpoints,
+            circuit_breaker,
+            protocol,
+            tls,
+            ..
+        } = backend
+        else {
+            continue;
+        };
+
+        match resolved {
+            Some(ResolvedPort::Endpoints {
+                number,
+                endpoints: es,
+            }) => {
+                *port = Port::Number(*number);
+                endpoints.clone_from(es);
+            }
+            Some(ResolvedPort::ExternalName {
+                external_name,
+                number,
+            }) => {
+                *backend = Backend::ExternalName {
+                    name: name.clone(),
+                    namespace: namespace.clone(),
+                    external_name: external_name.clone(),
+                    port: *number,
+                    circuit_breaker: circuit_breaker.take(),
+                    protocol: *protocol,
+                    tls: tls.take(),
+                };
+            }
+            None => {}
+        }
+    }
+
+    config.rules.retain(|r| is_resolved(&r.backend));
+    if config
+        .default_backend
+        .as_ref()
+        .is_some_and(|b| !is_resolved(b))
+    {
+        config.default_backend = None;
+    }
+}
+
+fn is_resolved(backend: &Backend) -> bool {
+    let Backend::Service {
+        name,
+        namespace,
+        port,
+        ..
+    } = backend
+    else {
+        return true;
+    };
+    match port {
+        Port::Number(_) => true,
+        Port::Name(port) => {
+            warn!("Service {namespace}/{name} has no port named '{port}'");
+            false
+        }
+    }
+}
+
+/// Service port of a backend, with the number of a named port
+#[derive(Debug, PartialEq)]
+enum ResolvedPort {
+    /// Ready endpoints of the port
+    Endpoints {
+        number: u16,
+        endpoints: Vec<Endpoint>,
+    },
+    /// Port of a Service of type ExternalName
+    ExternalName { external_name: String, number: u16 },
+}
+
+/// `None` when a named port is not found in the Service
+async fn service_endpoints(
+    client: Client,
+    namespace: &str,
+    name: &str,
+    port: &Port,
+) -> Result<Option<ResolvedPort>, kube::Error> {
+    let service_api: Api<Service> = Api::namespaced(client.clone(), namespace);
+    let service = service_api.get_opt(name).await?;
+
+    // Only the ports of Services with endpoints are matched to EndpointSlices
+    let spec = service.as_ref().and_then(|s| s.spec.as_ref());
+    let slices = if spec.is_some_and(|s| !is_external_name(s)) && service_port(spec, port).is_some()
+    {
+        let slice_api: Api<EndpointSlice> = Api::namespaced(client, namespace);
+        slice_api
+            .list(&ListParams::default().labels(&format!("kubernetes.io/service-name={name}")))
+            .await?
+            .items
+    } else {
+        Vec::new()
+    };
+
+    Ok(resolve_port(service.as_ref(), port, &slices))
+}
+
+fn is_external_name(spec: &ServiceSpec) -> bool {
+    spec.type_.as_deref() == Some("ExternalName")
+}
+
+/// Port of the Service matching the port of a backend, by number or by name
+fn service_port<'a>(spec: Option<&'a ServiceSpec>, port: &Port) -> Option<&'a ServicePort> {
+    spec.and_then(|s| s.ports.as_ref())?
+        .iter()
+        .find(|p| match port {
+            Port::Number(number) => p.port == i32::from(*number),
+            Port::Name(name) => p.name.as_ref() == Some(name),
+        })
+}
+
+/// Resolve the port of a backend against its Service, if any, and the EndpointSlices of the
+/// Service. `None` when a named port is not found in the Service.
+fn resolve_port(
+    service: Option<&Service>,
+    port: &Port,
+    slices: &[EndpointSlice],
+) -> Option<ResolvedPort> {
+    let spec = service.and_then(|s| s.spec.as_ref());
+    let service_port = service_port(spec, port);
+    let number = match port {
+        Port::Number(number) => Some(*number),
+        Port::Name(_) => service_port.and_then(|p| u16::try_from(p.port).ok()),
+    };
+
+    // An ExternalName Service has neither endpoints nor a cluster IP
+    if let Some(external_name) = spec
+        .filter(|s| is_external_name(s))
+        .and_then(|s| s.external_name.clone())
+    {
+        return number.map(|number| ResolvedPort::ExternalName {
+            external_name,
+            number,
+        });
+    }
+
+    let Some(service_port) = service_port else {
+        return number.map(|number| ResolvedPort::Endpoints {
+            number,
+            endpoints: Vec::new(),
+        });
+    };
+
+    let weights = service.map(endpoint_weights).unwrap_or_default();
+    let mut endpoints = Vec::new();
+    for slice in slices {
+        if slice.address_type == "FQDN" {
+            continue;
+        }
+        // EndpointSlice ports are matched to the Service port by name
+        let Some(target_port) = slice
+            .ports
+            .iter()
+            .flatten()
+            .find(|p| {
+                p.name.as_deref().unwrap_or_default()
+                    == service_port.name.as_deref().unwrap_or_default()
+            })
+            .and_then(|p| p.port)
+            .and_then(|p| u16::try_from(p).ok())
+        else {
+            continue;
+        };
+
+        let ready = slice
+            .endpoints
+            .iter()
+            .filter(|e| e.conditions.as_ref().and_then(|c| c.ready).unwrap_or(true));
+        for endpoint in ready {
+            let weight = [&endpoint.node_name, &endpoint.zone]
+                .into_iter()
+                .flatten()
+                .find_map(|key| weights.get(key))
+                .copied()
+                .unwrap_or(1);
+            for address in &endpoint.addresses {
+                let endpoint = Endpoint {
+                    address: address.clone(),
+                    port: target_port,
+                    weight,
+                };
+                if !endpoints.contains(&endpoint) {
+                    endpoints.push(endpoint);
+                }
+            }
+        }
+    }
+
+    number.map(|number| ResolvedPort::Endpoints { number, endpoints })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::controller::common::annotations::tests::warnings;
+    use crate::controller::common::endpoints::{fill_backends, resolve_port, ResolvedPort};
+    use crate::controller::common::ingresses::tests::{
+        http_path, ingress, port_number, rule, service,
+    };
+    use crate::controller::common::ingresses::GetFromIngresses;
+    use k8s_openapi::api::core::v1::{Service, ServicePort, ServiceSpec};
+    use k8s_openapi::api::discovery::v1::{
+        Endpoint as SliceEndpoint, EndpointConditions, EndpointPort, EndpointSlice,
+    };
+    use k8s_openapi::api::networking::v1::{IngressSpec, ServiceBackendPort};
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+    use pingress_config::{Backend, Endpoint, Port};
+    use std::collections::HashMap;
+
+    /// Service `default/app` with the named ports
+    fn app_service(ports: &[(Option<&str>, i32)]) -> Service {
+        Service {
+            metadata: ObjectMeta {
+                name: Some("app".to_string()),
+                namespace: Some("default".to_string()),
+                ..ObjectMeta::default()
+            },
+            spec: Some(ServiceSpec {
+                ports: Some(
+                    ports
+                        .iter()
+                        .map(|(name, port)| ServicePort {
+                            name: name.map(str::to_string),
+                            port: *port,
+                            ..ServicePort::default()
+                        })
+                        .collect(),
+                ),
+                ..ServiceSpec::default()
+            }),
+            status: None,
+        }
+    }
+
+    /// EndpointSlice with the named target ports and the addresses, ready or not
+    fn slice(ports: &[(Option<&str>, i32)], addresses: &[(&str, bool)]) -> EndpointSlice {
+        EndpointSlice {
+            address_type: "IPv4".to_string(),
+            endpoints: addresses
+                .iter()
+                .map(|(address, ready)| SliceEndpoint {
+                    addresses: vec![address.to_string()],
+                    conditions: Some(EndpointConditions {
+                        ready: Some(*ready),
+                        ..EndpointConditions::default()
+                    }),
+                    ..SliceEndpoint::default()
+                })
+                .collect(),
+            metadata: ObjectMeta::default(),
+            ports: Some(
+                ports
+                    .iter()
+                    .map(|(name, port)| EndpointPort {
+                        name: name.map(str::to_string),
+                        port: Some(*port),
+                        ..EndpointPort::default()
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    fn endpoints(port: u16, addresses: &[&str]) -> Vec<Endpoint> {
+        addresses
+            .iter()
+            .map(|address| Endpoint {
+                address: address.to_string(),
+                port,
+                weight: 1,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn resolve_ports() {
+        let service = app_service(&[(Some("http"), 80), (Some("metrics"), 9090)]);
+        let slices = [
+            slice(
+                &[(Some("http"), 8080), (Some("metrics"), 9091)],
+                &[("10.0.0.1", true), ("10.0.0.2", false)],
+            ),
+            // Target ports are matched by name, whatever their number
+            slice(&[(Some("metrics"), 8080)], &[("10.0.0.3", true)]),
+        ];
+        let name = |name: &str| Port::Name(name.to_string());
+
+        let cases = [
+            (
+                name("http"),
+                Some(ResolvedPort::Endpoints {
+                    number: 80,
+                    endpoints: endpoints(8080, &["10.0.0.1"]),
+                }),
+            ),
+            (
+                Port::Number(80),
+                Some(ResolvedPort::Endpoints {
+                    number: 80,
+                    endpoints: endpoints(8080, &["10.0.0.1"]),
+                }),
+            ),
+            (
+                name("metrics"),
+                Some(ResolvedPort::Endpoints {
+                    number: 9090,
+                    endpoints: [
+                        endpoints(9091, &["10.0.0.1"]),
+                        endpoints(8080, &["10.0.0.3"]),
+                    ]
+                    .concat(),
+                }),
+            ),
+            // A number not in the Service keeps its number without endpoints
+            (
+                Port::Number(81),
+                Some(ResolvedPort::Endpoints {
+                    number: 81,
+                    endpoints: Vec::new(),
+                }),
+            ),
+            // A name not in the Service is unresolved
+            (name("grpc"), None),
+        ];
+        for (port, expected) in cases {
+            assert_eq!(
+                resolve_port(Some(&service), &port, &slices),
+                expected,
+                "{port:?}"
+            );
+        }
+
+        assert_eq!(resolve_port(None, &name("http"), &[]), None);
+        assert_eq!(
+            resolve_port(None, &Port::Number(80), &[]),
+            Some(ResolvedPort::Endpoints {
+                number: 80,
+                endpoints: Vec::new(),
+            })
+        );
+    }
+
+    #[test]
+    fn resolve_unnamed_port() {
+        let service = app_service(&[(None, 80)]);
+        let slices = [slice(&[(None, 8080)], &[("10.0.0.1", true)])];
+        assert_eq!(
+            resolve_port(Some(&service), &Port::Number(80), &slices),
+            Some(ResolvedPort::Endpoints {
+                number: 80,
+                endpoints: endpoints(8080, &["10.0.0.1"]),
+            })
+        );
+    }
+
+    #[test]
+    fn resolve_external_name() {
+        let mut service = app_service(&[(Some("https"), 443)]);
+        let spec = service.spec.as_mut().unwrap();
+        spec.type_ = Some("ExternalName".to_string());
+        spec.external_name = Some("app.example.net".to_string());
+
+        assert_eq!(
+            resolve_port(Some(&service), &Port::Name("https".to_string()), &[]),
+            Some(ResolvedPort::ExternalName {
+                external_name: "app.example.net".to_string(),
+                number: 443,
+            })
+        );
+        assert_eq!(
+            resolve_port(Some(&service), &Port::Name("http".to_string()), &[]),
+            None
+        );
+    }
+
+    #[test]
+    fn drop_unresolved_names() {
+        let named = |name: &str| {
+            service(
+                "app",
+                ServiceBackendPort {
+                    number: None,
+                    name: Some(name.to_string()),
+                },
+            )
+        };
+        let ingresses = [ingress(
+            "app",
+            IngressSpec {
+                default_backend: Some(named("missing")),
+                rules: Some(vec![rule(
+                    Some("app.example.com"),
+                    vec![
+                        http_path("/http", "Prefix", named("http")),
+                        http_path("/missing", "Prefix", named("missing")),
+                        http_path("/number", "Prefix", service("app", port_number(8080))),
+                    ],
+                )]),
+                ..IngressSpec::default()
+            },
+        )];
+        let mut config = ingresses.as_slice().config();
+
+        let key = |port: Port| ("default".to_string(), "app".to_string(), port);
+        let resolved = HashMap::from([
+            (
+                key(Port::Name("http".to_string())),
+                Some(ResolvedPort::Endpoints {
+                    number: 80,
+                    endpoints: endpoints(8080, &["10.0.0.1"]),
+                }),
+            ),
+            (key(Port::Name("missing".to_string())), None),
+            (
+                key(Port::Number(8080)),
+                Some(ResolvedPort::Endpoints {
+                    number: 8080,
+                    endpoints: endpoints(8080, &["10.0.0.2"]),
+                }),
+            ),
+        ]);
+        let ((), logged) = warnings(|| fill_backends(&mut config, &resolved));
+
+        let backends: Vec<_> = config
+            .rules
+            .iter()
+            .map(|r| match &r.backend {
+                Backend::Service {
+                    port, endpoints, ..
+                } => (port.clone(), endpoints.clone()),
+                backend => panic!("{backend:?} is not a Service backend"),
+            })
+            .collect();
+        assert_eq!(
+            backends,
+            [
+                (Port::Number(80), endpoints(8080, &["10.0.0.1"])),
+                (Port::Number(8080), endpoints(8080, &["10.0.0.2"])),
+            ]
+        );
+        assert_eq!(config.default_backend, None);
+        assert_eq!(
+            logged,
+            [
+                "Service default/app has no port named 'missing'",
+                "Service default/app has no port named 'missing'",
+            ]
+        );
+    }
+}
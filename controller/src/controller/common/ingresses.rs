@@ -0,0 +1,602 @@
+use crate::controller::common::annotations::{
+    backend_protocol, backend_tls_ca_secret, backend_tls_client_secret, backend_tls_sni,
+    circuit_breaker, client_auth_mode, client_auth_secret, client_subject_header, health_check,
+    http2, load_balancing, ocsp_stapling, outlier_detection, retry_policy, timeouts, tls_ciphers,
+    tls_curves, tls_min_version,
+};
+use crate::controller::common::secret_path;
+use k8s_openapi::api::networking::v1::{Ingress, IngressBackend, IngressSpec};
+use kube::ResourceExt;
+use log::warn;
+use pingress_config::{
+    Backend, CircuitBreaker, ClientAuth, HealthCheck, HttpPath, LoadBalancing, OutlierDetection,
+    PathRule, PingressConfiguration, Port, Protocol, Tls, UpstreamTls, DEFAULT_SUBJECT_HEADER,
+};
+use std::collections::HashSet;
+
+pub(in crate::controller::common) struct TlsSecret {
+    pub host: String,
+    pub secret: String,
+    pub namespace: String,
+}
+
+/// Secret referenced by an Ingress for TLS to its backends or to verify its clients
+pub(in crate::controller::common) struct UpstreamSecret {
+    pub secret: String,
+    pub namespace: String,
+}
+
+/// Name of the file of the certificate (`crt`) or the key (`key`) of a host, once copied for
+/// the proxy.
+///
+/// Secret keys allow `[-._a-zA-Z0-9]` only, so the `*` of a wildcard host becomes `_`, which
+/// no host contains.
+pub(in crate::controller::common) fn tls_secret_file(host: &str, extension: &str) -> String {
+    let host: String = host
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | '0'..='9' | '-' | '.' => c,
+            'A'..='Z' => c.to_ascii_lowercase(),
+            _ => '_',
+        })
+        .collect();
+    format!("tls.{host}.{extension}")
+}
+
+/// Name of the file of the certificate (`crt`) or the key (`key`) of the default certificate.
+///
+/// It cannot clash with the files of the hosts, which start with `tls.`.
+pub(in crate::controller::common) fn default_tls_file(extension: &str) -> String {
+    format!("default.{extension}")
+}
+
+/// Name of the file of a key (e.g. `ca.crt`) of an upstream Secret, once copied for the proxy
+pub(in crate::controller::common) fn upstream_secret_file(
+    namespace: &str,
+    secret: &str,
+    key: &str,
+) -> String {
+    format!("upstream.{namespace}.{secret}.{key}")
+}
+
+pub(super) trait GetFromIngresses {
+    fn tls_secrets(&self) -> Vec<TlsSecret>;
+
+    fn upstream_secrets(&self) -> Vec<UpstreamSecret>;
+
+    fn config(&self) -> PingressConfiguration;
+}
+
+impl GetFromIngresses for &[Ingress] {
+    fn tls_secrets(&self) -> Vec<TlsSecret> {
+        self.iter()
+            .filter_map(|ingress| {
+                ingress
+                    .spec
+                    .as_ref()
+                    .map(|i| (i, ingress.namespace().unwrap_or("default".to_string())))
+            })
+            .filter_map(|(ingress, namespace)| {
+                ingress
+                    .tls
+                    .as_ref()
+                    .map(move |t| t.iter().map(move |t| (t, namespace.clone())))
+            })
+            .flatten()
+            .filter_map(|(tls, namespace)| {
+                tls.secret_name.clone().and_then(move |secret| {
+                    tls.hosts.clone().map(move |hosts| {
+                        hosts.into_iter().map(move |host| TlsSecret {
+                            host,
+                            secret: secret.clone(),
+                            namespace: namespace.clone(),
+                        })
+                    })
+                })
+            })
+            .flatten()
+            .collect()
+    }
+
+    fn upstream_secrets(&self) -> Vec<UpstreamSecret> {
+        let mut secrets: Vec<UpstreamSecret> = Vec::new();
+        for ingress in self.iter() {
+            let namespace = ingress.namespace().unwrap_or("default".to_string());
+            let names = [
+                backend_tls_ca_secret(ingress),
+                backend_tls_client_secret(ingress),
+                client_auth_secret(ingress),
+            ];
+            for secret in names.into_iter().flatten() {
+                if !secrets
+                    .iter()
+                    .any(|s| s.secret == secret && s.namespace == namespace)
+                {
+                    secrets.push(UpstreamSecret {
+                        secret,
+                        namespace: namespace.clone(),
+                    });
+                }
+            }
+        }
+        secrets
+    }
+
+    fn config(&self) -> PingressConfiguration {
+        let mut rules = Vec::new();
+        let mut default_backend = None;
+        for ingress in self.iter() {
+            let settings = BackendSettings::new(ingress);
+            if let Some(rs) = ingress_to_config(ingress, &settings) {
+                rules.extend(rs);
+            }
+
+            let Some(backend) = ingress
+                .spec
+                .as_ref()
+                .and_then(|s| s.default_backend.as_ref())
+                .and_then(|b| settings.backend(b))
+            else {
+                continue;
+            };
+            if default_backend.is_none() {
+                default_backend = Some(backend);
+            } else {
+                warn!(
+                    "Default backend of Ingress {}/{} is ignored: another Ingress sets one",
+                    ingress.namespace().unwrap_or("default".to_string()),
+                    ingress.name_any()
+                );
+            }
+        }
+        PingressConfiguration {
+            rules,
+            default_backend,
+            not_found_body: None,
+            default_tls: None,
+        }
+    }
+}
+
+/// Whether the Ingress routes to the Service
+pub(in crate::controller::common) fn references_service(
+    ingress: &Ingress,
+    namespace: &str,
+    name: &str,
+) -> bool {
+    if ingress.namespace().as_deref().unwrap_or("default") != namespace {
+        return false;
+    }
+    let Some(spec) = ingress.spec.as_ref() else {
+        return false;
+    };
+
+    spec.rules
+        .iter()
+        .flatten()
+        .filter_map(|r| r.http.as_ref())
+        .flat_map(|h| h.paths.iter())
+        .map(|p| &p.backend)
+        .chain(spec.default_backend.as_ref())
+        .filter_map(|b| b.service.as_ref())
+        .any(|s| s.name == name)
+}
+
+/// Whether the Ingress routes to the resource of the kind of the core API group
+pub(in crate::controller::common) fn references_resource(
+    ingress: &Ingress,
+    namespace: &str,
+    kind: &str,
+    name: &str,
+) -> bool {
+    if ingress.namespace().as_deref().unwrap_or("default") != namespace {
+        return false;
+    }
+    let Some(spec) = ingress.spec.as_ref() else {
+        return false;
+    };
+
+    spec.rules
+        .iter()
+        .flatten()
+        .filter_map(|r| r.http.as_ref())
+        .flat_map(|h| h.paths.iter())
+        .map(|p| &p.backend)
+        .chain(spec.default_backend.as_ref())
+        .filter_map(|b| b.resource.as_ref())
+        .any(|r| {
+            r.api_group.as_deref().unwrap_or_default().is_empty()
+                && r.kind == kind
+                && r.name == name
+        })
+}
+
+/// Settings of the Service backends of an Ingress, from its annotations
+struct BackendSettings {
+    namespace: String,
+    /// Name of the Ingress
+    name: String,
+    load_balancing: LoadBalancing,
+    health_check: Option<HealthCheck>,
+    outlier_detection: Option<OutlierDetection>,
+    circuit_breaker: Option<CircuitBreaker>,
+    protocol: Protocol,
+    tls: Option<UpstreamTls>,
+}
+
+impl BackendSettings {
+    fn new(ingress: &Ingress) -> Self {
+        Self {
+            namespace: ingress.namespace().unwrap_or("default".to_string()),
+            name: ingress.name_any(),
+            load_balancing: load_balancing(ingress),
+            health_check: health_check(ingress),
+            outlier_detection: outlier_detection(ingress),
+            circuit_breaker: circuit_breaker(ingress),
+            protocol: backend_protocol(ingress),
+            tls: upstream_tls(ingress),
+        }
+    }
+
+    /// Service or resource backend. Resources are resolved later on by their handler.
+    fn backend(&self, backend: &IngressBackend) -> Option<Backend> {
+        if let Some(resource) = backend.resource.as_ref() {
+            return Some(Backend::Resource {
+                api_group: resource.api_group.clone(),
+                kind: resource.kind.clone(),
+                name: resource.name.clone(),
+                namespace: self.namespace.clone(),
+            });
+        }
+        let service = backend.service.as_ref()?;
+        let port = service.port.as_ref()?;
+        let port = match (port.number, port.name.as_ref()) {
+            (Some(number), _) => {
+                let Ok(number) = u16::try_from(number) else {
+                    warn!(
+                        "Port {number} of Service {} in Ingress {}/{} is out of range",
+                        service.name, self.namespace, self.name
+                    );
+                    return None;
+                };
+                Port::Number(number)
+            }
+            (None, Some(name)) => Port::Name(name.clone()),
+            (None, None) => return None,
+        };
+        Some(Backend::Service {
+            name: service.name.clone(),
+            namespace: self.namespace.clone(),
+            port,
+            endpoints: Vec::new(),
+            load_balancing: self.load_balancing.clone(),
+            health_check: self.health_check.clone(),
+            outlier_detection: self.outlier_detection.clone(),
+            circuit_breaker: self.circuit_breaker.clone(),
+            protocol: self.protocol,
+            tls: self.tls.clone(),
+        })
+    }
+}
+
+fn ingress_to_config(ingress: &Ingress, settings: &BackendSettings) -> Option<Vec<PathRule>> {
+    let spec = ingress.spec.as_ref()?;
+
+    let tls = ingress_to_tls_map(spec).unwrap_or_default();
+    let retry = retry_policy(ingress);
+    let timeouts = timeouts(ingress);
+    let http2 = http2(ingress);
+    let host_tls = host_tls(ingress);
+
+    let mut rules = Vec::new();
+    for path in spec.rules.as_ref()? {
+        // Rules without a host match any host
+        let host = path.host.as_ref();
+        let Some(rule) = path.http.as_ref() else {
+            continue;
+        };
+        let tls = host.filter(|h| tls.contains(*h)).map(|host| Tls {
+            key: secret_path(tls_secret_file(host, "key").as_str()),
+            cert: secret_path(tls_secret_file(host, "crt").as_str()),
+            ..host_tls.clone()
+        });
+        let rs = rule.paths.iter().filter_map(|p| {
+            Some(PathRule {
+                host: host.cloned(),
+                tls: tls.clone(),
+                path: {
+                    let path = p.path.clone().unwrap_or_default();
+                    match p.path_type.as_str() {
+                        "Exact" => HttpPath::Exact(path),
+                        "ImplementationSpecific" => HttpPath::ImplementationSpecific(path),
+                        _ => HttpPath::Prefix(path),
+                    }
+                },
+                backend: settings.backend(&p.backend)?,
+                retry: retry.clone(),
+                timeouts: timeouts.clone(),
+                http2,
+            })
+        });
+        rules.extend(rs);
+    }
+
+    Some(rules)
+}
+
+/// Settings of the TLS of the hosts of an Ingress, from its annotations, without the files of
+/// the certificate
+fn host_tls(ingress: &Ingress) -> Tls {
+    Tls {
+        ocsp: ocsp_stapling(ingress),
+        min_version: tls_min_version(ingress),
+        ciphers: tls_ciphers(ingress),
+        curves: tls_curves(ingress),
+        client_auth: client_auth_secret(ingress).map(|secret| ClientAuth {
+            ca: upstream_secret_path(ingress, secret.as_str(), "ca.crt"),
+            mode: client_auth_mode(ingress),
+            subject_header: client_subject_header(ingress)
+                .unwrap_or_else(|| DEFAULT_SUBJECT_HEADER.to_string()),
+        }),
+        ..Tls::new(String::new(), String::new())
+    }
+}
+
+fn upstream_secret_path(ingress: &Ingress, secret: &str, key: &str) -> String {
+    let namespace = ingress.namespace().unwrap_or("default".to_string());
+    secret_path(upstream_secret_file(namespace.as_str(), secret, key).as_str())
+}
+
+fn upstream_tls(ingress: &Ingress) -> Option<UpstreamTls> {
+    let path = |secret: &str, key: &str| upstream_secret_path(ingress, secret, key);
+
+    let tls = UpstreamTls {
+        sni: backend_tls_sni(ingress),
+        ca: backend_tls_ca_secret(ingress).map(|s| path(s.as_str(), "ca.crt")),
+        client_cert: backend_tls_client_secret(ingress)
+            .map(|s| Tls::new(path(s.as_str(), "tls.key"), path(s.as_str(), "tls.crt"))),
+    };
+    (tls != UpstreamTls::default()).then_some(tls)
+}
+
+fn ingress_to_tls_map(ingress: &IngressSpec) -> Option<HashSet<String>> {
+    Some(
+        ingress
+            .tls
+            .as_ref()?
+            .iter()
+            .filter_map(|t| t.hosts.clone())
+            .flatten()
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+pub(in crate::controller::common) mod tests {
+    use crate::controller::common::annotations::tests::warnings;
+    use crate::controller::common::ingresses::GetFromIngresses;
+    use k8s_openapi::api::networking::v1::{
+        HTTPIngressPath, HTTPIngressRuleValue, Ingress, IngressBackend, IngressRule,
+        IngressServiceBackend, IngressSpec, IngressTLS, ServiceBackendPort,
+    };
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+    use pingress_config::{Backend, HttpPath, Port};
+
+    /// Backend to the port of the Service
+    pub(in crate::controller::common) fn service(
+        name: &str,
+        port: ServiceBackendPort,
+    ) -> IngressBackend {
+        IngressBackend {
+            service: Some(IngressServiceBackend {
+                name: name.to_string(),
+                port: Some(port),
+            }),
+            resource: None,
+        }
+    }
+
+    pub(in crate::controller::common) fn port_number(number: i32) -> ServiceBackendPort {
+        ServiceBackendPort {
+            number: Some(number),
+            name: None,
+        }
+    }
+
+    pub(in crate::controller::common) fn http_path(
+        path: &str,
+        path_type: &str,
+        backend: IngressBackend,
+    ) -> HTTPIngressPath {
+        HTTPIngressPath {
+            path: Some(path.to_string()),
+            path_type: path_type.to_string(),
+            backend,
+        }
+    }
+
+    pub(in crate::controller::common) fn rule(
+        host: Option<&str>,
+        paths: Vec<HTTPIngressPath>,
+    ) -> IngressRule {
+        IngressRule {
+            host: host.map(str::to_string),
+            http: Some(HTTPIngressRuleValue { paths }),
+        }
+    }
+
+    /// Ingress `default/{name}` of the `pingress` class
+    pub(in crate::controller::common) fn ingress(name: &str, spec: IngressSpec) -> Ingress {
+        Ingress {
+            metadata: ObjectMeta {
+                name: Some(name.to_string()),
+                namespace: Some("default".to_string()),
+                ..ObjectMeta::default()
+            },
+            spec: Some(IngressSpec {
+                ingress_class_name: Some("pingress".to_string()),
+                ..spec
+            }),
+            status: None,
+        }
+    }
+
+    /// Ingress `default/example` routing the hosts to `backend:80`, with their certificate in
+    /// the Secret `example-tls`
+    pub(in crate::controller::common) fn tls_ingress(hosts: &[&str]) -> Ingress {
+        let rules = hosts
+            .iter()
+            .map(|h| {
+                let path = http_path("/", "Prefix", service("backend", port_number(80)));
+                rule(Some(h), vec![path])
+            })
+            .collect();
+        ingress(
+            "example",
+            IngressSpec {
+                tls: Some(vec![IngressTLS {
+                    hosts: Some(hosts.iter().map(|h| h.to_string()).collect()),
+                    secret_name: Some("example-tls".to_string()),
+                }]),
+                rules: Some(rules),
+                ..IngressSpec::default()
+            },
+        )
+    }
+
+    fn service_name(backend: &Backend) -> &str {
+        match backend {
+            Backend::Service { name, .. } => name,
+            backend => panic!("{backend:?} is not a Service backend"),
+        }
+    }
+
+    #[test]
+    fn host_less_rules() {
+        let mut tls = tls_ingress(&["app.example.com"]);
+        let spec = tls.spec.as_mut().unwrap();
+        let any_host = http_path("/", "Prefix", service("fallback", port_number(80)));
+        spec.rules
+            .as_mut()
+            .unwrap()
+            .push(rule(None, vec![any_host]));
+        let ingresses = [tls];
+
+        let config = ingresses.as_slice().config();
+        let hosts: Vec<_> = config.rules.iter().map(|r| r.host.as_deref()).collect();
+        assert_eq!(hosts, [Some("app.example.com"), None]);
+        assert!(config.rules[0].tls.is_some());
+        // Certificates are looked up by host
+        assert_eq!(config.rules[1].tls, None);
+        assert_eq!(service_name(&config.rules[1].backend), "fallback");
+    }
+
+    #[test]
+    fn first_default_backend_wins() {
+        let with_default = |name: &str, backend: IngressBackend| {
+            ingress(
+                name,
+                IngressSpec {
+                    default_backend: Some(backend),
+                    ..IngressSpec::default()
+                },
+            )
+        };
+        let without_port = IngressBackend {
+            service: Some(IngressServiceBackend {
+                name: "broken".to_string(),
+                port: None,
+            }),
+            resource: None,
+        };
+        let ingresses = [
+            // A default backend that is not valid does not take precedence
+            with_default("broken", without_port),
+            with_default("first", service("first", port_number(80))),
+            with_default("second", service("second", port_number(80))),
+        ];
+
+        let (config, logged) = warnings(|| ingresses.as_slice().config());
+        assert!(config.rules.is_empty());
+        assert_eq!(
+            config.default_backend.as_ref().map(service_name),
+            Some("first")
+        );
+        assert_eq!(
+            logged,
+            ["Default backend of Ingress default/second is ignored: another Ingress sets one"]
+        );
+    }
+
+    #[test]
+    fn service_ports() {
+        let named = ServiceBackendPort {
+            number: None,
+            name: Some("http".to_string()),
+        };
+        let ingresses = [ingress(
+            "app",
+            IngressSpec {
+                rules: Some(vec![rule(
+                    Some("app.example.com"),
+                    vec![
+                        http_path("/number", "Prefix", service("app", port_number(8080))),
+                        http_path("/name", "Prefix", service("app", named)),
+                        http_path("/negative", "Prefix", service("app", port_number(-1))),
+                        http_path("/large", "Prefix", service("app", port_number(65536))),
+                    ],
+                )]),
+                ..IngressSpec::default()
+            },
+        )];
+
+        let (config, logged) = warnings(|| ingresses.as_slice().config());
+        let ports: Vec<_> = config
+            .rules
+            .iter()
+            .map(|r| match &r.backend {
+                Backend::Service { port, .. } => port.clone(),
+                backend => panic!("{backend:?} is not a Service backend"),
+            })
+            .collect();
+        assert_eq!(ports, [Port::Number(8080), Port::Name("http".to_string())]);
+        assert_eq!(
+            logged,
+            [
+                "Port -1 of Service app in Ingress default/app is out of range",
+                "Port 65536 of Service app in Ingress default/app is out of range",
+            ]
+        );
+    }
+
+    #[test]
+    fn path_types() {
+        let cases = [
+            ("Exact", HttpPath::Exact("/api".to_string())),
+            ("Prefix", HttpPath::Prefix("/api".to_string())),
+            (
+                "ImplementationSpecific",
+                HttpPath::ImplementationSpecific("/api".to_string()),
+            ),
+            ("Regex", HttpPath::Prefix("/api".to_string())),
+        ];
+        for (path_type, expected) in cases {
+            let path = http_path("/api", path_type, service("app", port_number(8080)));
+            let ingresses = [ingress(
+                "app",
+                IngressSpec {
+                    rules: Some(vec![rule(Some("app.example.com"), vec![path])]),
+                    ..IngressSpec::default()
+                },
+            )];
+
+            let config = ingresses.as_slice().config();
+            assert_eq!(config.rules.len(), 1, "{path_type}");
+            assert_eq!(config.rules[0].path, expected, "{path_type}");
+            let Backend::Service { port, .. } = &config.rules[0].backend else {
+                panic!("{path_type}: not a Service backend");
+            };
+            assert_eq!(*port, Port::Number(8080), "{path_type}");
+        }
+    }
+}
@@ -0,0 +1,184 @@
+mod annotations;
+mod config_map;
+mod endpoints;
+mod ingresses;
+mod pod;
+mod resources;
+mod secrets;
+
+pub(super) use config_map::{apply_config_map, cleanup_config_map};
+pub(super) use pod::{proxy_pod_labels, proxy_pod_template};
+pub(super) use secrets::{apply_tls_secrets, cleanup_tls_secret};
+
+use crate::controller::common::ingresses::{references_resource, references_service};
+use k8s_openapi::api::core::v1::{ConfigMap, Service};
+use k8s_openapi::api::discovery::v1::EndpointSlice;
+use k8s_openapi::api::networking::v1::Ingress;
+use kube::api::ListParams;
+use kube::runtime::finalizer::Event;
+use kube::runtime::reflector::{ObjectRef, Store};
+use kube::{Api, Client, ResourceExt};
+use std::collections::BTreeMap;
+
+pub(super) const FIELD_MANAGER: &str = "kinorca.com/pingress-controller";
+pub(super) const FINALIZER_NAME: &str = "kinorca.com/pingress-controller";
+pub(super) const INGRESS_CLASS_NAME: &str = "pingress";
+pub(super) const PROXY_SERVER_NAME: &str = "pingress-proxy-server";
+pub(super) const HTTP_PORT: i32 = 8080;
+pub(super) const HTTPS_PORT: i32 = 8443;
+const TLS_SECRET_NAME: &str = "pingress-tls-secret";
+const CONFIG_MAP_NAME: &str = "pingress-config";
+const CONFIG_KEY: &str = "proxy.json";
+const SECRET_BASE_PATH: &str = "/etc/pingress/keys";
+
+/// Path that a file of the Secret of the proxy server is mounted at
+fn secret_path(file: &str) -> String {
+    format!("{SECRET_BASE_PATH}/{file}")
+}
+
+/// Common accessors for the contexts of each backend controller.
+pub(super) trait ProxyContext {
+    fn client(&self) -> Client;
+
+    /// Namespace that the proxy server and its resources are deployed to
+    fn namespace(&self) -> &str;
+
+    /// HTML body of the 404 response of the proxy server
+    fn not_found_body(&self) -> Option<&str>;
+
+    /// TLS Secret (`namespace/name` or `name`) of the default certificate of the proxy server
+    fn default_tls_secret(&self) -> Option<&str>;
+
+    /// Labels attached to every resource managed by the controller
+    fn manifest_labels(&self) -> Option<BTreeMap<String, String>>;
+}
+
+/// List every Ingress handled by pingress.
+///
+/// An Ingress being cleaned up by the finalizer is excluded, so that the last deletion
+/// tears down the proxy resources.
+pub(super) async fn list_ingresses(
+    client: Client,
+    event: &Event<Ingress>,
+) -> Result<Vec<Ingress>, kube::Error> {
+    let removed = match event {
+        Event::Apply(_) => None,
+        Event::Cleanup(ingress) => Some((ingress.namespace(), ingress.name_any())),
+    };
+
+    let api: Api<Ingress> = Api::all(client);
+    let ingresses = api.list(&ListParams::default()).await?;
+    Ok(ingresses
+        .items
+        .into_iter()
+        .filter(is_pingress)
+        .filter(|i| i.metadata.deletion_timestamp.is_none())
+        .filter(|i| {
+            removed.as_ref().is_none_or(|(namespace, name)| {
+                *namespace != i.namespace() || *name != i.name_any()
+            })
+        })
+        .collect())
+}
+
+/// Map a changed EndpointSlice to the Ingresses routing to its Service.
+pub(super) fn endpoint_slice_mapper(
+    store: Store<Ingress>,
+) -> impl Fn(EndpointSlice) -> Vec<ObjectRef<Ingress>> + Send + Sync + 'static {
+    move |slice| {
+        let namespace = slice.namespace().unwrap_or("default".to_string());
+        let Some(service) = slice.labels().get("kubernetes.io/service-name") else {
+            return Vec::new();
+        };
+
+        store
+            .state()
+            .iter()
+            .filter(|i| is_pingress(i))
+            .filter(|i| references_service(i, namespace.as_str(), service))
+            .map(|i| ObjectRef::from_obj(i.as_ref()))
+            .collect()
+    }
+}
+
+/// Map a changed Service to the Ingresses routing to it, so that named ports are resolved again.
+pub(super) fn service_mapper(
+    store: Store<Ingress>,
+) -> impl Fn(Service) -> Vec<ObjectRef<Ingress>> + Send + Sync + 'static {
+    move |service| {
+        let namespace = service.namespace().unwrap_or("default".to_string());
+        let name = service.name_any();
+
+        store
+            .state()
+            .iter()
+            .filter(|i| is_pingress(i))
+            .filter(|i| references_service(i, namespace.as_str(), name.as_str()))
+            .map(|i| ObjectRef::from_obj(i.as_ref()))
+            .collect()
+    }
+}
+
+/// Map a changed or deleted resource of the proxy server to an Ingress handled by pingress, so
+/// that the resource is applied again.
+///
+/// Every reconciliation applies the resources shared by all the Ingresses, so one Ingress is
+/// enough. The resources have no owner references: they live in the namespace of the
+/// controller, and an owner in another namespace would get them garbage collected.
+pub(super) fn proxy_resource_mapper<K>(
+    store: Store<Ingress>,
+) -> impl Fn(K) -> Vec<ObjectRef<Ingress>> + Send + Sync + 'static {
+    move |_| {
+        store
+            .state()
+            .iter()
+            .filter(|i| is_pingress(i) && i.metadata.deletion_timestamp.is_none())
+            .take(1)
+            .map(|i| ObjectRef::from_obj(i.as_ref()))
+            .collect()
+    }
+}
+
+/// Label selector of the resources labeled with [ProxyContext::manifest_labels]
+pub(super) fn manifest_selector(ctx: &impl ProxyContext) -> String {
+    ctx.manifest_labels()
+        .unwrap_or_default()
+        .iter()
+        .map(|(key, value)| format!("{key}={value}"))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Map a changed ConfigMap to the Ingresses serving it as a resource backend.
+pub(super) fn config_map_mapper(
+    store: Store<Ingress>,
+) -> impl Fn(ConfigMap) -> Vec<ObjectRef<Ingress>> + Send + Sync + 'static {
+    move |config_map| {
+        let namespace = config_map.namespace().unwrap_or("default".to_string());
+        let name = config_map.name_any();
+
+        store
+            .state()
+            .iter()
+            .filter(|i| is_pingress(i))
+            .filter(|i| references_resource(i, namespace.as_str(), "ConfigMap", name.as_str()))
+            .map(|i| ObjectRef::from_obj(i.as_ref()))
+            .collect()
+    }
+}
+
+/// Whether the Ingress should be reconciled by pingress.
+///
+/// An Ingress that still carries our finalizer is managed even if its class was changed,
+/// so that the finalizer can be removed.
+pub(super) fn is_managed(ingress: &Ingress) -> bool {
+    is_pingress(ingress) || ingress.finalizers().iter().any(|f| f == FINALIZER_NAME)
+}
+
+fn is_pingress(ingress: &Ingress) -> bool {
+    ingress
+        .spec
+        .as_ref()
+        .and_then(|s| s.ingress_class_name.as_deref())
+        .is_some_and(|c| c == INGRESS_CLASS_NAME)
+}
@@ -0,0 +1,111 @@
+use crate::controller::common::{
+    CONFIG_KEY, CONFIG_MAP_NAME, HTTPS_PORT, HTTP_PORT, PROXY_SERVER_NAME, SECRET_BASE_PATH,
+    TLS_SECRET_NAME,
+};
+use k8s_openapi::api::core::v1::{
+    ConfigMapVolumeSource, Container, ContainerPort, LocalObjectReference, PodSpec,
+    PodTemplateSpec, SecretVolumeSource, SecurityContext, Volume, VolumeMount,
+};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use std::collections::BTreeMap;
+
+pub(in crate::controller) fn proxy_pod_labels() -> BTreeMap<String, String> {
+    BTreeMap::from([(
+        "app.kubernetes.io/name".to_string(),
+        PROXY_SERVER_NAME.to_string(),
+    )])
+}
+
+/// Pod template of pingress-proxy-server.
+///
+/// `host_ports` binds the HTTP and HTTPS ports to the node. (HostPort backend)
+pub(in crate::controller) fn proxy_pod_template(
+    proxy_server_image: &str,
+    image_pull_secret: Option<&str>,
+    node_selector: Option<BTreeMap<String, String>>,
+    host_ports: bool,
+) -> PodTemplateSpec {
+    PodTemplateSpec {
+        metadata: Some(ObjectMeta {
+            labels: Some(proxy_pod_labels()),
+            ..ObjectMeta::default()
+        }),
+        spec: Some(PodSpec {
+            containers: vec![Container {
+                command: Some(vec![
+                    "/usr/local/bin/pingress-proxy-server".to_string(),
+                    format!("--config=/etc/pingress/config/{CONFIG_KEY}"),
+                    format!("--watch=/etc/pingress/config,{SECRET_BASE_PATH}"),
+                    format!("--listen-http=0.0.0.0:{HTTP_PORT}"),
+                    format!("--listen-https=0.0.0.0:{HTTPS_PORT}"),
+                ]),
+                image: Some(proxy_server_image.to_string()),
+                name: PROXY_SERVER_NAME.to_string(),
+                ports: Some(vec![
+                    ContainerPort {
+                        container_port: HTTP_PORT,
+                        host_port: host_ports.then_some(80),
+                        name: Some("http".to_string()),
+                        protocol: Some("TCP".to_string()),
+                        ..ContainerPort::default()
+                    },
+                    ContainerPort {
+                        container_port: HTTPS_PORT,
+                        host_port: host_ports.then_some(443),
+                        name: Some("https".to_string()),
+                        protocol: Some("TCP".to_string()),
+                        ..ContainerPort::default()
+                    },
+                ]),
+                liveness_probe: None,
+                readiness_probe: None,
+                startup_probe: None,
+                security_context: Some(SecurityContext {
+                    read_only_root_filesystem: Some(true),
+                    ..SecurityContext::default()
+                }),
+                volume_mounts: Some(vec![
+                    VolumeMount {
+                        mount_path: "/etc/pingress/config".to_string(),
+                        name: CONFIG_MAP_NAME.to_string(),
+                        read_only: Some(true),
+                        ..VolumeMount::default()
+                    },
+                    VolumeMount {
+                        mount_path: SECRET_BASE_PATH.to_string(),
+                        name: TLS_SECRET_NAME.to_string(),
+                        read_only: Some(true),
+                        ..VolumeMount::default()
+                    },
+                ]),
+                ..Container::default()
+            }],
+            image_pull_secrets: image_pull_secret.map(|s| {
+                vec![LocalObjectReference {
+                    name: Some(s.to_string()),
+                }]
+            }),
+            node_selector,
+            volumes: Some(vec![
+                Volume {
+                    name: TLS_SECRET_NAME.to_string(),
+                    secret: Some(SecretVolumeSource {
+                        default_mode: Some(0o700),
+                        secret_name: Some(TLS_SECRET_NAME.to_string()),
+                        ..SecretVolumeSource::default()
+                    }),
+                    ..Volume::default()
+                },
+                Volume {
+                    name: CONFIG_MAP_NAME.to_string(),
+                    config_map: Some(ConfigMapVolumeSource {
+                        name: Some(CONFIG_MAP_NAME.to_string()),
+                        ..ConfigMapVolumeSource::default()
+                    }),
+                    ..Volume::default()
+                },
+            ]),
+            ..PodSpec::default()
+        }),
+    }
+}
@@ -0,0 +1,345 @@
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use k8s_openapi::api::core::v1::ConfigMap;
+use kube::{Api, Client, ResourceExt};
+use log::warn;
+use pingress_config::{Backend, PingressConfiguration};
+use std::collections::{HashMap, HashSet};
+
+/// Handler of the Ingress backends referencing a kind of resource
+trait ResourceHandler: Sync {
+    /// Whether the handler serves resources of the API group and kind
+    fn handles(&self, api_group: Option<&str>, kind: &str) -> bool;
+
+    /// Backend serving the resource, `None` when it is missing or invalid
+    fn resolve<'a>(
+        &'a self,
+        client: Client,
+        namespace: &'a str,
+        name: &'a str,
+    ) -> BoxFuture<'a, Result<Option<Backend>, kube::Error>>;
+}
+
+/// Handlers tried in order for each resource backend
+const HANDLERS: &[&dyn ResourceHandler] = &[&ConfigMapResponse];
+
+/// Replace each resource backend with the backend of its handler.
+///
+/// The rules of a resource that no handler serves are dropped.
+pub(in crate::controller) async fn resolve_resources(
+    client: Client,
+    config: &mut PingressConfiguration,
+) -> Result<(), kube::Error> {
+    let keys: HashSet<ResourceKey> = config
+        .rules
+        .iter()
+        .map(|r| &r.backend)
+        .chain(config.default_backend.as_ref())
+        .filter_map(resource_key)
+        .collect();
+    let mut resolved = HashMap::new();
+    for key in keys {
+        let (api_group, kind, namespace, name) = &key;
+        let Some(handler) = handler(api_group.as_deref(), kind) else {
+            warn!("Resource backend {kind} {namespace}/{name} is not supported");
+            continue;
+        };
+        let backend = handler.resolve(client.clone(), namespace, name).await?;
+        if backend.is_none() {
+            warn!("Resource backend {kind} {namespace}/{name} cannot be served");
+        }
+        resolved.insert(key, backend);
+    }
+
+    fill_resources(config, &resolved);
+    Ok(())
+}
+
+/// API group, kind, namespace and name of the resource of a backend
+type ResourceKey = (Option<String>, String, String, String);
+
+fn resource_key(backend: &Backend) -> Option<ResourceKey> {
+    let Backend::Resource {
+        api_group,
+        kind,
+        name,
+        namespace,
+    } = backend
+    else {
+        return None;
+    };
+    Some((
+        api_group.clone(),
+        kind.clone(),
+        namespace.clone(),
+        name.clone(),
+    ))
+}
+
+fn handler(api_group: Option<&str>, kind: &str) -> Option<&'static dyn ResourceHandler> {
+    HANDLERS
+        .iter()
+        .find(|h| h.handles(api_group, kind))
+        .copied()
+}
+
+/// Replace the resource backends with their resolved backend, dropping the rules of the
+/// unsupported or unresolved ones
+fn fill_resources(
+    config: &mut PingressConfiguration,
+    resolved: &HashMap<ResourceKey, Option<Backend>>,
+) {
+    let backends = config
+        .rules
+        .iter_mut()
+        .map(|r| &mut r.backend)
+        .chain(config.default_backend.as_mut());
+    for backend in backends {
+        if let Some(Some(resolved)) = resource_key(backend).and_then(|key| resolved.get(&key)) {
+            *backend = resolved.clone();
+        }
+    }
+
+    let is_resolved = |b: &Backend| !matches!(b, Backend::Resource { .. });
+    config.rules.retain(|r| is_resolved(&r.backend));
+    if config
+        .default_backend
+        .as_ref()
+        .is_some_and(|b| !is_resolved(b))
+    {
+        config.default_backend = None;
+    }
+}
+
+/// Static response or redirect described by a ConfigMap, e.g. a maintenance page.
+///
+/// The keys of the ConfigMap are
+/// - `status`: HTTP status, `302` with a `location` and `200` otherwise
+/// - `location`: target of a redirect
+/// - `content-type`: type of the body, HTML when unset
+/// - `body`: body of the response
+struct ConfigMapResponse;
+
+impl ResourceHandler for ConfigMapResponse {
+    fn handles(&self, api_group: Option<&str>, kind: &str) -> bool {
+        api_group.unwrap_or_default().is_empty() && kind == "ConfigMap"
+    }
+
+    fn resolve<'a>(
+        &'a self,
+        client: Client,
+        namespace: &'a str,
+        name: &'a str,
+    ) -> BoxFuture<'a, Result<Option<Backend>, kube::Error>> {
+        async move {
+            let api: Api<ConfigMap> = Api::namespaced(client, namespace);
+            Ok(api
+                .get_opt(name)
+                .await?
+                .as_ref()
+                .and_then(config_map_response))
+        }
+        .boxed()
+    }
+}
+
+/// Response described by the ConfigMap, `None` when its status is invalid
+fn config_map_response(config_map: &ConfigMap) -> Option<Backend> {
+    let data = config_map.data.clone().unwrap_or_default();
+
+    let location = data.get("location").cloned();
+    let status = match data.get("status") {
+        Some(status) => match status.trim().parse::<u16>() {
+            Ok(status @ 100..=599) => status,
+            _ => {
+                warn!(
+                    "ConfigMap {}/{} has an invalid status '{status}'",
+                    config_map.namespace().unwrap_or_default(),
+                    config_map.name_any()
+                );
+                return None;
+            }
+        },
+        None if location.is_some() => 302,
+        None => 200,
+    };
+
+    Some(Backend::Response {
+        status,
+        location,
+        content_type: data.get("content-type").cloned(),
+        body: data.get("body").cloned().unwrap_or_default(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::controller::common::annotations::tests::warnings;
+    use crate::controller::common::ingresses::tests::{
+        http_path, ingress, port_number, rule, service,
+    };
+    use crate::controller::common::ingresses::GetFromIngresses;
+    use crate::controller::common::resources::{config_map_response, fill_resources, handler};
+    use k8s_openapi::api::core::v1::{ConfigMap, TypedLocalObjectReference};
+    use k8s_openapi::api::networking::v1::{IngressBackend, IngressSpec};
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+    use pingress_config::{Backend, HttpPath};
+    use std::collections::{BTreeMap, HashMap};
+
+    fn config_map(data: &[(&str, &str)]) -> ConfigMap {
+        ConfigMap {
+            metadata: ObjectMeta {
+                name: Some("maintenance".to_string()),
+                namespace: Some("default".to_string()),
+                ..ObjectMeta::default()
+            },
+            data: Some(
+                data.iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect::<BTreeMap<_, _>>(),
+            ),
+            ..ConfigMap::default()
+        }
+    }
+
+    fn resource(kind: &str, name: &str) -> IngressBackend {
+        IngressBackend {
+            service: None,
+            resource: Some(TypedLocalObjectReference {
+                api_group: None,
+                kind: kind.to_string(),
+                name: name.to_string(),
+            }),
+        }
+    }
+
+    fn response(status: u16, location: Option<&str>, body: &str) -> Backend {
+        Backend::Response {
+            status,
+            location: location.map(str::to_string),
+            content_type: None,
+            body: body.to_string(),
+        }
+    }
+
+    #[test]
+    fn config_map_responses() {
+        let location = "https://status.example.com/";
+        let cases = [
+            (vec![], response(200, None, "")),
+            (
+                vec![("body", "<p>Down for maintenance</p>")],
+                response(200, None, "<p>Down for maintenance</p>"),
+            ),
+            (vec![("status", " 503 ")], response(503, None, "")),
+            // Redirects default to 302
+            (
+                vec![("location", location)],
+                response(302, Some(location), ""),
+            ),
+            (
+                vec![("status", "301"), ("location", location)],
+                response(301, Some(location), ""),
+            ),
+            (
+                vec![("content-type", "text/plain"), ("body", "down")],
+                Backend::Response {
+                    status: 200,
+                    location: None,
+                    content_type: Some("text/plain".to_string()),
+                    body: "down".to_string(),
+                },
+            ),
+        ];
+        for (data, expected) in cases {
+            assert_eq!(
+                config_map_response(&config_map(&data)),
+                Some(expected),
+                "{data:?}"
+            );
+        }
+        assert_eq!(
+            config_map_response(&ConfigMap::default()),
+            Some(response(200, None, ""))
+        );
+    }
+
+    #[test]
+    fn invalid_status() {
+        for status in ["ok", "99", "600", "-200", ""] {
+            let (backend, logged) = warnings(|| {
+                config_map_response(&config_map(&[("status", status), ("body", "down")]))
+            });
+            assert_eq!(backend, None, "{status}");
+            assert_eq!(
+                logged,
+                [format!(
+                    "ConfigMap default/maintenance has an invalid status '{status}'"
+                )]
+            );
+        }
+    }
+
+    #[test]
+    fn handlers() {
+        assert!(handler(None, "ConfigMap").is_some());
+        assert!(handler(Some(""), "ConfigMap").is_some());
+        assert!(handler(Some("example.com"), "ConfigMap").is_none());
+        assert!(handler(None, "Secret").is_none());
+    }
+
+    #[test]
+    fn drop_unresolved_resources() {
+        let ingresses = [ingress(
+            "app",
+            IngressSpec {
+                default_backend: Some(resource("ConfigMap", "missing")),
+                rules: Some(vec![rule(
+                    Some("app.example.com"),
+                    vec![
+                        http_path("/app", "Prefix", service("app", port_number(80))),
+                        http_path("/down", "Prefix", resource("ConfigMap", "maintenance")),
+                        http_path("/missing", "Prefix", resource("ConfigMap", "missing")),
+                        http_path("/secret", "Prefix", resource("Secret", "credentials")),
+                    ],
+                )]),
+                ..IngressSpec::default()
+            },
+        )];
+        let mut config = ingresses.as_slice().config();
+
+        let key = |name: &str| {
+            (
+                None,
+                "ConfigMap".to_string(),
+                "default".to_string(),
+                name.to_string(),
+            )
+        };
+        // Unsupported resources are not resolved at all
+        let resolved = HashMap::from([
+            (key("maintenance"), Some(response(503, None, "down"))),
+            (key("missing"), None),
+        ]);
+        fill_resources(&mut config, &resolved);
+
+        let paths: Vec<_> = config.rules.iter().map(|r| r.path.clone()).collect();
+        assert_eq!(
+            paths,
+            [
+                HttpPath::Prefix("/app".to_string()),
+                HttpPath::Prefix("/down".to_string())
+            ]
+        );
+        assert!(matches!(config.rules[0].backend, Backend::Service { .. }));
+        assert_eq!(config.rules[1].backend, response(503, None, "down"));
+        assert_eq!(config.default_backend, None);
+
+        // A resolved default backend is kept
+        let mut config = ingresses.as_slice().config();
+        let resolved = HashMap::from([(key("missing"), Some(response(200, None, "")))]);
+        fill_resources(&mut config, &resolved);
+        assert_eq!(config.rules.len(), 2);
+        assert_eq!(config.default_backend, Some(response(200, None, "")));
+    }
+}
@@ -0,0 +1,299 @@
+use crate::controller::common::ingresses::{
+    default_tls_file, tls_secret_file, upstream_secret_file, GetFromIngresses,
+};
+use crate::controller::common::{ProxyContext, FIELD_MANAGER, TLS_SECRET_NAME};
+use k8s_openapi::api::core::v1::Secret;
+use k8s_openapi::api::networking::v1::Ingress;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use k8s_openapi::ByteString;
+use kube::api::{DeleteParams, Patch, PatchParams};
+use kube::{Api, Client};
+use log::warn;
+use openssl::pkey::PKey;
+use openssl::x509::X509;
+use std::collections::{BTreeMap, HashSet};
+
+/// Certificates copied for the proxy
+pub(in crate::controller) struct PublishedTls {
+    /// Hosts whose TLS Secret is valid
+    pub hosts: HashSet<String>,
+    /// Whether the default certificate is valid
+    pub default: bool,
+}
+
+/// Keys copied from the Secrets referenced for TLS to the backends
+const UPSTREAM_SECRET_KEYS: [&str; 3] = ["ca.crt", "tls.crt", "tls.key"];
+
+/// Copy the TLS Secrets of the Ingresses and the Secrets for TLS to their backends into the
+/// Secret mounted by the proxy.
+///
+/// A Secret that is missing, not of type `kubernetes.io/tls` or without a matching certificate
+/// and key is skipped.
+pub(in crate::controller) async fn apply_tls_secrets(
+    ctx: &impl ProxyContext,
+    ingresses: &[Ingress],
+) -> Result<PublishedTls, kube::Error> {
+    let mut hosts = HashSet::new();
+    let mut default = false;
+    let tls_secrets = {
+        let secrets = ingresses.tls_secrets();
+
+        let mut ss = BTreeMap::new();
+        for s in secrets {
+            let Some(secret) = load_secret(ctx.client(), &s.namespace, &s.secret).await? else {
+                warn!(
+                    "TLS Secret {}/{} of {} is not found",
+                    s.namespace, s.secret, s.host
+                );
+                continue;
+            };
+            match add_tls_files(&mut ss, |e| tls_secret_file(s.host.as_str(), e), &secret) {
+                Ok(()) => {
+                    hosts.insert(s.host);
+                }
+                Err(e) => warn!(
+                    "TLS Secret {}/{} of {} is ignored: {e}",
+                    s.namespace, s.secret, s.host
+                ),
+            }
+        }
+        if let Some(default_secret) = ctx.default_tls_secret() {
+            let (namespace, name) = default_secret
+                .split_once('/')
+                .unwrap_or((ctx.namespace(), default_secret));
+            match load_secret(ctx.client(), namespace, name).await? {
+                Some(secret) => match add_tls_files(&mut ss, default_tls_file, &secret) {
+                    Ok(()) => default = true,
+                    Err(e) => warn!("Default TLS Secret {namespace}/{name} is ignored: {e}"),
+                },
+                None => warn!("Default TLS Secret {namespace}/{name} is not found"),
+            }
+        }
+        for s in ingresses.upstream_secrets() {
+            // The proxy skips the backends whose files are missing
+            let Some(secret) = load_secret(ctx.client(), &s.namespace, &s.secret).await? else {
+                warn!("Secret {}/{} is not found", s.namespace, s.secret);
+                continue;
+            };
+            for (key, value) in secret.data.into_iter().flatten() {
+                if UPSTREAM_SECRET_KEYS.contains(&key.as_str()) {
+                    let file = upstream_secret_file(s.namespace.as_str(), s.secret.as_str(), &key);
+                    ss.insert(file, value);
+                }
+            }
+        }
+
+        ss
+    };
+
+    let secret = Secret {
+        metadata: ObjectMeta {
+            name: Some(TLS_SECRET_NAME.to_string()),
+            namespace: Some(ctx.namespace().to_string()),
+            labels: ctx.manifest_labels(),
+            ..ObjectMeta::default()
+        },
+        data: Some(tls_secrets),
+        type_: Some("Opaque".to_string()),
+        ..Secret::default()
+    };
+
+    let api: Api<Secret> = Api::namespaced(ctx.client(), ctx.namespace());
+    api.patch(
+        TLS_SECRET_NAME,
+        &PatchParams::apply(FIELD_MANAGER),
+        &Patch::Apply(secret),
+    )
+    .await?;
+
+    Ok(PublishedTls { hosts, default })
+}
+
+pub(in crate::controller) async fn cleanup_tls_secret(
+    ctx: &impl ProxyContext,
+) -> Result<(), kube::Error> {
+    let api: Api<Secret> = Api::namespaced(ctx.client(), ctx.namespace());
+    if api.get_opt(TLS_SECRET_NAME).await?.is_some() {
+        api.delete(TLS_SECRET_NAME, &DeleteParams::default())
+            .await?;
+    }
+    Ok(())
+}
+
+async fn load_secret(
+    client: Client,
+    namespace: &str,
+    name: &str,
+) -> Result<Option<Secret>, kube::Error> {
+    let api: Api<Secret> = Api::namespaced(client, namespace);
+    api.get_opt(name).await
+}
+
+/// Add the certificate and the key of a TLS Secret to the data of the Secret mounted by the
+/// proxy, as the files named by `file` from their extension
+fn add_tls_files(
+    data: &mut BTreeMap<String, ByteString>,
+    file: impl Fn(&str) -> String,
+    secret: &Secret,
+) -> Result<(), String> {
+    let (cert, key) = secret.extract_tls()?;
+    data.insert(file("crt"), cert);
+    data.insert(file("key"), key);
+    Ok(())
+}
+
+trait ExtractTls {
+    /// Certificate and key of a `kubernetes.io/tls` Secret, checked to be a matching pair
+    fn extract_tls(&self) -> Result<(ByteString, ByteString), String>;
+}
+
+impl ExtractTls for Secret {
+    fn extract_tls(&self) -> Result<(ByteString, ByteString), String> {
+        if self.type_.as_deref() != Some("kubernetes.io/tls") {
+            return Err("type is not kubernetes.io/tls".to_string());
+        }
+        let get = |key: &str| {
+            self.data
+                .as_ref()
+                .and_then(|d| d.get(key))
+                .ok_or_else(|| format!("{key} is missing"))
+        };
+        let cert = get("tls.crt")?;
+        let key = get("tls.key")?;
+
+        let x509 = X509::from_pem(cert.0.as_slice()).map_err(|e| format!("tls.crt: {e}"))?;
+        let pkey =
+            PKey::private_key_from_pem(key.0.as_slice()).map_err(|e| format!("tls.key: {e}"))?;
+        let public_key = x509.public_key().map_err(|e| format!("tls.crt: {e}"))?;
+        if !public_key.public_eq(&pkey) {
+            return Err("tls.key does not match tls.crt".to_string());
+        }
+
+        Ok((cert.clone(), key.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::controller::common::ingresses::tests::tls_ingress;
+    use crate::controller::common::ingresses::{tls_secret_file, GetFromIngresses};
+    use crate::controller::common::secret_path;
+    use crate::controller::common::secrets::add_tls_files;
+    use k8s_openapi::api::core::v1::Secret;
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+    use k8s_openapi::ByteString;
+    use openssl::asn1::Asn1Time;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::hash::MessageDigest;
+    use openssl::nid::Nid;
+    use openssl::pkey::{PKey, Private};
+    use openssl::x509::{X509Builder, X509NameBuilder};
+    use pingress_proxy_server::{GetTls, TlsMap};
+    use std::collections::BTreeMap;
+
+    fn private_key() -> PKey<Private> {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap()
+    }
+
+    fn tls_secret(cert_key: &PKey<Private>, key: &PKey<Private>, type_: &str) -> Secret {
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", "*.example.com").unwrap();
+        let name = name.build();
+        let mut cert = X509Builder::new().unwrap();
+        cert.set_version(2).unwrap();
+        cert.set_subject_name(&name).unwrap();
+        cert.set_issuer_name(&name).unwrap();
+        cert.set_pubkey(cert_key).unwrap();
+        cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        cert.set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        cert.sign(cert_key, MessageDigest::sha256()).unwrap();
+
+        Secret {
+            metadata: ObjectMeta {
+                name: Some("example-tls".to_string()),
+                namespace: Some("default".to_string()),
+                ..ObjectMeta::default()
+            },
+            data: Some(BTreeMap::from([
+                (
+                    "tls.crt".to_string(),
+                    ByteString(cert.build().to_pem().unwrap()),
+                ),
+                (
+                    "tls.key".to_string(),
+                    ByteString(key.private_key_to_pem_pkcs8().unwrap()),
+                ),
+            ])),
+            type_: Some(type_.to_string()),
+            ..Secret::default()
+        }
+    }
+
+    #[test]
+    fn ingress_to_proxy_files() {
+        let key = private_key();
+        let secret = tls_secret(&key, &key, "kubernetes.io/tls");
+        let ingresses = [tls_ingress(&["*.example.com", "Foo.example.com"])];
+        let ingresses = ingresses.as_slice();
+
+        let mut data = BTreeMap::new();
+        for s in ingresses.tls_secrets() {
+            add_tls_files(&mut data, |e| tls_secret_file(s.host.as_str(), e), &secret).unwrap();
+        }
+        for file in data.keys() {
+            assert!(
+                file.chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_')),
+                "{file} is not a valid Secret key"
+            );
+        }
+
+        // The Secret is mounted at SECRET_BASE_PATH, a file per key
+        let dir = std::env::temp_dir().join(format!("pingress-tls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (file, content) in &data {
+            std::fs::write(dir.join(file), &content.0).unwrap();
+        }
+        let mounted = |path: &mut String| {
+            let file = path.strip_prefix(secret_path("").as_str()).unwrap();
+            *path = dir.join(file).to_str().unwrap().to_string();
+        };
+
+        let mut config = ingresses.config();
+        assert_eq!(config.rules.len(), 2);
+        for tls in config.rules.iter_mut().filter_map(|r| r.tls.as_mut()) {
+            mounted(&mut tls.key);
+            mounted(&mut tls.cert);
+        }
+
+        // Loaded by the proxy, which has no default certificate to fall back to
+        let tls = TlsMap::from(config);
+        let name = |sni| tls.get_tls(Some(sni)).map(|(name, _)| name);
+        assert_eq!(name("foo.example.com").as_deref(), Some("foo.example.com"));
+        assert_eq!(name("bar.example.com").as_deref(), Some("*.example.com"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn invalid_tls_secret() {
+        let key = private_key();
+        let file = |e: &str| tls_secret_file("example.com", e);
+
+        let opaque = tls_secret(&key, &key, "Opaque");
+        assert!(add_tls_files(&mut BTreeMap::new(), file, &opaque).is_err());
+
+        let mismatched = tls_secret(&key, &private_key(), "kubernetes.io/tls");
+        assert!(add_tls_files(&mut BTreeMap::new(), file, &mismatched).is_err());
+
+        let mut garbage = tls_secret(&key, &key, "kubernetes.io/tls");
+        garbage
+            .data
+            .as_mut()
+            .unwrap()
+            .insert("tls.crt".to_string(), ByteString(b"garbage".to_vec()));
+        assert!(add_tls_files(&mut BTreeMap::new(), file, &garbage).is_err());
+    }
+}
@@ -1,13 +1,15 @@
-mod config_map;
 mod daemonset;
-mod ingresses;
 mod reconcile;
-mod secrets;
 
+use crate::controller::common::{
+    config_map_mapper, endpoint_slice_mapper, manifest_selector, proxy_resource_mapper,
+    service_mapper, ProxyContext,
+};
 use crate::controller::host_port::reconcile::reconcile;
-use crate::controller::{handle_error, LogControllerResult};
+use crate::controller::{handle_error, LogControllerResult, ProxyDefaults};
 use k8s_openapi::api::apps::v1::DaemonSet;
-use k8s_openapi::api::core::v1::Service;
+use k8s_openapi::api::core::v1::{ConfigMap, Service};
+use k8s_openapi::api::discovery::v1::EndpointSlice;
 use k8s_openapi::api::networking::v1::Ingress;
 use kube::runtime::Controller;
 use kube::{Api, Client};
@@ -15,12 +17,6 @@ use std::collections::BTreeMap;
 use std::future::Future;
 use std::sync::Arc;
 
-const FIELD_MANAGER: &str = "kinorca.com/pingress-controller";
-const TLS_SECRET_NAME: &str = "pingress-tls-secret";
-const CONFIG_MAP_NAME: &str = "pingress-config";
-const CONFIG_KEY: &str = "proxy.json";
-const SECRET_BASE_PATH: &str = "/etc/pingress/keys";
-
 pub(crate) async fn run_host_port<F>(
     client: Client,
     shutdown_signal: F,
@@ -28,6 +24,7 @@ pub(crate) async fn run_host_port<F>(
     node_selector: Vec<String>,
     image_pull_secret: Option<String>,
     proxy_server_image: String,
+    defaults: ProxyDefaults,
 ) where
     F: Future<Output = ()> + Send + Sync + 'static,
 {
@@ -39,32 +36,67 @@ pub(crate) async fn run_host_port<F>(
         })
         .collect();
 
+    let ctx = Arc::new(Context::new(
+        client.clone(),
+        namespace,
+        node_selector,
+        image_pull_secret,
+        proxy_server_image,
+        defaults,
+    ));
+
     let ingress_api: Api<Ingress> = Api::all(client.clone());
     let ingress_wc = kube::runtime::watcher::Config::default().any_semantic();
 
-    let daemonset_api: Api<DaemonSet> = Api::namespaced(client.clone(), namespace.as_str());
-    let daemonset_wc = kube::runtime::watcher::Config::default()
-        .labels("kinorca.com/managed-by=pingress-controller");
+    // The resources of the proxy server are shared by every Ingress
+    let daemonset_api: Api<DaemonSet> = Api::namespaced(client.clone(), ctx.namespace());
+    let daemonset_wc =
+        kube::runtime::watcher::Config::default().labels(&manifest_selector(ctx.as_ref()));
+
+    let service_api: Api<Service> = Api::namespaced(client.clone(), ctx.namespace());
+    let service_wc =
+        kube::runtime::watcher::Config::default().labels(&manifest_selector(ctx.as_ref()));
+
+    let backend_service_api: Api<Service> = Api::all(client.clone());
+    let backend_service_wc = kube::runtime::watcher::Config::default();
+
+    let config_map_api: Api<ConfigMap> = Api::all(client.clone());
+    let config_map_wc = kube::runtime::watcher::Config::default();
 
-    let service_api: Api<Service> = Api::namespaced(client.clone(), namespace.as_str());
-    let service_wc = kube::runtime::watcher::Config::default()
-        .labels("kinorca.com/managed-by=pingress-controller");
+    let endpoint_slice_api: Api<EndpointSlice> = Api::all(client.clone());
+    let endpoint_slice_wc = kube::runtime::watcher::Config::default();
 
-    Controller::new(ingress_api, ingress_wc)
+    let controller = Controller::new(ingress_api, ingress_wc);
+    let store = controller.store();
+
+    controller
         .graceful_shutdown_on(shutdown_signal)
-        .owns(daemonset_api, daemonset_wc)
-        .owns(service_api, service_wc)
-        .run(
-            |i, c| async { reconcile(i, c).await },
-            handle_error,
-            Arc::new(Context::new(
-                client,
-                namespace,
-                node_selector,
-                image_pull_secret,
-                proxy_server_image,
-            )),
+        .watches(
+            daemonset_api,
+            daemonset_wc,
+            proxy_resource_mapper(store.clone()),
+        )
+        .watches(
+            service_api,
+            service_wc,
+            proxy_resource_mapper(store.clone()),
+        )
+        .watches(
+            backend_service_api,
+            backend_service_wc,
+            service_mapper(store.clone()),
+        )
+        .watches(
+            config_map_api,
+            config_map_wc,
+            config_map_mapper(store.clone()),
+        )
+        .watches(
+            endpoint_slice_api,
+            endpoint_slice_wc,
+            endpoint_slice_mapper(store),
         )
+        .run(|i, c| async { reconcile(i, c).await }, handle_error, ctx)
         .log_controller_result()
         .await;
 }
@@ -75,6 +107,7 @@ struct Context {
     node_selector: BTreeMap<String, String>,
     image_pull_secret: Option<String>,
     proxy_server_image: String,
+    defaults: ProxyDefaults,
 }
 
 impl Context {
@@ -84,6 +117,7 @@ impl Context {
         node_selector: BTreeMap<String, String>,
         image_pull_secret: Option<String>,
         proxy_server_image: String,
+        defaults: ProxyDefaults,
     ) -> Self {
         Self {
             client,
@@ -91,19 +125,38 @@ impl Context {
             node_selector,
             image_pull_secret,
             proxy_server_image,
+            defaults,
         }
     }
 }
 
-fn manifest_labels() -> Option<BTreeMap<String, String>> {
-    Some(BTreeMap::from([
-        (
-            "app.kubernetes.io/managed-by".to_string(),
-            "pingress-controller".to_string(),
-        ),
-        (
-            "kinorca.com/pingress-controller-type".to_string(),
-            "host-port".to_string(),
-        ),
-    ]))
+impl ProxyContext for Context {
+    fn client(&self) -> Client {
+        self.client.clone()
+    }
+
+    fn namespace(&self) -> &str {
+        self.namespace.as_str()
+    }
+
+    fn not_found_body(&self) -> Option<&str> {
+        self.defaults.not_found_body.as_deref()
+    }
+
+    fn default_tls_secret(&self) -> Option<&str> {
+        self.defaults.default_tls_secret.as_deref()
+    }
+
+    fn manifest_labels(&self) -> Option<BTreeMap<String, String>> {
+        Some(BTreeMap::from([
+            (
+                "app.kubernetes.io/managed-by".to_string(),
+                "pingress-controller".to_string(),
+            ),
+            (
+                "kinorca.com/pingress-controller-type".to_string(),
+                "host-port".to_string(),
+            ),
+        ]))
+    }
 }
@@ -0,0 +1,53 @@
+use crate::controller::common::{
+    proxy_pod_labels, proxy_pod_template, ProxyContext, FIELD_MANAGER, PROXY_SERVER_NAME,
+};
+use crate::controller::load_balancer::Context;
+use k8s_openapi::api::apps::v1::{Deployment, DeploymentSpec};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{LabelSelector, ObjectMeta};
+use kube::api::{DeleteParams, Patch, PatchParams};
+use kube::Api;
+
+pub(super) async fn apply_deployment(ctx: &Context) -> Result<(), kube::Error> {
+    let deployment = Deployment {
+        metadata: ObjectMeta {
+            name: Some(PROXY_SERVER_NAME.to_string()),
+            namespace: Some(ctx.namespace.clone()),
+            labels: ctx.manifest_labels(),
+            ..ObjectMeta::default()
+        },
+        spec: Some(DeploymentSpec {
+            replicas: Some(ctx.replicas),
+            selector: LabelSelector {
+                match_labels: Some(proxy_pod_labels()),
+                ..LabelSelector::default()
+            },
+            template: proxy_pod_template(
+                ctx.proxy_server_image.as_str(),
+                ctx.image_pull_secret.as_deref(),
+                None,
+                false,
+            ),
+            ..DeploymentSpec::default()
+        }),
+        ..Deployment::default()
+    };
+
+    let api: Api<Deployment> = Api::namespaced(ctx.client.clone(), ctx.namespace.as_str());
+    api.patch(
+        PROXY_SERVER_NAME,
+        &PatchParams::apply(FIELD_MANAGER),
+        &Patch::Apply(deployment),
+    )
+    .await?;
+
+    Ok(())
+}
+
+pub(super) async fn cleanup_deployment(ctx: &Context) -> Result<(), kube::Error> {
+    let api: Api<Deployment> = Api::namespaced(ctx.client.clone(), ctx.namespace.as_str());
+    if api.get_opt(PROXY_SERVER_NAME).await?.is_some() {
+        api.delete(PROXY_SERVER_NAME, &DeleteParams::default())
+            .await?;
+    }
+    Ok(())
+}
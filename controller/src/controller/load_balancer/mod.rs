@@ -1,7 +1,16 @@
+mod deployment;
 mod reconcile;
+mod service;
 
+use crate::controller::common::{
+    config_map_mapper, endpoint_slice_mapper, manifest_selector, proxy_resource_mapper,
+    service_mapper, ProxyContext,
+};
 use crate::controller::load_balancer::reconcile::reconcile;
-use crate::controller::{handle_error, LogControllerResult};
+use crate::controller::{handle_error, LogControllerResult, ProxyDefaults};
+use k8s_openapi::api::apps::v1::Deployment;
+use k8s_openapi::api::core::v1::{ConfigMap, Service};
+use k8s_openapi::api::discovery::v1::EndpointSlice;
 use k8s_openapi::api::networking::v1::Ingress;
 use kube::runtime::Controller;
 use kube::{Api, Client};
@@ -12,50 +21,135 @@ use std::sync::Arc;
 pub(crate) async fn run_load_balancer<F>(
     client: Client,
     shutdown_signal: F,
+    namespace: String,
+    replicas: i32,
     image_pull_secret: Option<String>,
     proxy_server_image: String,
+    defaults: ProxyDefaults,
 ) where
     F: Future<Output = ()> + Send + Sync + 'static,
 {
+    let ctx = Arc::new(Context::new(
+        client.clone(),
+        namespace,
+        replicas,
+        image_pull_secret,
+        proxy_server_image,
+        defaults,
+    ));
+
     let ingress_api: Api<Ingress> = Api::all(client.clone());
     let ingress_wc = kube::runtime::watcher::Config::default().any_semantic();
 
-    Controller::new(ingress_api, ingress_wc)
+    // The resources of the proxy server are shared by every Ingress
+    let deployment_api: Api<Deployment> = Api::namespaced(client.clone(), ctx.namespace());
+    let deployment_wc =
+        kube::runtime::watcher::Config::default().labels(&manifest_selector(ctx.as_ref()));
+
+    let service_api: Api<Service> = Api::namespaced(client.clone(), ctx.namespace());
+    let service_wc =
+        kube::runtime::watcher::Config::default().labels(&manifest_selector(ctx.as_ref()));
+
+    let backend_service_api: Api<Service> = Api::all(client.clone());
+    let backend_service_wc = kube::runtime::watcher::Config::default();
+
+    let config_map_api: Api<ConfigMap> = Api::all(client.clone());
+    let config_map_wc = kube::runtime::watcher::Config::default();
+
+    let endpoint_slice_api: Api<EndpointSlice> = Api::all(client.clone());
+    let endpoint_slice_wc = kube::runtime::watcher::Config::default();
+
+    let controller = Controller::new(ingress_api, ingress_wc);
+    let store = controller.store();
+
+    controller
         .graceful_shutdown_on(shutdown_signal)
-        .run(
-            |i, c| async { reconcile(i, c).await },
-            handle_error,
-            Arc::new(Context::new(client, image_pull_secret, proxy_server_image)),
+        .watches(
+            deployment_api,
+            deployment_wc,
+            proxy_resource_mapper(store.clone()),
+        )
+        .watches(
+            service_api,
+            service_wc,
+            proxy_resource_mapper(store.clone()),
+        )
+        .watches(
+            backend_service_api,
+            backend_service_wc,
+            service_mapper(store.clone()),
+        )
+        .watches(
+            config_map_api,
+            config_map_wc,
+            config_map_mapper(store.clone()),
         )
+        .watches(
+            endpoint_slice_api,
+            endpoint_slice_wc,
+            endpoint_slice_mapper(store),
+        )
+        .run(|i, c| async { reconcile(i, c).await }, handle_error, ctx)
         .log_controller_result()
         .await;
 }
 
 struct Context {
     client: Client,
+    namespace: String,
+    replicas: i32,
     image_pull_secret: Option<String>,
     proxy_server_image: String,
+    defaults: ProxyDefaults,
 }
 
 impl Context {
-    fn new(client: Client, image_pull_secret: Option<String>, proxy_server_image: String) -> Self {
+    fn new(
+        client: Client,
+        namespace: String,
+        replicas: i32,
+        image_pull_secret: Option<String>,
+        proxy_server_image: String,
+        defaults: ProxyDefaults,
+    ) -> Self {
         Self {
             client,
+            namespace,
+            replicas,
             image_pull_secret,
             proxy_server_image,
+            defaults,
         }
     }
 }
 
-fn manifest_labels() -> Option<BTreeMap<String, String>> {
-    Some(BTreeMap::from([
-        (
-            "app.kubernetes.io/managed-by".to_string(),
-            "pingress-controller".to_string(),
-        ),
-        (
-            "kinorca.com/pingress-controller-type".to_string(),
-            "load-balancer".to_string(),
-        ),
-    ]))
+impl ProxyContext for Context {
+    fn client(&self) -> Client {
+        self.client.clone()
+    }
+
+    fn namespace(&self) -> &str {
+        self.namespace.as_str()
+    }
+
+    fn not_found_body(&self) -> Option<&str> {
+        self.defaults.not_found_body.as_deref()
+    }
+
+    fn default_tls_secret(&self) -> Option<&str> {
+        self.defaults.default_tls_secret.as_deref()
+    }
+
+    fn manifest_labels(&self) -> Option<BTreeMap<String, String>> {
+        Some(BTreeMap::from([
+            (
+                "app.kubernetes.io/managed-by".to_string(),
+                "pingress-controller".to_string(),
+            ),
+            (
+                "kinorca.com/pingress-controller-type".to_string(),
+                "load-balancer".to_string(),
+            ),
+        ]))
+    }
 }
@@ -1,4 +1,11 @@
+use crate::controller::common::{
+    apply_config_map, apply_tls_secrets, cleanup_config_map, cleanup_tls_secret, is_managed,
+    list_ingresses, FINALIZER_NAME,
+};
+use crate::controller::load_balancer::deployment::{apply_deployment, cleanup_deployment};
+use crate::controller::load_balancer::service::{apply_service, cleanup_service};
 use crate::controller::load_balancer::Context;
+use crate::try_with_log;
 use k8s_openapi::api::networking::v1::Ingress;
 use kube::runtime::controller::Action;
 use kube::runtime::finalizer;
@@ -10,12 +17,7 @@ pub(super) async fn reconcile(
     ingress: Arc<Ingress>,
     ctx: Arc<Context>,
 ) -> Result<Action, Error<kube::Error>> {
-    if ingress
-        .spec
-        .as_ref()
-        .and_then(|s| s.ingress_class_name.as_deref())
-        .is_some_and(|s| s == "pingress")
-    {
+    if !is_managed(ingress.as_ref()) {
         return Ok(Action::await_change());
     }
 
@@ -24,15 +26,27 @@ pub(super) async fn reconcile(
         ingress.namespace().as_deref().unwrap_or("default"),
     );
 
-    finalizer(
-        &api,
-        "kinorca.com/pingress-controller",
-        ingress,
-        |e| async { reconcile_impl(ctx, e).await },
-    )
+    finalizer(&api, FINALIZER_NAME, ingress, |e| async {
+        reconcile_impl(ctx, e).await
+    })
     .await
 }
 
-async fn reconcile_impl(_ctx: Arc<Context>, _event: Event<Ingress>) -> Result<Action, kube::Error> {
-    todo!()
+async fn reconcile_impl(ctx: Arc<Context>, event: Event<Ingress>) -> Result<Action, kube::Error> {
+    let ingresses = try_with_log!(list_ingresses(ctx.client.clone(), &event).await);
+
+    if ingresses.is_empty() {
+        try_with_log!(cleanup_service(ctx.as_ref()).await);
+        try_with_log!(cleanup_deployment(ctx.as_ref()).await);
+        try_with_log!(cleanup_tls_secret(ctx.as_ref()).await);
+        try_with_log!(cleanup_config_map(ctx.as_ref()).await);
+        return Ok(Action::await_change());
+    }
+
+    let tls = try_with_log!(apply_tls_secrets(ctx.as_ref(), ingresses.as_slice()).await);
+    try_with_log!(apply_config_map(ctx.as_ref(), ingresses.as_slice(), &tls).await);
+    try_with_log!(apply_deployment(ctx.as_ref()).await);
+    try_with_log!(apply_service(ctx.as_ref()).await);
+
+    Ok(Action::await_change())
 }
@@ -0,0 +1,61 @@
+use crate::controller::common::{
+    proxy_pod_labels, ProxyContext, FIELD_MANAGER, HTTPS_PORT, HTTP_PORT, PROXY_SERVER_NAME,
+};
+use crate::controller::load_balancer::Context;
+use k8s_openapi::api::core::v1::{Service, ServicePort, ServiceSpec};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use k8s_openapi::apimachinery::pkg::util::intstr::IntOrString;
+use kube::api::{DeleteParams, Patch, PatchParams};
+use kube::Api;
+
+pub(super) async fn apply_service(ctx: &Context) -> Result<(), kube::Error> {
+    let service = Service {
+        metadata: ObjectMeta {
+            name: Some(PROXY_SERVER_NAME.to_string()),
+            namespace: Some(ctx.namespace.clone()),
+            labels: ctx.manifest_labels(),
+            ..ObjectMeta::default()
+        },
+        spec: Some(ServiceSpec {
+            type_: Some("LoadBalancer".to_string()),
+            selector: Some(proxy_pod_labels()),
+            ports: Some(vec![
+                ServicePort {
+                    name: Some("http".to_string()),
+                    port: 80,
+                    protocol: Some("TCP".to_string()),
+                    target_port: Some(IntOrString::Int(HTTP_PORT)),
+                    ..ServicePort::default()
+                },
+                ServicePort {
+                    name: Some("https".to_string()),
+                    port: 443,
+                    protocol: Some("TCP".to_string()),
+                    target_port: Some(IntOrString::Int(HTTPS_PORT)),
+                    ..ServicePort::default()
+                },
+            ]),
+            ..ServiceSpec::default()
+        }),
+        ..Service::default()
+    };
+
+    let api: Api<Service> = Api::namespaced(ctx.client.clone(), ctx.namespace.as_str());
+    api.patch(
+        PROXY_SERVER_NAME,
+        &PatchParams::apply(FIELD_MANAGER),
+        &Patch::Apply(service),
+    )
+    .await?;
+
+    Ok(())
+}
+
+pub(super) async fn cleanup_service(ctx: &Context) -> Result<(), kube::Error> {
+    let api: Api<Service> = Api::namespaced(ctx.client.clone(), ctx.namespace.as_str());
+    if api.get_opt(PROXY_SERVER_NAME).await?.is_some() {
+        api.delete(PROXY_SERVER_NAME, &DeleteParams::default())
+            .await?;
+    }
+    Ok(())
+}
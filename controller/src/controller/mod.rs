@@ -1,3 +1,4 @@
+mod common;
 mod host_port;
 mod load_balancer;
 
@@ -12,6 +13,12 @@ use std::future::{ready, Future};
 use std::sync::Arc;
 use std::time::Duration;
 
+/// Responses and certificate of the proxy server for the requests matching no Ingress
+pub(crate) struct ProxyDefaults {
+    pub not_found_body: Option<String>,
+    pub default_tls_secret: Option<String>,
+}
+
 trait LogControllerResult {
     fn log_controller_result(self) -> impl Future<Output = ()>;
 }
@@ -1,6 +1,6 @@
 mod controller;
 
-use crate::controller::{run_host_port, run_load_balancer};
+use crate::controller::{run_host_port, run_load_balancer, ProxyDefaults};
 use clap::{Parser, ValueEnum};
 use kube::Client;
 use log::{debug, info};
@@ -33,13 +33,26 @@ struct Args {
     #[clap(long, default_value = "ghcr.io/kinorca/pingress-proxy-server:latest")]
     proxy_server_image: String,
 
-    /// Proxy deployment namespace (--backend=HostPort only)
+    /// Proxy deployment namespace
     #[clap(long, default_value = "pingress-system")]
     namespace: String,
 
+    /// Number of proxy server replicas. (--backend=LoadBalancer only)
+    #[clap(long, default_value_t = 2)]
+    replicas: i32,
+
     /// Node selector labels. (--backend=HostPort only) (e.g.: "example.com/node-type=external-network")
     #[clap(long, value_delimiter = ',', num_args = 0..)]
     node_selector: Vec<String>,
+
+    /// HTML body of the 404 response to requests matching no Ingress rule without a default backend
+    #[clap(long)]
+    not_found_body: Option<String>,
+
+    /// TLS Secret (`namespace/name`, or `name` in --namespace) served to the clients whose SNI
+    /// matches no Ingress, or without SNI
+    #[clap(long)]
+    default_tls_secret: Option<String>,
 }
 
 #[tokio::main]
@@ -62,16 +75,25 @@ async fn main() {
                 args.node_selector,
                 args.image_pull_secret,
                 args.proxy_server_image,
+                ProxyDefaults {
+                    not_found_body: args.not_found_body,
+                    default_tls_secret: args.default_tls_secret,
+                },
             )
             .await
         }
         Type::LoadBalancer => {
-            unimplemented!();
             run_load_balancer(
                 client,
                 shutdown_signal(),
+                args.namespace,
+                args.replicas,
                 args.image_pull_secret,
                 args.proxy_server_image,
+                ProxyDefaults {
+                    not_found_body: args.not_found_body,
+                    default_tls_secret: args.default_tls_secret,
+                },
             )
             .await
         }
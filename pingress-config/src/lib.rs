@@ -3,48 +3,530 @@ use serde::{Deserialize, Serialize};
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct PingressConfiguration {
     pub rules: Vec<PathRule>,
+    /// Backend of the requests matching no rule
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_backend: Option<Backend>,
+    /// HTML body of the 404 response to the requests matching no rule without a default backend
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_found_body: Option<String>,
+    /// Certificate served to the clients whose SNI matches no certificate, or without SNI
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_tls: Option<Tls>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct PathRule {
-    pub host: String,
+    /// Host of the requests, any host when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
     pub tls: Option<Tls>,
     pub path: HttpPath,
     pub backend: Backend,
+    /// Retries of failed requests, none when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry: Option<RetryPolicy>,
+    #[serde(default)]
+    pub timeouts: Timeouts,
+    /// Whether HTTP/2 is advertised in TLS ALPN for the host
+    #[serde(default = "default_true")]
+    pub http2: bool,
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
 pub struct Tls {
     pub key: String,
+    /// PEM of the certificate followed by its intermediates
     pub cert: String,
+    /// OCSP response stapled to the handshakes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ocsp: Option<OcspStapling>,
+    /// Lowest protocol version accepted from the clients
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_version: Option<TlsVersion>,
+    /// OpenSSL cipher list of TLS 1.2 and below (e.g. `ECDHE+AESGCM`).
+    /// TLS 1.3 cipher suites are negotiated before the certificate is selected.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ciphers: Option<String>,
+    /// Colon separated curves of the key exchange of TLS 1.2 and below (e.g. `X25519:P-256`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub curves: Option<String>,
+    /// Verification of client certificates (mutual TLS)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_auth: Option<ClientAuth>,
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+impl Tls {
+    /// Certificate and key with the default settings
+    pub fn new(key: String, cert: String) -> Self {
+        Self {
+            key,
+            cert,
+            ocsp: None,
+            min_version: None,
+            ciphers: None,
+            curves: None,
+            client_auth: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+pub enum TlsVersion {
+    Tls10,
+    Tls11,
+    Tls12,
+    Tls13,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct ClientAuth {
+    /// Path to the PEM bundle of the CAs that verify the client certificates
+    pub ca: String,
+    #[serde(default)]
+    pub mode: ClientAuthMode,
+    /// Request header carrying the subject of the verified client certificate to the backend.
+    /// It is removed from the requests of the clients.
+    #[serde(default = "default_subject_header")]
+    pub subject_header: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum ClientAuthMode {
+    /// Clients without a valid certificate are rejected
+    #[default]
+    Required,
+    /// Clients may omit their certificate, but an invalid one is rejected
+    Optional,
+}
+
+/// Request header carrying the subject of the client certificate unless configured otherwise
+pub const DEFAULT_SUBJECT_HEADER: &str = "X-Client-Subject";
+
+fn default_subject_header() -> String {
+    DEFAULT_SUBJECT_HEADER.to_string()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(tag = "type")]
+pub enum OcspStapling {
+    /// DER OCSP response read from a file, kept up to date by another process
+    File { path: String },
+    /// Response fetched in the background from the OCSP responder of the certificate
+    Fetch {
+        /// URL of the responder, instead of the one of the certificate
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        responder: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 #[serde(tag = "type", content = "path")]
 pub enum HttpPath {
+    /// Match on path elements split by `/`, ignoring a trailing slash
     Prefix(String),
+    /// Match the whole path, case-sensitively
     Exact(String),
+    /// Match as a plain string prefix (e.g. `/foo` matches `/foobar`)
+    ImplementationSpecific(String),
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 #[serde(tag = "type")]
 pub enum Backend {
     Service {
         name: String,
         namespace: String,
         port: Port,
+        /// Ready endpoints of the Service.
+        /// When empty, the Service is reached through its cluster DNS name.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        endpoints: Vec<Endpoint>,
+        /// How requests are spread across the endpoints
+        #[serde(default)]
+        load_balancing: LoadBalancing,
+        /// Active health check of the endpoints
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        health_check: Option<HealthCheck>,
+        /// Passive ejection of endpoints that keep failing
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        outlier_detection: Option<OutlierDetection>,
+        /// Limits of concurrent requests to the backend
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        circuit_breaker: Option<CircuitBreaker>,
+        #[serde(default)]
+        protocol: Protocol,
+        /// TLS settings of `Https` and `Grpcs` backends
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tls: Option<UpstreamTls>,
+    },
+    /// Service of type ExternalName, reached through the DNS name it points to
+    ExternalName {
+        name: String,
+        namespace: String,
+        /// DNS name of the Service, sent as the Host and the SNI of the requests
+        external_name: String,
+        port: u16,
+        /// Limits of concurrent requests to the backend
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        circuit_breaker: Option<CircuitBreaker>,
+        #[serde(default)]
+        protocol: Protocol,
+        /// TLS settings of `Https` and `Grpcs` backends
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tls: Option<UpstreamTls>,
+    },
+    /// Resource referenced by an Ingress backend, resolved by the controller
+    Resource {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        api_group: Option<String>,
+        kind: String,
+        name: String,
+        namespace: String,
+    },
+    /// Response served by the proxy itself (e.g. a maintenance page or a redirect)
+    Response {
+        status: u16,
+        /// `Location` header of a redirect
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        location: Option<String>,
+        /// `Content-Type` header of the body, HTML when unset
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        content_type: Option<String>,
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        body: String,
     },
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+/// Protocol spoken to the endpoints
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Protocol {
+    /// HTTP/1.1
+    #[default]
+    Http,
+    /// HTTP/1.1 over TLS
+    Https,
+    /// HTTP/2 over TLS, negotiated with ALPN
+    H2,
+    /// HTTP/2 over plain text, with prior knowledge
+    H2c,
+    /// gRPC over plain text (`h2c`)
+    Grpc,
+    /// gRPC over TLS (`h2`)
+    Grpcs,
+}
+
+impl Protocol {
+    pub fn is_tls(&self) -> bool {
+        matches!(self, Protocol::Https | Protocol::H2 | Protocol::Grpcs)
+    }
+
+    /// Whether the endpoints speak HTTP/2 only
+    pub fn is_http2(&self) -> bool {
+        matches!(
+            self,
+            Protocol::H2 | Protocol::H2c | Protocol::Grpc | Protocol::Grpcs
+        )
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct UpstreamTls {
+    /// Server name sent in SNI and verified, instead of the host of the request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sni: Option<String>,
+    /// Path to the PEM bundle of the CAs that verify the backend certificate.
+    /// The backend certificate is not verified when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ca: Option<String>,
+    /// Client certificate for mutual TLS
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_cert: Option<Tls>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Endpoint {
+    /// IPv4 or IPv6 address
+    pub address: String,
+    pub port: u16,
+    /// Relative weight for `WeightedRoundRobin`
+    #[serde(default = "default_weight")]
+    pub weight: u16,
+}
+
+fn default_weight() -> u16 {
+    1
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "type")]
+pub enum LoadBalancing {
+    #[default]
+    RoundRobin,
+    /// Round robin in proportion to the endpoint weights
+    WeightedRoundRobin,
+    /// Endpoint with the fewest active requests
+    LeastConnections,
+    /// Less loaded of two endpoints picked at random
+    RandomTwoChoices,
+    /// Ketama consistent hashing, so that requests with the same key reach the same endpoint
+    ConsistentHash { key: HashKey },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct HealthCheck {
+    #[serde(flatten)]
+    pub probe: Probe,
+    #[serde(default = "default_interval_ms")]
+    pub interval_ms: u64,
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+    /// Consecutive successes to mark an unhealthy endpoint healthy
+    #[serde(default = "default_healthy_threshold")]
+    pub healthy_threshold: usize,
+    /// Consecutive failures to mark a healthy endpoint unhealthy
+    #[serde(default = "default_unhealthy_threshold")]
+    pub unhealthy_threshold: usize,
+}
+
+impl HealthCheck {
+    /// Health check with the default interval, timeout and thresholds
+    pub fn new(probe: Probe) -> Self {
+        Self {
+            probe,
+            interval_ms: default_interval_ms(),
+            timeout_ms: default_timeout_ms(),
+            healthy_threshold: default_healthy_threshold(),
+            unhealthy_threshold: default_unhealthy_threshold(),
+        }
+    }
+}
+
+fn default_interval_ms() -> u64 {
+    10_000
+}
+
+fn default_timeout_ms() -> u64 {
+    1_000
+}
+
+fn default_healthy_threshold() -> usize {
+    1
+}
+
+fn default_unhealthy_threshold() -> usize {
+    3
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "type")]
+pub enum Probe {
+    /// Succeeds when a TCP connection is established
+    Tcp,
+    /// Succeeds on a 2xx response to `GET {path}`
+    Http { path: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct OutlierDetection {
+    /// Consecutive 5xx responses or connect failures to eject an endpoint
+    #[serde(default = "default_consecutive_errors")]
+    pub consecutive_errors: usize,
+    /// Duration of the first ejection, doubled on every ejection in a row
+    #[serde(default = "default_base_ejection_ms")]
+    pub base_ejection_ms: u64,
+    #[serde(default = "default_max_ejection_ms")]
+    pub max_ejection_ms: u64,
+}
+
+impl Default for OutlierDetection {
+    fn default() -> Self {
+        Self {
+            consecutive_errors: default_consecutive_errors(),
+            base_ejection_ms: default_base_ejection_ms(),
+            max_ejection_ms: default_max_ejection_ms(),
+        }
+    }
+}
+
+fn default_consecutive_errors() -> usize {
+    5
+}
+
+fn default_base_ejection_ms() -> u64 {
+    30_000
+}
+
+fn default_max_ejection_ms() -> u64 {
+    300_000
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct CircuitBreaker {
+    /// Concurrent requests to the backend
+    #[serde(default = "default_max_requests")]
+    pub max_requests: usize,
+    /// Concurrent requests waiting for a connection to the backend
+    #[serde(default = "default_max_pending")]
+    pub max_pending: usize,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self {
+            max_requests: default_max_requests(),
+            max_pending: default_max_pending(),
+        }
+    }
+}
+
+fn default_max_requests() -> usize {
+    1024
+}
+
+fn default_max_pending() -> usize {
+    1024
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RetryPolicy {
+    /// Conditions that trigger a retry
+    #[serde(default = "default_retry_on")]
+    pub retry_on: Vec<RetryOn>,
+    /// Attempts including the first one
+    #[serde(default = "default_attempts")]
+    pub attempts: usize,
+    /// Delay before the first retry, doubled on every retry
+    #[serde(default = "default_backoff_ms")]
+    pub backoff_ms: u64,
+    #[serde(default = "default_max_backoff_ms")]
+    pub max_backoff_ms: u64,
+    /// Retry on an endpoint that was not tried yet, when there is one
+    #[serde(default = "default_true")]
+    pub different_endpoint: bool,
+    /// Retry methods that are not idempotent (e.g. `POST`) too
+    #[serde(default)]
+    pub non_idempotent: bool,
+    #[serde(default)]
+    pub budget: RetryBudget,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retry_on: default_retry_on(),
+            attempts: default_attempts(),
+            backoff_ms: default_backoff_ms(),
+            max_backoff_ms: default_max_backoff_ms(),
+            different_endpoint: true,
+            non_idempotent: false,
+            budget: RetryBudget::default(),
+        }
+    }
+}
+
+fn default_retry_on() -> Vec<RetryOn> {
+    vec![RetryOn::ConnectFailure]
+}
+
+fn default_attempts() -> usize {
+    3
+}
+
+fn default_backoff_ms() -> u64 {
+    25
+}
+
+fn default_max_backoff_ms() -> u64 {
+    250
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum RetryOn {
+    /// The connection to the endpoint cannot be established
+    ConnectFailure,
+    /// The endpoint responds 502, 503 or 504
+    GatewayError,
+    /// The connection is reset or closed before the response starts
+    Reset,
+}
+
+/// Limit of concurrent retries, so that retries cannot overload a failing backend
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RetryBudget {
+    /// Concurrent retries as a percentage of the concurrent requests of the route
+    #[serde(default = "default_budget_percent")]
+    pub percent: usize,
+    /// Concurrent retries allowed regardless of the percentage
+    #[serde(default = "default_min_concurrency")]
+    pub min_concurrency: usize,
+}
+
+impl Default for RetryBudget {
+    fn default() -> Self {
+        Self {
+            percent: default_budget_percent(),
+            min_concurrency: default_min_concurrency(),
+        }
+    }
+}
+
+fn default_budget_percent() -> usize {
+    20
+}
+
+fn default_min_concurrency() -> usize {
+    3
+}
+
+/// Timeouts of the requests to the backend. Unset ones are left to the proxy defaults.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Timeouts {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connect_ms: Option<u64>,
+    /// Longest wait for data from the backend
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read_ms: Option<u64>,
+    /// Longest wait to send data to the backend
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub write_ms: Option<u64>,
+    /// How long an unused connection to the backend is kept open for reuse
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idle_ms: Option<u64>,
+    /// Deadline of the whole request, retries included
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_ms: Option<u64>,
+    /// Longest wait for data from the backend on an upgraded connection (e.g. WebSocket),
+    /// instead of the read and write timeouts. Upgraded connections never time out when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upgrade_idle_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "type", content = "name")]
+pub enum HashKey {
+    Header(String),
+    Cookie(String),
+    ClientIp,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
 #[serde(untagged)]
 pub enum Port {
     Number(u16),
+    /// Name of a Service port, resolved to its number by the controller
+    Name(String),
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::PingressConfiguration;
+    use crate::{
+        Backend, ClientAuthMode, HashKey, HttpPath, LoadBalancing, OcspStapling, PathRule,
+        PingressConfiguration, Port, Probe, Protocol, RetryOn, TlsVersion, DEFAULT_SUBJECT_HEADER,
+    };
 
     #[test]
     fn can_parse() {
@@ -83,4 +565,319 @@ mod tests {
 
         serde_json::from_str::<PingressConfiguration>(json).expect("Can parse");
     }
+
+    fn rule(json: &str) -> PathRule {
+        serde_json::from_str(json).expect("Can parse")
+    }
+
+    #[test]
+    fn parse_rule_without_host() {
+        let rule = rule(
+            r#"
+            {
+                "path": {
+                    "type": "Prefix",
+                    "path": "/healthz"
+                },
+                "backend": {
+                    "type": "Service",
+                    "name": "health",
+                    "namespace": "default",
+                    "port": "http"
+                }
+            }
+            "#,
+        );
+
+        assert_eq!(rule.host, None);
+        assert!(rule.http2);
+        assert!(rule.retry.is_none());
+        let Backend::Service { port, .. } = rule.backend else {
+            panic!("{:?}", rule.backend);
+        };
+        assert_eq!(port, Port::Name("http".to_string()));
+    }
+
+    #[test]
+    fn parse_route_policies() {
+        let rule = rule(
+            r#"
+            {
+                "host": "*.example.net",
+                "path": {
+                    "type": "Exact",
+                    "path": "/v1/users"
+                },
+                "retry": {
+                    "retry_on": ["ConnectFailure", "GatewayError"],
+                    "attempts": 2
+                },
+                "timeouts": {
+                    "connect_ms": 1000,
+                    "total_ms": 30000,
+                    "upgrade_idle_ms": 3600000
+                },
+                "http2": false,
+                "backend": {
+                    "type": "Service",
+                    "name": "backend-api",
+                    "namespace": "default",
+                    "port": 8080
+                }
+            }
+            "#,
+        );
+
+        let retry = rule.retry.unwrap();
+        assert_eq!(
+            retry.retry_on,
+            vec![RetryOn::ConnectFailure, RetryOn::GatewayError]
+        );
+        assert_eq!(retry.attempts, 2);
+        assert_eq!(rule.timeouts.connect_ms, Some(1_000));
+        assert_eq!(rule.timeouts.read_ms, None);
+        assert_eq!(rule.timeouts.total_ms, Some(30_000));
+        assert_eq!(rule.timeouts.upgrade_idle_ms, Some(3_600_000));
+        assert!(!rule.http2);
+    }
+
+    #[test]
+    fn parse_backend_settings() {
+        let rule = rule(
+            r#"
+            {
+                "host": "api.example.com",
+                "path": {
+                    "type": "Prefix",
+                    "path": "/"
+                },
+                "backend": {
+                    "type": "Service",
+                    "name": "backend-api",
+                    "namespace": "default",
+                    "port": 8080,
+                    "endpoints": [
+                        { "address": "10.0.0.1", "port": 8080 },
+                        { "address": "fd00::1", "port": 8080, "weight": 3 }
+                    ],
+                    "load_balancing": {
+                        "type": "ConsistentHash",
+                        "key": { "type": "Header", "name": "X-User" }
+                    },
+                    "health_check": {
+                        "type": "Http",
+                        "path": "/healthz",
+                        "interval_ms": 5000
+                    },
+                    "outlier_detection": {
+                        "consecutive_errors": 3
+                    },
+                    "circuit_breaker": {
+                        "max_requests": 100,
+                        "max_pending": 10
+                    },
+                    "protocol": "Https",
+                    "tls": {
+                        "sni": "backend-api.internal",
+                        "ca": "/etc/pingress/keys/ca.crt",
+                        "client_cert": {
+                            "key": "/etc/pingress/keys/client.key",
+                            "cert": "/etc/pingress/keys/client.crt"
+                        }
+                    }
+                }
+            }
+            "#,
+        );
+
+        let Backend::Service {
+            endpoints,
+            load_balancing,
+            health_check,
+            outlier_detection,
+            circuit_breaker,
+            protocol,
+            tls,
+            ..
+        } = rule.backend
+        else {
+            panic!("{:?}", rule.backend);
+        };
+        let weights: Vec<u16> = endpoints.iter().map(|e| e.weight).collect();
+        assert_eq!(weights, vec![1, 3]);
+        assert_eq!(
+            load_balancing,
+            LoadBalancing::ConsistentHash {
+                key: HashKey::Header("X-User".to_string())
+            }
+        );
+        let health_check = health_check.unwrap();
+        assert_eq!(
+            health_check.probe,
+            Probe::Http {
+                path: "/healthz".to_string()
+            }
+        );
+        assert_eq!(health_check.interval_ms, 5_000);
+        assert_eq!(outlier_detection.unwrap().consecutive_errors, 3);
+        let circuit_breaker = circuit_breaker.unwrap();
+        assert_eq!(circuit_breaker.max_requests, 100);
+        assert_eq!(circuit_breaker.max_pending, 10);
+        assert_eq!(protocol, Protocol::Https);
+        let tls = tls.unwrap();
+        assert_eq!(tls.sni.as_deref(), Some("backend-api.internal"));
+        assert_eq!(tls.ca.as_deref(), Some("/etc/pingress/keys/ca.crt"));
+        assert_eq!(
+            tls.client_cert.unwrap().cert,
+            "/etc/pingress/keys/client.crt"
+        );
+    }
+
+    #[test]
+    fn parse_external_name_and_response_backends() {
+        let external = rule(
+            r#"
+            {
+                "host": "external.example.com",
+                "path": {
+                    "type": "Prefix",
+                    "path": "/"
+                },
+                "backend": {
+                    "type": "ExternalName",
+                    "name": "external",
+                    "namespace": "default",
+                    "external_name": "api.example.org",
+                    "port": 443,
+                    "protocol": "Https"
+                }
+            }
+            "#,
+        );
+        let Backend::ExternalName {
+            external_name,
+            port,
+            protocol,
+            ..
+        } = external.backend
+        else {
+            panic!("{:?}", external.backend);
+        };
+        assert_eq!(external_name, "api.example.org");
+        assert_eq!(port, 443);
+        assert_eq!(protocol, Protocol::Https);
+
+        let redirect = rule(
+            r#"
+            {
+                "host": "old.example.com",
+                "path": {
+                    "type": "Prefix",
+                    "path": "/"
+                },
+                "backend": {
+                    "type": "Response",
+                    "status": 301,
+                    "location": "https://new.example.com/"
+                }
+            }
+            "#,
+        );
+        let Backend::Response {
+            status,
+            location,
+            content_type,
+            body,
+        } = redirect.backend
+        else {
+            panic!("{:?}", redirect.backend);
+        };
+        assert_eq!(status, 301);
+        assert_eq!(location.as_deref(), Some("https://new.example.com/"));
+        assert_eq!(content_type, None);
+        assert_eq!(body, "");
+    }
+
+    #[test]
+    fn parse_defaults() {
+        let json = r#"
+        {
+            "rules": [],
+            "default_backend": {
+                "type": "Service",
+                "name": "default-http-backend",
+                "namespace": "default",
+                "port": 80
+            },
+            "not_found_body": "<h1>Not Found</h1>",
+            "default_tls": {
+                "key": "/etc/pingress/keys/default.key",
+                "cert": "/etc/pingress/keys/default.crt"
+            }
+        }
+        "#;
+
+        let config: PingressConfiguration = serde_json::from_str(json).expect("Can parse");
+        assert!(matches!(
+            config.default_backend,
+            Some(Backend::Service { ref name, .. }) if name == "default-http-backend"
+        ));
+        assert_eq!(config.not_found_body.as_deref(), Some("<h1>Not Found</h1>"));
+        let tls = config.default_tls.unwrap();
+        assert_eq!(tls.cert, "/etc/pingress/keys/default.crt");
+        assert_eq!(tls.ocsp, None);
+        assert_eq!(tls.client_auth, None);
+    }
+
+    #[test]
+    fn parse_tls_policy() {
+        let rule = rule(
+            r#"
+            {
+                "host": "secure.example.com",
+                "tls": {
+                    "key": "/etc/pingress/keys/tls.key",
+                    "cert": "/etc/pingress/keys/tls.crt",
+                    "ocsp": {
+                        "type": "Fetch",
+                        "responder": "http://ocsp.example.com"
+                    },
+                    "min_version": "Tls12",
+                    "ciphers": "ECDHE+AESGCM",
+                    "curves": "X25519:P-256",
+                    "client_auth": {
+                        "ca": "/etc/pingress/keys/client-ca.crt",
+                        "mode": "Optional"
+                    }
+                },
+                "path": {
+                    "type": "Prefix",
+                    "path": "/"
+                },
+                "backend": {
+                    "type": "Service",
+                    "name": "secure",
+                    "namespace": "default",
+                    "port": 80
+                }
+            }
+            "#,
+        );
+
+        assert!(matches!(rule.path, HttpPath::Prefix(ref p) if p == "/"));
+        let tls = rule.tls.unwrap();
+        assert_eq!(
+            tls.ocsp,
+            Some(OcspStapling::Fetch {
+                responder: Some("http://ocsp.example.com".to_string())
+            })
+        );
+        assert_eq!(tls.min_version, Some(TlsVersion::Tls12));
+        assert_eq!(tls.ciphers.as_deref(), Some("ECDHE+AESGCM"));
+        assert_eq!(tls.curves.as_deref(), Some("X25519:P-256"));
+        let client_auth = tls.client_auth.unwrap();
+        assert_eq!(client_auth.ca, "/etc/pingress/keys/client-ca.crt");
+        assert_eq!(client_auth.mode, ClientAuthMode::Optional);
+        assert_eq!(client_auth.subject_header, DEFAULT_SUBJECT_HEADER);
+    }
 }
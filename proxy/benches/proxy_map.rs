@@ -0,0 +1,100 @@
+use arc_swap::ArcSwap;
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use pingress_config::{
+    Backend, HttpPath, LoadBalancing, PathRule, PingressConfiguration, Port, Protocol, Timeouts,
+};
+use pingress_proxy_server::ProxyMap;
+use std::fs::File;
+use std::sync::Arc;
+
+fn configuration() -> PingressConfiguration {
+    let mut rules = Vec::new();
+    for i in 0..50 {
+        rules.push(PathRule {
+            host: Some(format!("app{i}.example.com")),
+            tls: None,
+            path: HttpPath::Prefix("/".to_string()),
+            backend: Backend::Service {
+                name: format!("app{i}"),
+                namespace: "default".to_string(),
+                port: Port::Number(80),
+                endpoints: Vec::new(),
+                load_balancing: LoadBalancing::default(),
+                health_check: None,
+                outlier_detection: None,
+                circuit_breaker: None,
+                protocol: Protocol::default(),
+                tls: None,
+            },
+            retry: None,
+            timeouts: Timeouts::default(),
+            http2: true,
+        });
+        rules.push(PathRule {
+            host: Some(format!("*.app{i}.example.net")),
+            tls: None,
+            path: HttpPath::Prefix("/".to_string()),
+            backend: Backend::Service {
+                name: format!("wildcard{i}"),
+                namespace: "default".to_string(),
+                port: Port::Number(80),
+                endpoints: Vec::new(),
+                load_balancing: LoadBalancing::default(),
+                health_check: None,
+                outlier_detection: None,
+                circuit_breaker: None,
+                protocol: Protocol::default(),
+                tls: None,
+            },
+            retry: None,
+            timeouts: Timeouts::default(),
+            http2: true,
+        });
+    }
+    PingressConfiguration {
+        rules,
+        default_backend: None,
+        not_found_body: None,
+        default_tls: None,
+    }
+}
+
+fn requests(c: &mut Criterion) {
+    let config = configuration();
+    let config_file = std::env::temp_dir().join("pingress-bench-proxy.json");
+    serde_json::to_writer(File::create(&config_file).unwrap(), &config).unwrap();
+
+    let mut group = c.benchmark_group("requests");
+    group.throughput(Throughput::Elements(1));
+
+    // Previous behaviour: read and compile the configuration for every request
+    group.bench_function("rebuild_per_request", |b| {
+        b.iter(|| {
+            let config: PingressConfiguration =
+                serde_json::from_reader(File::open(&config_file).unwrap()).unwrap();
+            let proxy_map = ProxyMap::from(config);
+            black_box(
+                proxy_map
+                    .get_route("api.app49.example.net", "/v1/users")
+                    .and_then(|r| r.upstream().map(|u| u.select(b"").address)),
+            )
+        })
+    });
+
+    let shared = Arc::new(ArcSwap::from_pointee(ProxyMap::from(config)));
+    group.bench_function("shared_route_table", |b| {
+        b.iter(|| {
+            black_box(
+                shared
+                    .load()
+                    .get_route("api.app49.example.net", "/v1/users")
+                    .and_then(|r| r.upstream().map(|u| u.select(b"").address)),
+            )
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, requests);
+criterion_main!(benches);
@@ -0,0 +1,193 @@
+//! Requests per second through a running proxy, reported by criterion as elements per second.
+//!
+//! Concurrent keep-alive connections send requests through the plain text listener to a backend
+//! that answers them all with the same small response.
+
+use arc_swap::ArcSwap;
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use futures::future::join_all;
+use pingora::server::configuration::ServerConf;
+use pingress_config::{
+    Backend, Endpoint, HttpPath, LoadBalancing, PathRule, PingressConfiguration, Port, Protocol,
+    Timeouts,
+};
+use pingress_proxy_server::{create_http_proxies, Args, ProxyMap, TlsMap};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::Runtime;
+
+/// Keep-alive connections sending requests concurrently
+const CONNECTIONS: usize = 16;
+
+/// Requests sent one after another on each connection per iteration
+const REQUESTS: usize = 16;
+
+const REQUEST: &[u8] = b"GET / HTTP/1.1\r\nHost: app.example.com\r\n\r\n";
+
+const RESPONSE: &[u8] = b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok";
+
+fn configuration(backend: SocketAddr) -> PingressConfiguration {
+    PingressConfiguration {
+        rules: vec![PathRule {
+            host: Some("app.example.com".to_string()),
+            tls: None,
+            path: HttpPath::Prefix("/".to_string()),
+            backend: Backend::Service {
+                name: "app".to_string(),
+                namespace: "default".to_string(),
+                port: Port::Number(backend.port()),
+                endpoints: vec![Endpoint {
+                    address: backend.ip().to_string(),
+                    port: backend.port(),
+                    weight: 1,
+                }],
+                load_balancing: LoadBalancing::default(),
+                health_check: None,
+                outlier_detection: None,
+                circuit_breaker: None,
+                protocol: Protocol::default(),
+                tls: None,
+            },
+            retry: None,
+            timeouts: Timeouts::default(),
+            http2: true,
+        }],
+        default_backend: None,
+        not_found_body: None,
+        default_tls: None,
+    }
+}
+
+fn free_address() -> SocketAddr {
+    std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+/// Backend answering every request, which has no body, with [RESPONSE]
+async fn start_backend() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut buf = Vec::new();
+                while let Some(end) = read_head(&mut stream, &mut buf).await {
+                    buf.drain(..end);
+                    if stream.write_all(RESPONSE).await.is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+    });
+    address
+}
+
+/// Proxy routing `app.example.com` to the backend, returning the address of its plain text
+/// listener
+fn start_proxy(backend: SocketAddr) -> SocketAddr {
+    let config = configuration(backend);
+    let args = Args {
+        listen_http: free_address().to_string(),
+        listen_https: free_address().to_string(),
+        h2c: false,
+        config: String::new(),
+        watch: Vec::new(),
+    };
+
+    let services = create_http_proxies(
+        &Arc::new(ServerConf::default()),
+        &args,
+        Arc::new(ArcSwap::from_pointee(ProxyMap::from(config.clone()))),
+        Arc::new(ArcSwap::from_pointee(TlsMap::from(config))),
+    );
+    for mut service in services {
+        let (shutdown, watch) = tokio::sync::watch::channel(false);
+        tokio::spawn(async move {
+            let _shutdown = shutdown;
+            service.start_service(None, watch).await;
+        });
+    }
+    args.listen_http.parse().unwrap()
+}
+
+async fn connect(address: SocketAddr) -> TcpStream {
+    for _ in 0..50 {
+        if let Ok(stream) = TcpStream::connect(address).await {
+            return stream;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("{address} is not listening");
+}
+
+/// Read the head of a message into `buf`, returning its length. `None` once the peer is gone.
+async fn read_head(stream: &mut TcpStream, buf: &mut Vec<u8>) -> Option<usize> {
+    loop {
+        if let Some(i) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            return Some(i + 4);
+        }
+        read_more(stream, buf).await?;
+    }
+}
+
+async fn read_more(stream: &mut TcpStream, buf: &mut Vec<u8>) -> Option<()> {
+    let mut chunk = [0; 4096];
+    let n = stream.read(&mut chunk).await.ok().filter(|n| *n > 0)?;
+    buf.extend_from_slice(&chunk[..n]);
+    Some(())
+}
+
+/// Send a request and read its whole response
+async fn request(stream: &mut TcpStream, buf: &mut Vec<u8>) {
+    stream.write_all(REQUEST).await.unwrap();
+    let end = read_head(stream, buf).await.unwrap();
+    let head = String::from_utf8_lossy(&buf[..end]).to_ascii_lowercase();
+    assert!(head.starts_with("http/1.1 200"), "{head}");
+    let length: usize = head
+        .lines()
+        .find_map(|l| l.strip_prefix("content-length:"))
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or_default();
+    while buf.len() < end + length {
+        read_more(stream, buf).await.unwrap();
+    }
+    buf.drain(..end + length);
+}
+
+fn throughput(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let mut connections = runtime.block_on(async {
+        let proxy = start_proxy(start_backend().await);
+        let mut connections = Vec::new();
+        for _ in 0..CONNECTIONS {
+            connections.push((connect(proxy).await, Vec::new()));
+        }
+        connections
+    });
+
+    let mut group = c.benchmark_group("proxy");
+    group.throughput(Throughput::Elements((CONNECTIONS * REQUESTS) as u64));
+    group.bench_function("keep_alive_requests", |b| {
+        b.iter(|| {
+            runtime.block_on(async {
+                let clients = connections.iter_mut().map(|(stream, buf)| async move {
+                    for _ in 0..REQUESTS {
+                        request(stream, buf).await;
+                    }
+                });
+                join_all(clients).await;
+            })
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, throughput);
+criterion_main!(benches);
@@ -0,0 +1,105 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Caps the concurrent requests to a backend
+pub(crate) struct CircuitBreaker {
+    config: pingress_config::CircuitBreaker,
+    requests: Arc<AtomicUsize>,
+    pending: Arc<AtomicUsize>,
+}
+
+/// Admission of a request, counted until dropped
+pub(crate) struct Permit {
+    requests: Arc<AtomicUsize>,
+    /// Released once connected to the backend
+    pending: Option<Arc<AtomicUsize>>,
+}
+
+impl CircuitBreaker {
+    pub(crate) fn new(config: pingress_config::CircuitBreaker) -> Self {
+        Self {
+            config,
+            requests: Arc::new(AtomicUsize::new(0)),
+            pending: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Admit a request, or `None` when a limit is reached
+    pub(crate) fn try_acquire(&self) -> Option<Permit> {
+        if self.requests.fetch_add(1, Ordering::AcqRel) >= self.config.max_requests {
+            self.requests.fetch_sub(1, Ordering::AcqRel);
+            return None;
+        }
+        if self.pending.fetch_add(1, Ordering::AcqRel) >= self.config.max_pending {
+            self.pending.fetch_sub(1, Ordering::AcqRel);
+            self.requests.fetch_sub(1, Ordering::AcqRel);
+            return None;
+        }
+
+        Some(Permit {
+            requests: self.requests.clone(),
+            pending: Some(self.pending.clone()),
+        })
+    }
+
+    /// Count the requests admitted by the breaker of the same backend in a previous
+    /// configuration, which are still in flight
+    pub(crate) fn inherit(&mut self, previous: &CircuitBreaker) {
+        self.requests = previous.requests.clone();
+        self.pending = previous.pending.clone();
+    }
+
+    pub(crate) fn requests(&self) -> usize {
+        self.requests.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn pending(&self) -> usize {
+        self.pending.load(Ordering::Relaxed)
+    }
+}
+
+impl Permit {
+    pub(crate) fn connected(&mut self) {
+        if let Some(pending) = self.pending.take() {
+            pending.fetch_sub(1, Ordering::AcqRel);
+        }
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.connected();
+        self.requests.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::circuit_breaker::CircuitBreaker;
+
+    #[test]
+    fn limits() {
+        let breaker = CircuitBreaker::new(pingress_config::CircuitBreaker {
+            max_requests: 2,
+            max_pending: 1,
+        });
+
+        let mut first = breaker.try_acquire().unwrap();
+        // Waiting for a connection
+        assert!(breaker.try_acquire().is_none());
+
+        first.connected();
+        let second = breaker.try_acquire().unwrap();
+        assert_eq!((breaker.requests(), breaker.pending()), (2, 1));
+        drop(second);
+
+        // Too many requests
+        let mut second = breaker.try_acquire().unwrap();
+        second.connected();
+        assert!(breaker.try_acquire().is_none());
+
+        drop(first);
+        assert!(breaker.try_acquire().is_some());
+        assert_eq!((breaker.requests(), breaker.pending()), (1, 0));
+    }
+}
@@ -0,0 +1,47 @@
+use crate::error::ConfigError;
+use crate::proxy_map::ProxyMap;
+use crate::tls::TlsMap;
+use pingress_config::PingressConfiguration;
+use std::fs::File;
+
+/// Route table and certificates built from one configuration file
+pub(crate) struct LoadedConfiguration {
+    pub(crate) proxy_map: ProxyMap,
+    pub(crate) tls: TlsMap,
+}
+
+impl LoadedConfiguration {
+    /// No route and no certificate, until a valid configuration is loaded
+    pub(crate) fn empty() -> Self {
+        let config = PingressConfiguration {
+            rules: Vec::new(),
+            default_backend: None,
+            not_found_body: None,
+            default_tls: None,
+        };
+        Self {
+            proxy_map: ProxyMap::from(config.clone()),
+            tls: TlsMap::from(config),
+        }
+    }
+}
+
+/// Read the configuration file.
+///
+/// Nothing is returned unless the file can be read and parsed. Invalid rules and certificates
+/// are skipped one by one instead, so that one bad Ingress or Secret does not take down every
+/// host. The routes take over the state of the `previous` route table, if any.
+pub(crate) fn load_configuration(
+    path: &str,
+    previous: Option<&ProxyMap>,
+) -> Result<LoadedConfiguration, ConfigError> {
+    let config: PingressConfiguration = {
+        let file = File::open(path).map_err(|e| ConfigError::Io(path.to_string(), e))?;
+        serde_json::from_reader(file).map_err(ConfigError::Parse)?
+    };
+
+    Ok(LoadedConfiguration {
+        proxy_map: ProxyMap::new(config.clone(), previous),
+        tls: TlsMap::from(config),
+    })
+}
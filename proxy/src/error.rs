@@ -0,0 +1,46 @@
+use std::fmt::{Display, Formatter};
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(String, std::io::Error),
+    Parse(serde_json::Error),
+    InvalidHost(String, String),
+    InvalidTls(String, String),
+    InvalidEndpoint(String, String),
+    InvalidHealthCheck(String, String),
+    UnresolvedPort(String),
+    UnresolvedResource(String),
+    NotUpstream(String),
+    InvalidResponse(u16, String),
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "Cannot read '{path}': {e}"),
+            ConfigError::Parse(e) => write!(f, "Cannot parse configuration: {e}"),
+            ConfigError::InvalidHost(host, e) => write!(f, "Invalid host '{host}': {e}"),
+            ConfigError::InvalidTls(host, e) => write!(f, "Invalid TLS for '{host}': {e}"),
+            ConfigError::InvalidEndpoint(backend, e) => {
+                write!(f, "Invalid endpoint of '{backend}': {e}")
+            }
+            ConfigError::InvalidHealthCheck(backend, e) => {
+                write!(f, "Invalid health check of '{backend}': {e}")
+            }
+            ConfigError::UnresolvedPort(backend) => {
+                write!(f, "Named port of '{backend}' is not resolved")
+            }
+            ConfigError::UnresolvedResource(backend) => {
+                write!(f, "Resource backend '{backend}' is not resolved")
+            }
+            ConfigError::NotUpstream(backend) => {
+                write!(f, "'{backend}' is not proxied to an upstream")
+            }
+            ConfigError::InvalidResponse(status, e) => {
+                write!(f, "Invalid response with status {status}: {e}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
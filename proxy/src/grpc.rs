@@ -0,0 +1,71 @@
+use pingora::http::{RequestHeader, ResponseHeader};
+
+/// Whether the request is a gRPC call
+pub(crate) fn is_grpc(req: &RequestHeader) -> bool {
+    req.headers
+        .get("Content-Type")
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| {
+            v == "application/grpc"
+                || v.starts_with("application/grpc+")
+                || v.starts_with("application/grpc;")
+        })
+}
+
+/// Trailers-only gRPC response for an error of the proxy with HTTP status `status`
+pub(crate) fn error_response(status: u16) -> pingora::Result<ResponseHeader> {
+    let code = grpc_status(status);
+    let mut resp = ResponseHeader::build(200, Some(3))?;
+    resp.insert_header("Content-Type", "application/grpc")?;
+    resp.insert_header("grpc-status", code.to_string())?;
+    resp.insert_header("grpc-message", format!("pingress: HTTP status {status}"))?;
+    Ok(resp)
+}
+
+/// gRPC status code of an HTTP status, as mapped by the gRPC specification
+fn grpc_status(status: u16) -> u32 {
+    match status {
+        // INTERNAL
+        400 => 13,
+        // UNAUTHENTICATED
+        401 => 16,
+        // PERMISSION_DENIED
+        403 => 7,
+        // UNIMPLEMENTED
+        404 => 12,
+        // UNAVAILABLE
+        429 | 502 | 503 | 504 => 14,
+        // UNKNOWN
+        _ => 2,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::grpc::{error_response, is_grpc};
+    use pingora::http::RequestHeader;
+
+    #[test]
+    fn grpc_request() {
+        let request = |content_type: &str| {
+            let mut req = RequestHeader::build("POST", b"/pkg.Service/Method", None).unwrap();
+            req.insert_header("Content-Type", content_type).unwrap();
+            req
+        };
+
+        assert!(is_grpc(&request("application/grpc")));
+        assert!(is_grpc(&request("application/grpc+proto")));
+        assert!(!is_grpc(&request("application/grpc-web")));
+        assert!(!is_grpc(&request("application/json")));
+    }
+
+    #[test]
+    fn grpc_error() {
+        let resp = error_response(503).unwrap();
+        assert_eq!(resp.status.as_u16(), 200);
+        assert_eq!(resp.headers.get("grpc-status").unwrap(), "14");
+
+        let resp = error_response(404).unwrap();
+        assert_eq!(resp.headers.get("grpc-status").unwrap(), "12");
+    }
+}
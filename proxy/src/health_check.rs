@@ -0,0 +1,93 @@
+use crate::metrics::{
+    UPSTREAM_ENDPOINT_EJECTED, UPSTREAM_ENDPOINT_HEALTHY, UPSTREAM_PENDING_REQUESTS,
+    UPSTREAM_REQUESTS,
+};
+use crate::proxy_map::ProxyMap;
+use crate::upstream::Upstream;
+use arc_swap::ArcSwap;
+use async_trait::async_trait;
+use futures::future::join_all;
+use pingora::server::ShutdownWatch;
+use pingora::services::background::BackgroundService;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Resolution of the health check intervals
+const TICK: Duration = Duration::from_millis(100);
+
+/// Runs the active health checks of the current configuration and exports the state of
+/// the upstreams.
+///
+/// Upstreams are rebuilt on every reload, so a single service follows the current
+/// [ProxyMap] instead of registering each load balancer as a background service.
+pub(crate) struct HealthCheckService {
+    proxy_map: Arc<ArcSwap<ProxyMap>>,
+}
+
+impl HealthCheckService {
+    pub(crate) fn new(proxy_map: Arc<ArcSwap<ProxyMap>>) -> Self {
+        Self { proxy_map }
+    }
+}
+
+#[async_trait]
+impl BackgroundService for HealthCheckService {
+    async fn start(&self, mut shutdown: ShutdownWatch) {
+        let mut interval = tokio::time::interval(TICK);
+        let mut current: Option<Arc<ProxyMap>> = None;
+
+        loop {
+            tokio::select! {
+                _ = shutdown.changed() => return,
+                _ = interval.tick() => {}
+            }
+
+            let proxy_map = self.proxy_map.load_full();
+            if !current.as_ref().is_some_and(|c| Arc::ptr_eq(c, &proxy_map)) {
+                // Drop the series of removed backends, every check of a new map is due
+                UPSTREAM_ENDPOINT_HEALTHY.reset();
+                UPSTREAM_ENDPOINT_EJECTED.reset();
+                UPSTREAM_REQUESTS.reset();
+                UPSTREAM_PENDING_REQUESTS.reset();
+                current = Some(proxy_map.clone());
+            }
+
+            let now = Instant::now();
+            let due = proxy_map
+                .upstreams()
+                .filter(|u| u.health_check_due(now))
+                .map(|u| u.run_health_check());
+            join_all(due).await;
+
+            for upstream in proxy_map.upstreams() {
+                export(upstream);
+            }
+        }
+    }
+}
+
+fn export(upstream: &Upstream) {
+    let backend = upstream.name();
+    for status in upstream.endpoint_status() {
+        let labels = [backend, status.address.as_str()];
+        if let Some(healthy) = status.healthy {
+            UPSTREAM_ENDPOINT_HEALTHY
+                .with_label_values(&labels)
+                .set(healthy as i64);
+        }
+        if let Some(ejected) = status.ejected {
+            UPSTREAM_ENDPOINT_EJECTED
+                .with_label_values(&labels)
+                .set(ejected as i64);
+        }
+    }
+
+    if let Some(circuit_breaker) = upstream.circuit_breaker() {
+        UPSTREAM_REQUESTS
+            .with_label_values(&[backend])
+            .set(circuit_breaker.requests() as i64);
+        UPSTREAM_PENDING_REQUESTS
+            .with_label_values(&[backend])
+            .set(circuit_breaker.pending() as i64);
+    }
+}
@@ -0,0 +1,103 @@
+/// Host of a rule, as allowed by the Ingress specification
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum HostPattern<'a> {
+    /// Precise host name (e.g. `foo.example.com`)
+    Exact(&'a str),
+    /// Wildcard host (e.g. `*.example.com`), holding the part after `*.`
+    Wildcard(&'a str),
+}
+
+/// Validate a host of a rule.
+///
+/// Hosts are RFC 1123 subdomains. A wildcard is allowed only as the whole first label.
+pub(crate) fn parse_host_pattern(host: &str) -> Result<HostPattern<'_>, String> {
+    let (pattern, name) = match host.strip_prefix("*.") {
+        Some(suffix) => (HostPattern::Wildcard(suffix), suffix),
+        None => (HostPattern::Exact(host), host),
+    };
+
+    if name.is_empty() || name.len() > 253 {
+        return Err("must be 1 to 253 characters".to_string());
+    }
+    for label in name.split('.') {
+        if !is_dns_label(label) {
+            return Err(format!("invalid label '{label}'"));
+        }
+    }
+
+    Ok(pattern)
+}
+
+fn is_dns_label(label: &str) -> bool {
+    !label.is_empty()
+        && label.len() <= 63
+        && label
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+        && !label.starts_with('-')
+        && !label.ends_with('-')
+}
+
+/// Normalize a requested host (`Host` header or SNI): drop the port and a trailing dot,
+/// and lowercase it.
+pub(crate) fn normalize_host(host: &str) -> String {
+    let host = match host.strip_prefix('[').and_then(|h| h.find(']')) {
+        // IPv6 literal
+        Some(end) => &host[..end + 2],
+        None => match host.rsplit_once(':') {
+            Some((h, port)) if !h.contains(':') && port.bytes().all(|b| b.is_ascii_digit()) => h,
+            _ => host,
+        },
+    };
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// Suffix that a wildcard host must have to cover `host`.
+///
+/// A wildcard covers exactly one label, so `*.example.com` matches `foo.example.com`
+/// but neither `example.com` nor `bar.foo.example.com`.
+pub(crate) fn wildcard_suffix(host: &str) -> Option<&str> {
+    let (label, suffix) = host.split_once('.')?;
+    (!label.is_empty() && !suffix.is_empty()).then_some(suffix)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::host::{normalize_host, parse_host_pattern, wildcard_suffix, HostPattern};
+
+    #[test]
+    fn host_pattern() {
+        let cases = [
+            (
+                "foo.example.com",
+                Some(HostPattern::Exact("foo.example.com")),
+            ),
+            ("*.example.com", Some(HostPattern::Wildcard("example.com"))),
+            ("localhost", Some(HostPattern::Exact("localhost"))),
+            ("*", None),
+            ("*.", None),
+            ("foo.*.example.com", None),
+            ("*foo.example.com", None),
+            ("*.*.example.com", None),
+            ("Foo.example.com", None),
+            ("-foo.example.com", None),
+            ("foo..example.com", None),
+            ("foo.example.com.", None),
+            ("", None),
+        ];
+
+        for (host, expected) in cases {
+            assert_eq!(parse_host_pattern(host).ok(), expected, "{host}");
+        }
+    }
+
+    #[test]
+    fn requested_host() {
+        assert_eq!(normalize_host("Foo.Example.com:8080"), "foo.example.com");
+        assert_eq!(normalize_host("foo.example.com."), "foo.example.com");
+        assert_eq!(normalize_host("[::1]:443"), "[::1]");
+
+        assert_eq!(wildcard_suffix("foo.example.com"), Some("example.com"));
+        assert_eq!(wildcard_suffix("localhost"), None);
+    }
+}
@@ -1,22 +1,74 @@
-use crate::proxy_map::ProxyMap;
+use crate::circuit_breaker::Permit;
+use crate::grpc;
+use crate::metrics::UPSTREAM_OVERFLOW;
+use crate::proxy_map::{ProxyMap, Route, Target};
+use crate::retry::{InFlight, Retry};
+use crate::static_response::StaticResponse;
+use crate::tls_policy::ClientSubjects;
+use crate::upstream::{resolve, Selected};
+use arc_swap::ArcSwap;
 use async_trait::async_trait;
+use bytes::Bytes;
+use pingora::http::{RequestHeader, ResponseHeader};
 use pingora::prelude::{HttpPeer, ProxyHttp};
-use pingora::ErrorType;
-use pingress_config::PingressConfiguration;
-use std::fs::File;
+use pingora::protocols::{Digest, ALPN};
+use pingora::proxy::Session;
+use pingora::{ErrorSource, ErrorType};
+use pingress_config::{ClientAuth, ClientAuthMode, HashKey, LoadBalancing, RetryOn, Timeouts};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 pub(crate) struct PingressHttpProxy {
-    config_file: String,
+    proxy_map: Arc<ArcSwap<ProxyMap>>,
+    /// Subjects of the client certificates verified by the TLS handshakes
+    subjects: Arc<ClientSubjects>,
 }
 
 impl PingressHttpProxy {
-    pub(crate) fn new(config_file: String) -> Self {
-        Self { config_file }
+    pub(crate) fn new(proxy_map: Arc<ArcSwap<ProxyMap>>, subjects: Arc<ClientSubjects>) -> Self {
+        Self {
+            proxy_map,
+            subjects,
+        }
+    }
+
+    /// Subject of the client certificate of the session, if verified for the route
+    fn client_subject(&self, session: &Session, client_auth: &ClientAuth) -> Option<String> {
+        let ssl = session.digest()?.ssl_digest.as_ref()?;
+        if ssl.cert_digest.is_empty() {
+            return None;
+        }
+        self.subjects
+            .get(client_auth.ca.as_str(), ssl.cert_digest.as_slice())
     }
 }
 
+#[derive(Default)]
 pub struct Context {
-    proxy_map: ProxyMap,
+    /// Route of the request, kept across retries
+    route: Option<Arc<Route>>,
+    /// Endpoint of the request, counted as active while the request lasts
+    selected: Option<Selected>,
+    /// Admission by the circuit breaker of the backend
+    permit: Option<Permit>,
+    /// Deadline of the whole request, from the total timeout of the route
+    deadline: Option<Instant>,
+    /// Attempts to proxy the request
+    attempts: usize,
+    /// Endpoints of the previous attempts
+    tried: Vec<String>,
+    /// Counts the request in the retry budget of the route
+    request: Option<InFlight>,
+    /// Counts the retried request against the retry budget of the route
+    retry: Option<InFlight>,
+    /// The response header is sent downstream, so the request cannot be retried anymore
+    response_started: bool,
+    /// The response is discarded for a retry
+    retrying: bool,
+    /// The connection is upgraded (e.g. WebSocket), so the total timeout no longer applies
+    upgraded: bool,
+    /// Subject of the verified client certificate, forwarded to the backend
+    client_subject: Option<String>,
 }
 
 #[async_trait]
@@ -24,38 +76,1204 @@ impl ProxyHttp for PingressHttpProxy {
     type CTX = Context;
 
     fn new_ctx(&self) -> Self::CTX {
-        let config: PingressConfiguration = {
-            let file = File::open(self.config_file.as_str()).unwrap();
-            serde_json::from_reader(file).unwrap()
+        Context::default()
+    }
+
+    async fn request_filter(
+        &self,
+        session: &mut Session,
+        ctx: &mut Self::CTX,
+    ) -> pingora::Result<bool> {
+        let host = match request_host(session) {
+            Some(a) => a,
+            None => return pingora::Error::err(ErrorType::InvalidHTTPHeader),
         };
-        Context {
-            proxy_map: ProxyMap::from(config),
+        let path = session.req_header().uri.path();
+
+        let proxy_map = self.proxy_map.load();
+        let route = match proxy_map.get_route(host, path) {
+            Some(route) => route,
+            None => {
+                respond_not_found(session, proxy_map.not_found()).await?;
+                return Ok(true);
+            }
+        };
+        if let Some(client_auth) = route.client_auth.as_ref() {
+            ctx.client_subject = self.client_subject(session, client_auth);
+            if ctx.client_subject.is_none() && client_auth.mode == ClientAuthMode::Required {
+                session.respond_error(403).await?;
+                return Ok(true);
+            }
+        }
+        if let Target::Response(response) = &route.target {
+            response.respond(session).await?;
+            return Ok(true);
         }
+        ctx.request = route.retry.as_ref().map(Retry::start_request);
+        ctx.deadline = route
+            .timeouts
+            .total_ms
+            .map(|ms| Instant::now() + Duration::from_millis(ms));
+        ctx.route = Some(route);
+        Ok(false)
     }
 
     async fn upstream_peer(
         &self,
-        session: &mut pingora::proxy::Session,
+        session: &mut Session,
         ctx: &mut Self::CTX,
     ) -> pingora::Result<Box<HttpPeer>> {
-        let host = match session
-            .req_header()
-            .headers
-            .get("Host")
-            .and_then(|h| h.to_str().ok())
-        {
+        let host = match request_host(session) {
             Some(a) => a,
             None => return pingora::Error::err(ErrorType::InvalidHTTPHeader),
         };
-        let path = session.req_header().uri.path();
+        let route = match &ctx.route {
+            Some(route) => route.clone(),
+            None => return pingora::Error::err(ErrorType::ConnectNoRoute),
+        };
+        let upstream = match route.upstream() {
+            Some(upstream) => upstream,
+            None => return pingora::Error::err(ErrorType::ConnectNoRoute),
+        };
+
+        if ctx.attempts > 0 {
+            if let Some(retry) = &route.retry {
+                tokio::time::sleep(retry.backoff(ctx.attempts)).await;
+            }
+        }
+        ctx.attempts += 1;
+        check_deadline(ctx)?;
+        let remaining = ctx
+            .deadline
+            .map(|d| d.saturating_duration_since(Instant::now()));
+
+        ctx.permit = None;
+        if let Some(circuit_breaker) = upstream.circuit_breaker() {
+            match circuit_breaker.try_acquire() {
+                Some(permit) => ctx.permit = Some(permit),
+                None => {
+                    UPSTREAM_OVERFLOW
+                        .with_label_values(&[upstream.name()])
+                        .inc();
+                    return pingora::Error::e_explain(
+                        ErrorType::HTTPStatus(503),
+                        "circuit breaker is open",
+                    );
+                }
+            }
+        }
+
+        let key = match upstream.load_balancing() {
+            LoadBalancing::ConsistentHash { key } => hash_key(session, key),
+            _ => Vec::new(),
+        };
+        let different_endpoint = route
+            .retry
+            .as_ref()
+            .is_some_and(|r| r.policy().different_endpoint);
+        let selected = if different_endpoint {
+            upstream.select_excluding(key.as_slice(), &ctx.tried)
+        } else {
+            upstream.select(key.as_slice())
+        };
+        ctx.tried.push(selected.address.clone());
+        let address = resolve(selected.address.as_str()).await?;
+        let mut peer = upstream.peer(address, host);
+        let upgrade = session.is_upgrade_req();
+        if upgrade {
+            // HTTP/2 has no upgrade mechanism
+            peer.options.alpn = ALPN::H1;
+        }
+        apply_timeouts(&mut peer, &route.timeouts, remaining, upgrade);
+        ctx.selected = Some(selected);
+
+        Ok(Box::new(peer))
+    }
+
+    async fn upstream_request_filter(
+        &self,
+        _session: &mut Session,
+        upstream_request: &mut RequestHeader,
+        ctx: &mut Self::CTX,
+    ) -> pingora::Result<()> {
+        // External servers only know their own name
+        let external_name = ctx
+            .route
+            .as_ref()
+            .and_then(|r| r.upstream())
+            .and_then(|u| u.external_name());
+        if let Some(external_name) = external_name {
+            upstream_request.insert_header("Host", external_name)?;
+        }
+        // Clients cannot forge the subject of their certificate
+        if let Some(client_auth) = ctx.route.as_ref().and_then(|r| r.client_auth.as_ref()) {
+            upstream_request.remove_header(client_auth.subject_header.as_str());
+            if let Some(subject) = ctx.client_subject.clone() {
+                upstream_request.insert_header(client_auth.subject_header.clone(), subject)?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn connected_to_upstream(
+        &self,
+        _session: &mut Session,
+        _reused: bool,
+        _peer: &HttpPeer,
+        _fd: std::os::unix::io::RawFd,
+        _digest: Option<&Digest>,
+        ctx: &mut Self::CTX,
+    ) -> pingora::Result<()> {
+        if let Some(permit) = ctx.permit.as_mut() {
+            permit.connected();
+        }
+        Ok(())
+    }
+
+    fn fail_to_connect(
+        &self,
+        session: &mut Session,
+        _peer: &HttpPeer,
+        ctx: &mut Self::CTX,
+        mut e: Box<pingora::Error>,
+    ) -> Box<pingora::Error> {
+        if let Some(selected) = &ctx.selected {
+            selected.observe(false);
+        }
+        if ctx.route.as_ref().is_some_and(|r| r.retry.is_some()) {
+            e.set_retry(retry(session, ctx, RetryOn::ConnectFailure));
+        }
+        e
+    }
+
+    fn error_while_proxy(
+        &self,
+        peer: &HttpPeer,
+        session: &mut Session,
+        e: Box<pingora::Error>,
+        ctx: &mut Self::CTX,
+        client_reused: bool,
+    ) -> Box<pingora::Error> {
+        let mut e = e.more_context(format!("Peer: {peer}"));
+        // Reused connections may have been closed by the upstream in the meantime
+        e.retry
+            .decide_reuse(client_reused && !session.as_ref().retry_buffer_truncated());
+
+        let reset = !ctx.response_started && e.esource() == &ErrorSource::Upstream;
+        if std::mem::take(&mut ctx.retrying) || (reset && retry(session, ctx, RetryOn::Reset)) {
+            e.set_retry(true);
+        }
+        e
+    }
+
+    fn upstream_response_filter(
+        &self,
+        _session: &mut Session,
+        upstream_response: &mut ResponseHeader,
+        ctx: &mut Self::CTX,
+    ) {
+        if let Some(selected) = &ctx.selected {
+            selected.observe(!upstream_response.status.is_server_error());
+        }
+    }
+
+    async fn response_filter(
+        &self,
+        session: &mut Session,
+        upstream_response: &mut ResponseHeader,
+        ctx: &mut Self::CTX,
+    ) -> pingora::Result<()> {
+        let status = upstream_response.status.as_u16();
+        if matches!(status, 502..=504)
+            && !ctx.response_started
+            && retry(session, ctx, RetryOn::GatewayError)
+        {
+            // Fail the attempt before anything is sent downstream
+            ctx.retrying = true;
+            return pingora::Error::e_explain(
+                ErrorType::HTTPStatus(status),
+                "retry on gateway error",
+            );
+        }
+
+        ctx.response_started = true;
+        ctx.upgraded = status == 101;
+        Ok(())
+    }
+
+    async fn fail_to_proxy(
+        &self,
+        session: &mut Session,
+        e: &pingora::Error,
+        _ctx: &mut Self::CTX,
+    ) -> u16 {
+        let code = error_status(e);
+        if code == 0 {
+            return code;
+        }
+
+        if grpc::is_grpc(session.req_header()) {
+            if let Ok(resp) = grpc::error_response(code) {
+                // The status of the call is in the headers, so nothing follows them
+                let _ = session.write_response_header(Box::new(resp), true).await;
+                return 200;
+            }
+        }
+
+        session.as_mut().respond_error(code).await;
+        code
+    }
+
+    async fn request_body_filter(
+        &self,
+        _session: &mut Session,
+        _body: &mut Option<Bytes>,
+        _end_of_stream: bool,
+        ctx: &mut Self::CTX,
+    ) -> pingora::Result<()> {
+        check_deadline(ctx)
+    }
+
+    fn response_body_filter(
+        &self,
+        _session: &mut Session,
+        _body: &mut Option<Bytes>,
+        _end_of_stream: bool,
+        ctx: &mut Self::CTX,
+    ) -> pingora::Result<Option<Duration>> {
+        check_deadline(ctx)?;
+        Ok(None)
+    }
+}
+
+/// Host of the request, from the `Host` header or the authority of HTTP/2 requests
+fn request_host(session: &Session) -> Option<&str> {
+    let req = session.req_header();
+    req.headers
+        .get("Host")
+        .and_then(|h| h.to_str().ok())
+        .or_else(|| req.uri.authority().map(|a| a.as_str()))
+}
+
+/// Responds 404 to a request matching no route, with the custom response if any
+async fn respond_not_found(
+    session: &mut Session,
+    response: Option<&StaticResponse>,
+) -> pingora::Result<()> {
+    if grpc::is_grpc(session.req_header()) {
+        let resp = grpc::error_response(404)?;
+        return session.write_response_header(Box::new(resp), true).await;
+    }
+    match response {
+        Some(response) => response.respond(session).await,
+        None => session.respond_error(404).await,
+    }
+}
+
+/// HTTP status of the response to an error, `0` when the downstream connection is already dead
+fn error_status(e: &pingora::Error) -> u16 {
+    match e.etype() {
+        ErrorType::HTTPStatus(code) => *code,
+        _ => match e.esource() {
+            ErrorSource::Upstream => 502,
+            ErrorSource::Downstream => match e.etype() {
+                ErrorType::WriteError | ErrorType::ReadError | ErrorType::ConnectionClosed => 0,
+                _ => 400,
+            },
+            ErrorSource::Internal | ErrorSource::Unset => 500,
+        },
+    }
+}
+
+/// Set the timeouts of the route on the peer.
+///
+/// The connect, read and write timeouts never exceed the time `remaining` before the deadline,
+/// which is also checked on every chunk of the bodies.
+/// The read and write timeouts of an `upgrade` request are the upgrade idle timeout instead,
+/// as the connection outlives the deadline once upgraded.
+fn apply_timeouts(
+    peer: &mut HttpPeer,
+    timeouts: &Timeouts,
+    remaining: Option<Duration>,
+    upgrade: bool,
+) {
+    let shortest = |ms: Option<u64>| match (ms.map(Duration::from_millis), remaining) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    };
+
+    peer.options.connection_timeout = shortest(timeouts.connect_ms);
+    if upgrade {
+        peer.options.read_timeout = timeouts.upgrade_idle_ms.map(Duration::from_millis);
+        peer.options.write_timeout = timeouts.upgrade_idle_ms.map(Duration::from_millis);
+    } else {
+        peer.options.read_timeout = shortest(timeouts.read_ms);
+        peer.options.write_timeout = shortest(timeouts.write_ms);
+    }
+    peer.options.idle_timeout = timeouts.idle_ms.map(Duration::from_millis);
+}
+
+fn check_deadline(ctx: &Context) -> pingora::Result<()> {
+    if !ctx.upgraded && ctx.deadline.is_some_and(|d| d <= Instant::now()) {
+        return pingora::Error::e_explain(ErrorType::HTTPStatus(504), "request timeout");
+    }
+    Ok(())
+}
+
+/// Whether a failed attempt is retried, as allowed by the retry policy and budget of the route
+fn retry(session: &Session, ctx: &mut Context, condition: RetryOn) -> bool {
+    let Some(retry) = ctx.route.as_ref().and_then(|r| r.retry.as_ref()) else {
+        return false;
+    };
+    // The request body must be sent again
+    if session.as_ref().retry_buffer_truncated() {
+        return false;
+    }
+
+    match retry.try_retry(condition, &session.req_header().method, ctx.attempts) {
+        Some(in_flight) => {
+            ctx.retry = Some(in_flight);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Key for consistent hashing. Falls back to the client address when the request has no key.
+fn hash_key(session: &Session, key: &HashKey) -> Vec<u8> {
+    let value = match key {
+        HashKey::Header(name) => session
+            .req_header()
+            .headers
+            .get(name.as_str())
+            .map(|v| v.as_bytes().to_vec()),
+        HashKey::Cookie(name) => session
+            .req_header()
+            .headers
+            .get_all("Cookie")
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(';'))
+            .filter_map(|c| c.trim().split_once('='))
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_bytes().to_vec()),
+        HashKey::ClientIp => None,
+    };
+
+    value.unwrap_or_else(|| {
+        session
+            .client_addr()
+            .and_then(|a| a.as_inet())
+            .map(|a| a.ip().to_string().into_bytes())
+            .unwrap_or_default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http_proxy::apply_timeouts;
+    use crate::ocsp::tests::{fixture, fixture_tls};
+    use crate::ocsp::OcspService;
+    use crate::proxy_map::ProxyMap;
+    use crate::server::{create_http_proxies, Args};
+    use crate::tls::tests::{ca_certificate, certificate, ec_key, self_signed, write_tls};
+    use crate::tls::TlsMap;
+    use arc_swap::ArcSwap;
+    use futures::{SinkExt, StreamExt};
+    use openssl::pkey::{PKey, Private};
+    use openssl::ssl::{
+        SslConnector, SslConnectorBuilder, SslMethod, SslSession, SslVerifyMode, SslVersion,
+        StatusType,
+    };
+    use openssl::x509::X509;
+    use pingora::prelude::HttpPeer;
+    use pingora::server::configuration::ServerConf;
+    use pingora::services::background::background_service;
+    use pingora::tls::tokio_ssl::SslStream;
+    use pingress_config::{
+        Backend, ClientAuth, ClientAuthMode, Endpoint, HttpPath, LoadBalancing, OcspStapling,
+        PathRule, PingressConfiguration, Port, Protocol, RetryOn, RetryPolicy, Timeouts, Tls,
+        TlsVersion, DEFAULT_SUBJECT_HEADER,
+    };
+    use std::net::SocketAddr;
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_tungstenite::tungstenite::Message;
+    use tokio_tungstenite::WebSocketStream;
+    use tonic::transport::{Channel, Server};
+    use tonic::Code;
+    use tonic_health::pb::health_check_response::ServingStatus;
+    use tonic_health::pb::health_client::HealthClient;
+    use tonic_health::pb::HealthCheckRequest;
+
+    #[test]
+    fn timeouts_within_deadline() {
+        let timeouts = Timeouts {
+            connect_ms: Some(1_000),
+            read_ms: Some(60_000),
+            write_ms: None,
+            idle_ms: Some(90_000),
+            total_ms: Some(30_000),
+            upgrade_idle_ms: Some(3_600_000),
+        };
+        let mut peer = HttpPeer::new("127.0.0.1:8080", false, String::new());
+        apply_timeouts(&mut peer, &timeouts, Some(Duration::from_secs(10)), false);
+
+        let secs = |s| Some(Duration::from_secs(s));
+        assert_eq!(peer.options.connection_timeout, secs(1));
+        assert_eq!(peer.options.read_timeout, secs(10));
+        assert_eq!(peer.options.write_timeout, secs(10));
+        assert_eq!(peer.options.idle_timeout, secs(90));
+
+        let mut peer = HttpPeer::new("127.0.0.1:8080", false, String::new());
+        apply_timeouts(&mut peer, &timeouts, Some(Duration::from_secs(10)), true);
+        assert_eq!(peer.options.connection_timeout, secs(1));
+        assert_eq!(peer.options.read_timeout, secs(3_600));
+        assert_eq!(peer.options.write_timeout, secs(3_600));
+
+        let mut peer = HttpPeer::new("127.0.0.1:8080", false, String::new());
+        apply_timeouts(&mut peer, &Timeouts::default(), None, false);
+        assert_eq!(peer.options.read_timeout, None);
+    }
+
+    fn free_address() -> SocketAddr {
+        std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+    }
+
+    fn rule(host: &str, backend: SocketAddr, protocol: Protocol) -> PathRule {
+        PathRule {
+            host: Some(host.to_string()),
+            tls: None,
+            path: HttpPath::Prefix("/".to_string()),
+            backend: Backend::Service {
+                name: "backend".to_string(),
+                namespace: "default".to_string(),
+                port: Port::Number(80),
+                endpoints: vec![Endpoint {
+                    address: backend.ip().to_string(),
+                    port: backend.port(),
+                    weight: 1,
+                }],
+                load_balancing: LoadBalancing::default(),
+                health_check: None,
+                outlier_detection: None,
+                circuit_breaker: None,
+                protocol,
+                tls: None,
+            },
+            retry: None,
+            timeouts: Timeouts::default(),
+            http2: true,
+        }
+    }
+
+    /// Addresses of the plain text and TLS listeners of a proxy
+    struct Listeners {
+        http: SocketAddr,
+        https: SocketAddr,
+    }
+
+    fn configuration(rules: Vec<PathRule>) -> PingressConfiguration {
+        PingressConfiguration {
+            rules,
+            default_backend: None,
+            not_found_body: None,
+            default_tls: None,
+        }
+    }
+
+    /// Start the proxies of both listeners, as the server does
+    fn start_proxy(config: PingressConfiguration, h2c: bool) -> Listeners {
+        let proxy_map = ProxyMap::from(config.clone());
+        let tls = TlsMap::from(config);
+        let listeners = Listeners {
+            http: free_address(),
+            https: free_address(),
+        };
+        let args = Args {
+            listen_http: listeners.http.to_string(),
+            listen_https: listeners.https.to_string(),
+            h2c,
+            config: String::new(),
+            watch: Vec::new(),
+        };
+
+        let tls = Arc::new(ArcSwap::from_pointee(tls));
+        let mut services = create_http_proxies(
+            &Arc::new(ServerConf::default()),
+            &args,
+            Arc::new(ArcSwap::from_pointee(proxy_map)),
+            tls.clone(),
+        );
+        services.push(Box::new(background_service(
+            "OCSP stapling",
+            OcspService::new(tls),
+        )));
+        for mut service in services {
+            let (shutdown, watch) = tokio::sync::watch::channel(false);
+            tokio::spawn(async move {
+                let _shutdown = shutdown;
+                service.start_service(None, watch).await;
+            });
+        }
+        listeners
+    }
+
+    async fn connect_tcp(address: SocketAddr) -> TcpStream {
+        for _ in 0..50 {
+            if let Ok(stream) = TcpStream::connect(address).await {
+                return stream;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("{address} is not listening");
+    }
+
+    async fn connect(proxy: SocketAddr, host: &str) -> HealthClient<Channel> {
+        let endpoint = Channel::from_shared(format!("http://{proxy}"))
+            .unwrap()
+            .origin(format!("http://{host}").parse().unwrap());
+        for _ in 0..50 {
+            if let Ok(channel) = endpoint.connect().await {
+                return HealthClient::new(channel);
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("proxy is not listening");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn grpc_backend() {
+        let backend = free_address();
+        let (mut reporter, health) = tonic_health::server::health_reporter();
+        reporter
+            .set_service_status("pingress.Test", tonic_health::ServingStatus::Serving)
+            .await;
+        tokio::spawn(Server::builder().add_service(health).serve(backend));
+        let proxy = start_proxy(
+            configuration(vec![rule("grpc.example.com", backend, Protocol::Grpc)]),
+            true,
+        )
+        .http;
+
+        let mut client = connect(proxy, "grpc.example.com").await;
+        let response = client
+            .check(HealthCheckRequest {
+                service: "pingress.Test".to_string(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(response.get_ref().status(), ServingStatus::Serving);
+
+        // The status of the backend passes through in the trailers
+        let status = client
+            .check(HealthCheckRequest {
+                service: "pingress.Missing".to_string(),
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+
+        let mut client = connect(proxy, "unknown.example.com").await;
+        let status = client
+            .check(HealthCheckRequest::default())
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::Unimplemented);
+    }
+
+    /// HTTP server responding with the statuses in turn, then 200. Also returns the number of
+    /// requests it receives.
+    async fn start_status_server(statuses: Vec<u16>) -> (SocketAddr, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let requests = Arc::new(AtomicUsize::new(0));
+        let received = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                // Requests fit in a segment
+                let mut buf = [0; 4096];
+                if !matches!(stream.read(&mut buf).await, Ok(n) if n > 0) {
+                    continue;
+                }
+                let i = received.fetch_add(1, Ordering::SeqCst);
+                let status = statuses.get(i).copied().unwrap_or(200);
+                let response = format!(
+                    "HTTP/1.1 {status} Status\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        (address, requests)
+    }
+
+    fn retry_rule(host: &str, endpoints: &[SocketAddr], retry_on: RetryOn) -> PathRule {
+        let mut rule = rule(host, endpoints[0], Protocol::Http);
+        if let Backend::Service { endpoints: e, .. } = &mut rule.backend {
+            *e = endpoints
+                .iter()
+                .map(|address| Endpoint {
+                    address: address.ip().to_string(),
+                    port: address.port(),
+                    weight: 1,
+                })
+                .collect();
+        }
+        rule.retry = Some(RetryPolicy {
+            retry_on: vec![retry_on],
+            backoff_ms: 1,
+            ..RetryPolicy::default()
+        });
+        rule
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn retry_on_gateway_error() {
+        let (backend, requests) = start_status_server(vec![503]).await;
+        let rule = retry_rule("retry.example.com", &[backend], RetryOn::GatewayError);
+        let proxy = start_proxy(configuration(vec![rule]), false);
+
+        let response = get(proxy.http, "retry.example.com").await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+        // Only the conditions of the policy are retried
+        let (backend, requests) = start_status_server(vec![503]).await;
+        let rule = retry_rule("retry.example.com", &[backend], RetryOn::Reset);
+        let proxy = start_proxy(configuration(vec![rule]), false);
+        let response = get(proxy.http, "retry.example.com").await;
+        assert!(response.starts_with("HTTP/1.1 503"), "{response}");
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn retry_on_connect_failure() {
+        let (backend, requests) = start_status_server(Vec::new()).await;
+        let unreachable = free_address();
+        let rule = retry_rule(
+            "retry.example.com",
+            &[unreachable, backend],
+            RetryOn::ConnectFailure,
+        );
+        let proxy = start_proxy(configuration(vec![rule]), false);
+
+        // Every other request is first sent to the unreachable endpoint
+        for _ in 0..4 {
+            let response = get(proxy.http, "retry.example.com").await;
+            assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        }
+        assert_eq!(requests.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn no_retry_of_non_idempotent_requests() {
+        let (backend, requests) = start_status_server(vec![503]).await;
+        let rule = retry_rule("retry.example.com", &[backend], RetryOn::GatewayError);
+        let proxy = start_proxy(configuration(vec![rule]), false);
+
+        let mut stream = connect_tcp(proxy.http).await;
+        let request = "POST / HTTP/1.1\r\nHost: retry.example.com\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello";
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 503"), "{response}");
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    /// Echo WebSocket server
+    async fn start_echo_server() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let Ok(mut ws) = tokio_tungstenite::accept_async(stream).await else {
+                        return;
+                    };
+                    while let Some(Ok(message)) = ws.next().await {
+                        if message.is_text() || message.is_binary() {
+                            let _ = ws.send(message).await;
+                        }
+                    }
+                });
+            }
+        });
+        address
+    }
+
+    async fn echo<S>(ws: &mut WebSocketStream<S>, text: &str) -> Option<String>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        ws.send(Message::text(text)).await.ok()?;
+        match ws.next().await {
+            Some(Ok(Message::Text(text))) => Some(text),
+            _ => None,
+        }
+    }
+
+    fn websocket_rule(backend: SocketAddr) -> PathRule {
+        let mut rule = rule("ws.example.com", backend, Protocol::Http);
+        rule.tls = Some(self_signed(&["ws.example.com"]));
+        rule.timeouts = Timeouts {
+            read_ms: Some(100),
+            total_ms: Some(100),
+            upgrade_idle_ms: Some(1_000),
+            ..Timeouts::default()
+        };
+        rule
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn websocket_over_http() {
+        let backend = start_echo_server().await;
+        let proxy = start_proxy(configuration(vec![websocket_rule(backend)]), false);
+
+        let stream = connect_tcp(proxy.http).await;
+        let (mut ws, response) =
+            tokio_tungstenite::client_async("ws://ws.example.com/echo", stream)
+                .await
+                .unwrap();
+        assert_eq!(response.status(), 101);
+        assert_eq!(echo(&mut ws, "hello").await.as_deref(), Some("hello"));
+
+        // Neither the read timeout nor the total timeout applies once upgraded
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(echo(&mut ws, "again").await.as_deref(), Some("again"));
+
+        // The upgrade idle timeout does
+        tokio::time::sleep(Duration::from_millis(1_500)).await;
+        assert_eq!(echo(&mut ws, "idle").await, None);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn websocket_over_https() {
+        let backend = start_echo_server().await;
+        let proxy = start_proxy(configuration(vec![websocket_rule(backend)]), false);
+
+        let stream = connect_tls(proxy.https, "ws.example.com").await;
+        let (mut ws, response) =
+            tokio_tungstenite::client_async("wss://ws.example.com/echo", stream)
+                .await
+                .unwrap();
+        assert_eq!(response.status(), 101);
+        assert_eq!(echo(&mut ws, "hello").await.as_deref(), Some("hello"));
+        assert_eq!(echo(&mut ws, "world").await.as_deref(), Some("world"));
+    }
+
+    /// TLS connection to the proxy, requesting the OCSP status of its certificate
+    async fn connect_tls(proxy: SocketAddr, sni: &str) -> SslStream<TcpStream> {
+        let mut connector = SslConnector::builder(SslMethod::tls()).unwrap();
+        connector.set_verify(SslVerifyMode::NONE);
+        let mut ssl = connector
+            .build()
+            .configure()
+            .unwrap()
+            .into_ssl(sni)
+            .unwrap();
+        ssl.set_status_type(StatusType::OCSP).unwrap();
+        let mut stream = SslStream::new(ssl, connect_tcp(proxy).await).unwrap();
+        Pin::new(&mut stream).connect().await.unwrap();
+        stream
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn ocsp_stapling() {
+        let mut rule = rule(
+            "ocsp.example.com",
+            "127.0.0.1:1".parse().unwrap(),
+            Protocol::Http,
+        );
+        rule.tls = Some(fixture_tls(OcspStapling::File {
+            path: fixture("response.der"),
+        }));
+        let proxy = start_proxy(configuration(vec![rule]), false);
+        let response = std::fs::read(fixture("response.der")).unwrap();
+
+        // The response is read in the background, shortly after the start
+        for _ in 0..50 {
+            let stream = connect_tls(proxy.https, "ocsp.example.com").await;
+            // The leaf and its issuer
+            assert_eq!(stream.ssl().peer_cert_chain().unwrap().len(), 2);
+            if stream.ssl().ocsp_status() == Some(response.as_slice()) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("The OCSP response is not stapled");
+    }
+
+    /// Raw HTTP/1.1 response of the proxy to a request of `/` on `host`
+    async fn get(proxy: SocketAddr, host: &str) -> String {
+        let mut stream = connect_tcp(proxy).await;
+        let request = format!("GET / HTTP/1.1\r\nHost: {host}\r\nConnection: close\r\n\r\n");
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn not_found() {
+        let proxy = start_proxy(configuration(Vec::new()), false);
+        let response = get(proxy.http, "unknown.example.com").await;
+        assert!(response.starts_with("HTTP/1.1 404"), "{response}");
+
+        let mut config = configuration(Vec::new());
+        config.not_found_body = Some("<h1>Nothing here</h1>".to_string());
+        let proxy = start_proxy(config, false);
+        let response = get(proxy.http, "unknown.example.com").await;
+        assert!(response.starts_with("HTTP/1.1 404"), "{response}");
+        assert!(response.contains("text/html; charset=utf-8"), "{response}");
+        assert!(response.ends_with("<h1>Nothing here</h1>"), "{response}");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn default_backend() {
+        let backend = start_echo_server().await;
+        let mut config = configuration(Vec::new());
+        config.default_backend = Some(rule("", backend, Protocol::Http).backend);
+        let proxy = start_proxy(config, false);
+
+        let stream = connect_tcp(proxy.http).await;
+        let (mut ws, response) =
+            tokio_tungstenite::client_async("ws://unknown.example.com/echo", stream)
+                .await
+                .unwrap();
+        assert_eq!(response.status(), 101);
+        assert_eq!(echo(&mut ws, "hello").await.as_deref(), Some("hello"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn static_response() {
+        let mut maintenance = rule("maintenance.example.com", free_address(), Protocol::Http);
+        maintenance.backend = Backend::Response {
+            status: 503,
+            location: None,
+            content_type: None,
+            body: "<h1>Under maintenance</h1>".to_string(),
+        };
+        let mut redirect = rule("old.example.com", free_address(), Protocol::Http);
+        redirect.backend = Backend::Response {
+            status: 301,
+            location: Some("https://new.example.com/".to_string()),
+            content_type: None,
+            body: String::new(),
+        };
+        let proxy = start_proxy(configuration(vec![maintenance, redirect]), false);
+
+        let response = get(proxy.http, "maintenance.example.com").await;
+        assert!(response.starts_with("HTTP/1.1 503"), "{response}");
+        assert!(
+            response.ends_with("<h1>Under maintenance</h1>"),
+            "{response}"
+        );
+
+        let response = get(proxy.http, "old.example.com").await;
+        assert!(response.starts_with("HTTP/1.1 301"), "{response}");
+        assert!(
+            response.contains("Location: https://new.example.com/"),
+            "{response}"
+        );
+    }
+
+    /// HTTP server responding with the value of a header of the request
+    async fn start_header_server(header: &'static str) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    match stream.read(&mut buf).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => request.extend_from_slice(&buf[..n]),
+                    }
+                }
+                let request = String::from_utf8_lossy(&request);
+                let value = request
+                    .lines()
+                    .filter_map(|l| l.split_once(": "))
+                    .find(|(name, _)| name.eq_ignore_ascii_case(header))
+                    .map(|(_, value)| value)
+                    .unwrap_or_default();
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{value}",
+                    value.len()
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        address
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn external_name_backend() {
+        let backend = start_header_server("Host").await;
+        let mut rule = rule("external.example.com", backend, Protocol::Http);
+        rule.backend = Backend::ExternalName {
+            name: "external".to_string(),
+            namespace: "default".to_string(),
+            external_name: "localhost".to_string(),
+            port: backend.port(),
+            circuit_breaker: None,
+            protocol: Protocol::Http,
+            tls: None,
+        };
+        let proxy = start_proxy(configuration(vec![rule]), false);
+
+        let response = get(proxy.http, "external.example.com").await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        assert!(response.ends_with("\r\n\r\nlocalhost"), "{response}");
+    }
+
+    /// Raw HTTP/1.1 response of the proxy to a request of `/` on `host` over TLS, presenting the
+    /// client certificate if any, with a forged subject header. `None` when the proxy rejects the
+    /// connection.
+    async fn get_tls(
+        proxy: SocketAddr,
+        sni: &str,
+        host: &str,
+        configure: impl FnOnce(&mut SslConnectorBuilder),
+    ) -> Option<String> {
+        let mut connector = SslConnector::builder(SslMethod::tls()).unwrap();
+        connector.set_verify(SslVerifyMode::NONE);
+        configure(&mut connector);
+        let (response, _) = get_tls_session(&connector.build(), proxy, sni, host, None).await?;
+        Some(response)
+    }
+
+    /// Like [get_tls], resuming the session if any. Also returns the session of the connection,
+    /// `None` when it is not resumed.
+    async fn get_tls_session(
+        connector: &SslConnector,
+        proxy: SocketAddr,
+        sni: &str,
+        host: &str,
+        session: Option<&SslSession>,
+    ) -> Option<(String, Option<SslSession>)> {
+        let mut ssl = connector.configure().unwrap().into_ssl(sni).unwrap();
+        if let Some(session) = session {
+            // SAFETY: the session comes from a connection of the same connector
+            unsafe { ssl.set_session(session).unwrap() };
+        }
+        let mut stream = SslStream::new(ssl, connect_tcp(proxy).await).unwrap();
+        Pin::new(&mut stream).connect().await.ok()?;
+        let request = format!(
+            "GET / HTTP/1.1\r\nHost: {host}\r\nX-Client-Subject: CN=forged\r\nConnection: close\r\n\r\n"
+        );
+        stream.write_all(request.as_bytes()).await.ok()?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.ok()?;
+        // Sessions of connections not shut down are not resumed
+        let _ = stream.shutdown().await;
+        let session = match session {
+            Some(_) => stream
+                .ssl()
+                .session_reused()
+                .then(|| session.cloned())
+                .flatten(),
+            // Tickets come after the handshake
+            None => stream.ssl().session().map(|s| s.to_owned()),
+        };
+        (!response.is_empty()).then_some((response, session))
+    }
+
+    fn client_certificate(
+        cert: &X509,
+        key: &PKey<Private>,
+    ) -> impl FnOnce(&mut SslConnectorBuilder) {
+        let (cert, key) = (cert.clone(), key.clone());
+        move |connector| {
+            connector.set_certificate(&cert).unwrap();
+            connector.set_private_key(&key).unwrap();
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn client_certificate_auth() {
+        let backend = start_header_server("X-Client-Subject").await;
+        let ca_key = ec_key();
+        let ca = ca_certificate("Client CA", &ca_key);
+        let ca_pem = ca_key.private_key_to_pem_pkcs8().unwrap();
+        let ca = (write_tls("mtls-ca", ca_pem.as_slice(), &[&ca]).cert, ca);
+        let client_key = ec_key();
+        let client = certificate(&["client"], &client_key, Some((&ca.1, &ca_key)));
+        let other_key = ec_key();
+        let other = certificate(&["other"], &other_key, None);
+
+        let mut rules = Vec::new();
+        for (host, mode) in [
+            ("required.example.com", ClientAuthMode::Required),
+            ("optional.example.com", ClientAuthMode::Optional),
+        ] {
+            let mut rule = rule(host, backend, Protocol::Http);
+            rule.tls = Some(Tls {
+                client_auth: Some(ClientAuth {
+                    ca: ca.0.clone(),
+                    mode,
+                    subject_header: DEFAULT_SUBJECT_HEADER.to_string(),
+                }),
+                ..self_signed(&[host])
+            });
+            rules.push(rule);
+        }
+        let mut plain = rule("plain.example.com", backend, Protocol::Http);
+        plain.tls = Some(self_signed(&["plain.example.com"]));
+        rules.push(plain);
+        let proxy = start_proxy(configuration(rules), false);
+
+        // The verified subject replaces the forged one
+        for host in ["required.example.com", "optional.example.com"] {
+            let response = get_tls(
+                proxy.https,
+                host,
+                host,
+                client_certificate(&client, &client_key),
+            )
+            .await
+            .unwrap();
+            assert!(response.ends_with("\r\n\r\nCN=client"), "{response}");
+        }
+
+        // Without a certificate
+        let response = get_tls(
+            proxy.https,
+            "required.example.com",
+            "required.example.com",
+            |_| {},
+        )
+        .await;
+        assert_eq!(response, None);
+        let response = get_tls(
+            proxy.https,
+            "optional.example.com",
+            "optional.example.com",
+            |_| {},
+        )
+        .await
+        .unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        assert!(response.ends_with("\r\n\r\n"), "{response}");
+
+        // With a certificate of another CA
+        for host in ["required.example.com", "optional.example.com"] {
+            let response = get_tls(
+                proxy.https,
+                host,
+                host,
+                client_certificate(&other, &other_key),
+            )
+            .await;
+            assert_eq!(response, None);
+        }
+
+        // The host is required to be reached with its certificate verified
+        let response = get_tls(
+            proxy.https,
+            "plain.example.com",
+            "required.example.com",
+            client_certificate(&client, &client_key),
+        )
+        .await
+        .unwrap();
+        assert!(response.starts_with("HTTP/1.1 403"), "{response}");
+        let response = get(proxy.http, "required.example.com").await;
+        assert!(response.starts_with("HTTP/1.1 403"), "{response}");
+
+        // Resuming the session of a closed connection, which does not verify the certificate
+        // again
+        for version in [SslVersion::TLS1_2, SslVersion::TLS1_3] {
+            let mut connector = SslConnector::builder(SslMethod::tls()).unwrap();
+            connector.set_verify(SslVerifyMode::NONE);
+            connector.set_max_proto_version(Some(version)).unwrap();
+            client_certificate(&client, &client_key)(&mut connector);
+            let connector = connector.build();
+            for host in ["required.example.com", "optional.example.com"] {
+                let (_, session) = get_tls_session(&connector, proxy.https, host, host, None)
+                    .await
+                    .unwrap();
+                let (response, resumed) =
+                    get_tls_session(&connector, proxy.https, host, host, session.as_ref())
+                        .await
+                        .unwrap();
+                assert!(resumed.is_some(), "{version:?} {host}");
+                assert!(response.ends_with("\r\n\r\nCN=client"), "{response}");
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn protocol_and_cipher_policy() {
+        let backend = start_header_server("Host").await;
+        let mut modern = rule("modern.example.com", backend, Protocol::Http);
+        modern.tls = Some(Tls {
+            min_version: Some(TlsVersion::Tls13),
+            ..self_signed(&["modern.example.com"])
+        });
+        let mut ciphers = rule("ciphers.example.com", backend, Protocol::Http);
+        ciphers.tls = Some(Tls {
+            ciphers: Some("ECDHE-ECDSA-AES256-GCM-SHA384".to_string()),
+            curves: Some("P-384".to_string()),
+            ..self_signed(&["ciphers.example.com"])
+        });
+        let proxy = start_proxy(configuration(vec![modern, ciphers]), false);
+
+        let tls12 = |ciphers: &'static str, curves: &'static str| {
+            move |connector: &mut SslConnectorBuilder| {
+                connector
+                    .set_max_proto_version(Some(SslVersion::TLS1_2))
+                    .unwrap();
+                connector.set_cipher_list(ciphers).unwrap();
+                connector.set_groups_list(curves).unwrap();
+            }
+        };
+        let any = tls12(
+            "ECDHE-ECDSA-AES128-GCM-SHA256:ECDHE-ECDSA-AES256-GCM-SHA384",
+            "P-256:P-384",
+        );
+
+        let response = get_tls(
+            proxy.https,
+            "modern.example.com",
+            "modern.example.com",
+            |_| {},
+        )
+        .await
+        .unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        let response = get_tls(proxy.https, "modern.example.com", "modern.example.com", any).await;
+        assert_eq!(response, None);
 
-        match ctx.proxy_map.get_backend(host, path) {
-            None => pingora::Error::err(ErrorType::ConnectNoRoute),
-            Some(backend_host) => Ok(Box::new(HttpPeer::new(
-                backend_host,
-                false,
-                host.to_string(),
-            ))),
+        let response = get_tls(
+            proxy.https,
+            "ciphers.example.com",
+            "ciphers.example.com",
+            any,
+        )
+        .await;
+        assert!(response.is_some_and(|r| r.starts_with("HTTP/1.1 200")));
+        let cases = [
+            tls12("ECDHE-ECDSA-AES128-GCM-SHA256", "P-256:P-384"),
+            tls12("ECDHE-ECDSA-AES256-GCM-SHA384", "P-256"),
+        ];
+        for configure in cases {
+            let response = get_tls(
+                proxy.https,
+                "ciphers.example.com",
+                "ciphers.example.com",
+                configure,
+            )
+            .await;
+            assert_eq!(response, None);
         }
     }
 }
@@ -0,0 +1,30 @@
+//! Pingress proxy server: routes the requests to the backends of the Ingresses, as configured
+//! by the controller.
+//!
+//! The binary runs [run]. The rest is exposed for the benchmarks and the tests of the
+//! controller.
+
+mod circuit_breaker;
+mod config;
+mod error;
+mod grpc;
+mod health_check;
+mod host;
+mod http_proxy;
+mod metrics;
+mod ocsp;
+mod outlier;
+mod proxy_map;
+mod retry;
+mod server;
+mod static_response;
+mod tls;
+mod tls_policy;
+mod upstream;
+mod upstream_tls;
+mod watcher;
+
+pub use crate::proxy_map::{ProxyMap, Route};
+pub use crate::server::{create_http_proxies, run, Args};
+pub use crate::tls::{Certificate, GetTls, TlsMap};
+pub use crate::upstream::{Selected, Upstream};
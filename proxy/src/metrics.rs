@@ -0,0 +1,82 @@
+use prometheus::{register_int_counter_vec, register_int_gauge_vec, IntCounterVec, IntGaugeVec};
+use std::sync::LazyLock;
+
+/// 1 when an upstream endpoint passes its health check, 0 otherwise
+pub(crate) static UPSTREAM_ENDPOINT_HEALTHY: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "pingress_upstream_endpoint_healthy",
+        "Whether an upstream endpoint passes its health check",
+        &["backend", "endpoint"]
+    )
+    .unwrap()
+});
+
+/// 1 while an upstream endpoint is ejected by outlier detection, 0 otherwise
+pub(crate) static UPSTREAM_ENDPOINT_EJECTED: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "pingress_upstream_endpoint_ejected",
+        "Whether an upstream endpoint is ejected by outlier detection",
+        &["backend", "endpoint"]
+    )
+    .unwrap()
+});
+
+/// Concurrent requests of a backend with a circuit breaker
+pub(crate) static UPSTREAM_REQUESTS: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "pingress_upstream_requests",
+        "Concurrent requests to an upstream backend",
+        &["backend"]
+    )
+    .unwrap()
+});
+
+/// Concurrent requests of a backend with a circuit breaker, waiting for a connection
+pub(crate) static UPSTREAM_PENDING_REQUESTS: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "pingress_upstream_pending_requests",
+        "Concurrent requests waiting for a connection to an upstream backend",
+        &["backend"]
+    )
+    .unwrap()
+});
+
+/// Requests rejected by the circuit breaker of a backend
+pub(crate) static UPSTREAM_OVERFLOW: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "pingress_upstream_overflow_total",
+        "Requests rejected by the circuit breaker of an upstream backend",
+        &["backend"]
+    )
+    .unwrap()
+});
+
+/// Rules skipped when loading the configuration, e.g. with an invalid host or backend
+pub(crate) static CONFIG_RULE_ERRORS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "pingress_config_rule_errors_total",
+        "Rules of a host that cannot be loaded",
+        &["host"]
+    )
+    .unwrap()
+});
+
+/// Certificates skipped when loading the configuration, e.g. unreadable or with a wrong key
+pub(crate) static TLS_CERTIFICATE_ERRORS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "pingress_tls_certificate_errors_total",
+        "Certificates of a host that cannot be loaded",
+        &["host"]
+    )
+    .unwrap()
+});
+
+/// OCSP responses that cannot be fetched, read or verified
+pub(crate) static TLS_OCSP_ERRORS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "pingress_tls_ocsp_errors_total",
+        "OCSP responses of a certificate that cannot be stapled",
+        &["certificate"]
+    )
+    .unwrap()
+});
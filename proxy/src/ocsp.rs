@@ -0,0 +1,681 @@
+use crate::metrics::TLS_OCSP_ERRORS;
+use crate::tls::TlsMap;
+use arc_swap::{ArcSwap, ArcSwapOption};
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::future::join_all;
+use log::{error, warn};
+use openssl::asn1::{Asn1GeneralizedTimeRef, Asn1Time};
+use openssl::error::ErrorStack;
+use openssl::hash::MessageDigest;
+use openssl::ocsp::{
+    OcspCertId, OcspCertStatus, OcspFlag, OcspRequest, OcspResponse, OcspResponseStatus,
+};
+use openssl::stack::Stack;
+use openssl::x509::store::X509StoreBuilder;
+use openssl::x509::verify::X509VerifyFlags;
+use openssl::x509::X509;
+use pingora::connectors::http::Connector;
+use pingora::http::RequestHeader;
+use pingora::server::ShutdownWatch;
+use pingora::services::background::BackgroundService;
+use pingora::upstreams::peer::HttpPeer;
+use pingress_config::OcspStapling;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Resolution of the refresh schedule
+const TICK: Duration = Duration::from_secs(1);
+
+/// Period to fetch or read a response again while the current one is valid
+const REFRESH: Duration = Duration::from_secs(3600);
+
+/// Time before the next update of a response to fetch or read it again, at most half of its
+/// remaining validity
+const EXPIRY_MARGIN: Duration = Duration::from_secs(300);
+
+/// Delay before another attempt after a failure
+const RETRY: Duration = Duration::from_secs(60);
+
+/// Clock skew tolerated on the validity period of the responses
+const MAX_SKEW_SECS: u32 = 300;
+
+/// Timeout of the connections and reads to the responders
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Largest response accepted from a responder
+const MAX_RESPONSE_SIZE: usize = 64 * 1024;
+
+#[derive(PartialEq)]
+enum Source {
+    File(String),
+    Responder(String),
+}
+
+/// Verified response, with the time after which it must not be stapled
+struct Stapled {
+    der: Arc<Vec<u8>>,
+    next_update: Option<SystemTime>,
+}
+
+/// OCSP response stapled to the handshakes of a certificate.
+///
+/// A response is only stapled once verified against the issuer of the certificate, and until
+/// it expires.
+pub(crate) struct OcspStaple {
+    /// Path to the certificate, naming it in the logs and metrics
+    name: String,
+    source: Source,
+    leaf: X509,
+    issuer: X509,
+    response: ArcSwapOption<Stapled>,
+    /// When to fetch or read the response again
+    refresh_at: Mutex<Instant>,
+}
+
+impl OcspStaple {
+    /// Stapling of the leaf certificate, whose issuer must be the first certificate of the chain
+    pub(crate) fn new(
+        name: &str,
+        config: &OcspStapling,
+        leaf: &X509,
+        chain: &[X509],
+    ) -> Result<Self, String> {
+        let issuer = chain
+            .first()
+            .ok_or("the chain does not contain the issuer of the certificate")?;
+        let source = match config {
+            OcspStapling::File { path } => Source::File(path.clone()),
+            OcspStapling::Fetch {
+                responder: Some(responder),
+            } => Source::Responder(responder.clone()),
+            OcspStapling::Fetch { responder: None } => leaf
+                .ocsp_responders()
+                .map_err(|e| e.to_string())?
+                .iter()
+                .next()
+                .map(|r| Source::Responder(r.to_string()))
+                .ok_or("the certificate has no OCSP responder")?,
+        };
+
+        Ok(Self {
+            name: name.to_string(),
+            source,
+            leaf: leaf.clone(),
+            issuer: issuer.clone(),
+            response: ArcSwapOption::empty(),
+            refresh_at: Mutex::new(Instant::now()),
+        })
+    }
+
+    /// DER of the current response, if any and not past its next update
+    pub(crate) fn response(&self) -> Option<Arc<Vec<u8>>> {
+        let now = SystemTime::now();
+        self.response
+            .load()
+            .as_ref()
+            .filter(|r| r.next_update.is_none_or(|t| now < t))
+            .map(|r| r.der.clone())
+    }
+
+    fn due(&self, now: Instant) -> bool {
+        *self.refresh_at.lock().unwrap() <= now
+    }
+
+    /// Take over the response of the same certificate from a previous configuration
+    pub(crate) fn inherit(&self, previous: &OcspStaple) {
+        if self.source == previous.source && self.leaf == previous.leaf {
+            self.response.store(previous.response.load_full());
+            *self.refresh_at.lock().unwrap() = *previous.refresh_at.lock().unwrap();
+        }
+    }
+
+    /// Fetch or read the response again. On failure, the current response is kept as long as
+    /// it is valid.
+    pub(crate) async fn refresh(&self, connector: &Connector) {
+        let response = match &self.source {
+            Source::File(path) => std::fs::read(path).map_err(|e| format!("{path}: {e}")),
+            Source::Responder(url) => self.fetch(connector, url.as_str()).await,
+        }
+        .and_then(|response| {
+            let next_update = self.verify(response.as_slice())?;
+            Ok((response, next_update))
+        });
+
+        let next = match response {
+            Ok((response, next_update)) => {
+                self.response.store(Some(Arc::new(Stapled {
+                    der: Arc::new(response),
+                    next_update,
+                })));
+                // Before the response expires, leaving time to retry on failure
+                next_update.map_or(REFRESH, |t| {
+                    let remaining = t.duration_since(SystemTime::now()).unwrap_or_default();
+                    REFRESH.min(remaining - EXPIRY_MARGIN.min(remaining / 2))
+                })
+            }
+            Err(e) => {
+                error!("Error: OCSP response of '{}': {e}", self.name);
+                TLS_OCSP_ERRORS
+                    .with_label_values(&[self.name.as_str()])
+                    .inc();
+                let current = self.response.load_full();
+                if current.is_some_and(|r| self.verify(r.der.as_slice()).is_err()) {
+                    self.response.store(None);
+                }
+                RETRY
+            }
+        };
+        *self.refresh_at.lock().unwrap() = Instant::now() + next;
+    }
+
+    fn cert_id(&self) -> Result<OcspCertId, ErrorStack> {
+        OcspCertId::from_cert(MessageDigest::sha1(), &self.leaf, &self.issuer)
+    }
+
+    async fn fetch(&self, connector: &Connector, url: &str) -> Result<Vec<u8>, String> {
+        let request = {
+            let mut request = OcspRequest::new().map_err(|e| e.to_string())?;
+            let id = self.cert_id().map_err(|e| e.to_string())?;
+            request.add_id(id).map_err(|e| e.to_string())?;
+            request.to_der().map_err(|e| e.to_string())?
+        };
+        post(connector, url, request)
+            .await
+            .map_err(|e| format!("{url}: {e}"))
+    }
+
+    /// Check that the response is a successful one about the certificate, signed by its issuer
+    /// or a responder the issuer delegates to, and currently valid. Returns its next update, if
+    /// any.
+    fn verify(&self, response: &[u8]) -> Result<Option<SystemTime>, String> {
+        let response = OcspResponse::from_der(response).map_err(|e| e.to_string())?;
+        if response.status() != OcspResponseStatus::SUCCESSFUL {
+            return Err(format!(
+                "the responder answers with status {}",
+                response.status().as_raw()
+            ));
+        }
+        let basic = response.basic().map_err(|e| e.to_string())?;
+
+        let verified = || -> Result<(), ErrorStack> {
+            let mut certs = Stack::new()?;
+            certs.push(self.issuer.clone())?;
+            let mut store = X509StoreBuilder::new()?;
+            store.add_cert(self.issuer.clone())?;
+            store.set_flags(X509VerifyFlags::PARTIAL_CHAIN)?;
+            basic.verify(&certs, &store.build(), OcspFlag::TRUST_OTHER)
+        };
+        verified().map_err(|e| format!("invalid signature: {e}"))?;
+
+        let id = self.cert_id().map_err(|e| e.to_string())?;
+        let status = basic
+            .find_status(&id)
+            .ok_or("the response is not about the certificate")?;
+        status
+            .check_validity(MAX_SKEW_SECS, None)
+            .map_err(|e| format!("expired: {e}"))?;
+        if status.status == OcspCertStatus::REVOKED {
+            warn!("Certificate '{}' is revoked", self.name);
+        }
+        status.next_update().map(system_time).transpose()
+    }
+}
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// `YYYYMMDDHHMMSSZ` form of a time printed by OpenSSL, like `Oct  8 11:28:24.5 2026 GMT`
+fn generalized_time(printed: &str) -> Result<String, String> {
+    let invalid = || format!("invalid time '{printed}'");
+    let [month, day, time, year, "GMT"] = printed.split_whitespace().collect::<Vec<_>>()[..] else {
+        return Err(invalid());
+    };
+    let month = MONTHS
+        .iter()
+        .position(|m| *m == month)
+        .ok_or_else(invalid)?
+        + 1;
+    let day: u32 = day.parse().map_err(|_| invalid())?;
+    // Fractions of seconds are dropped
+    let time = time.split('.').next().unwrap_or_default().replace(':', "");
+    Ok(format!("{year}{month:02}{day:02}{time}Z"))
+}
+
+/// Time of a response field, which the openssl crate only exposes printed or compared to an
+/// [Asn1Time]
+fn system_time(time: &Asn1GeneralizedTimeRef) -> Result<SystemTime, String> {
+    let time = Asn1Time::from_str(generalized_time(time.to_string().as_str())?.as_str())
+        .map_err(|e| e.to_string())?;
+    let diff = Asn1Time::days_from_now(0)
+        .and_then(|now| now.diff(&time))
+        .map_err(|e| e.to_string())?;
+    let secs = i64::from(diff.days) * 86400 + i64::from(diff.secs);
+    let offset = Duration::from_secs(secs.unsigned_abs());
+    let now = SystemTime::now();
+    Ok(if secs < 0 { now - offset } else { now + offset })
+}
+
+/// POST the DER request to the responder. Responders are reached over plain HTTP, as their
+/// responses are signed.
+async fn post(connector: &Connector, url: &str, body: Vec<u8>) -> Result<Vec<u8>, String> {
+    let (authority, host, port, path) = parse_http_url(url)?;
+    let address = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|e| e.to_string())?
+        .next()
+        .ok_or("the host has no address")?;
+
+    let mut peer = HttpPeer::new(address, false, String::new());
+    peer.options.connection_timeout = Some(TIMEOUT);
+    peer.options.read_timeout = Some(TIMEOUT);
+    peer.options.write_timeout = Some(TIMEOUT);
+
+    let mut request =
+        RequestHeader::build("POST", path.as_bytes(), None).map_err(|e| e.to_string())?;
+    let headers = [
+        ("Host", authority.to_string()),
+        ("Content-Type", "application/ocsp-request".to_string()),
+        ("Content-Length", body.len().to_string()),
+    ];
+    for (name, value) in headers {
+        request
+            .insert_header(name, value)
+            .map_err(|e| e.to_string())?;
+    }
+
+    let (mut session, _) = connector
+        .get_http_session(&peer)
+        .await
+        .map_err(|e| e.to_string())?;
+    session
+        .write_request_header(Box::new(request))
+        .await
+        .map_err(|e| e.to_string())?;
+    session
+        .write_request_body(Bytes::from(body), true)
+        .await
+        .map_err(|e| e.to_string())?;
+    session
+        .finish_request_body()
+        .await
+        .map_err(|e| e.to_string())?;
+    session
+        .read_response_header()
+        .await
+        .map_err(|e| e.to_string())?;
+    let status = session.response_header().map(|h| h.status);
+    if !status.is_some_and(|s| s.is_success()) {
+        return Err(format!("the responder answers with HTTP status {status:?}"));
+    }
+
+    let mut response = Vec::new();
+    while let Some(chunk) = session
+        .read_response_body()
+        .await
+        .map_err(|e| e.to_string())?
+    {
+        response.extend_from_slice(&chunk);
+        if response.len() > MAX_RESPONSE_SIZE {
+            return Err("the response is too large".to_string());
+        }
+    }
+    Ok(response)
+}
+
+/// Authority, host, port and path of an `http://` URL
+fn parse_http_url(url: &str) -> Result<(&str, &str, u16, &str), String> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or("only http responders are supported")?;
+    let (authority, path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, "/"),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        // Not the last group of an IPv6 address
+        Some((host, port)) if !port.contains(']') => {
+            let port = port.parse().map_err(|_| format!("invalid port '{port}'"))?;
+            (host, port)
+        }
+        _ => (authority, 80),
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if host.is_empty() {
+        return Err("no host".to_string());
+    }
+    Ok((authority, host, port, path))
+}
+
+/// Refreshes the OCSP responses of the certificates of the current configuration.
+///
+/// Like [crate::health_check::HealthCheckService], a single service follows the current
+/// [TlsMap], whose certificates are rebuilt on every reload.
+pub(crate) struct OcspService {
+    tls: Arc<ArcSwap<TlsMap>>,
+    connector: Connector,
+}
+
+impl OcspService {
+    pub(crate) fn new(tls: Arc<ArcSwap<TlsMap>>) -> Self {
+        Self {
+            tls,
+            connector: Connector::new(None),
+        }
+    }
+}
+
+#[async_trait]
+impl BackgroundService for OcspService {
+    async fn start(&self, mut shutdown: ShutdownWatch) {
+        let mut interval = tokio::time::interval(TICK);
+
+        loop {
+            tokio::select! {
+                _ = shutdown.changed() => return,
+                _ = interval.tick() => {}
+            }
+
+            let tls = self.tls.load_full();
+            let now = Instant::now();
+            let due = tls
+                .ocsp_staples()
+                .filter(|s| s.due(now))
+                .map(|s| s.refresh(&self.connector));
+            join_all(due).await;
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use crate::ocsp::{generalized_time, parse_http_url, OcspStaple};
+    use crate::tls::tests::{ca_certificate, certificate, ec_key};
+    use openssl::asn1::Asn1Time;
+    use openssl::bn::BigNumContext;
+    use openssl::ec::PointConversionForm;
+    use openssl::hash::MessageDigest;
+    use openssl::ocsp::{OcspResponse, OcspResponseStatus};
+    use openssl::pkey::{PKey, Private};
+    use openssl::sha::sha1;
+    use openssl::sign::Signer;
+    use openssl::x509::X509;
+    use pingora::connectors::http::Connector;
+    use pingress_config::{OcspStapling, Tls};
+    use std::net::SocketAddr;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Certificate issued by a test CA for `ocsp.example.com`, followed by the CA certificate.
+    /// Its responder `http://ocsp.invalid/` cannot be reached.
+    pub(crate) fn fixture(file: &str) -> String {
+        format!("{}/tests/fixtures/ocsp/{file}", env!("CARGO_MANIFEST_DIR"))
+    }
+
+    /// Certificate with the `good` response of the CA, which has no next update
+    pub(crate) fn fixture_tls(ocsp: OcspStapling) -> Tls {
+        Tls {
+            ocsp: Some(ocsp),
+            ..Tls::new(fixture("tls.key"), fixture("tls.crt"))
+        }
+    }
+
+    fn fixture_staple(config: OcspStapling) -> OcspStaple {
+        let chain = X509::stack_from_pem(&std::fs::read(fixture("tls.crt")).unwrap()).unwrap();
+        OcspStaple::new("test", &config, &chain[0], &chain[1..]).unwrap()
+    }
+
+    fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+        let mut der = vec![tag];
+        match content.len() {
+            len @ 0..=0x7f => der.push(len as u8),
+            len @ 0x80..=0xff => der.extend([0x81, len as u8]),
+            len => der.extend([0x82, (len >> 8) as u8, len as u8]),
+        }
+        der.extend_from_slice(content);
+        der
+    }
+
+    fn sequence(items: &[&[u8]]) -> Vec<u8> {
+        der(0x30, &items.concat())
+    }
+
+    fn time(time: SystemTime) -> Vec<u8> {
+        let secs = time.duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let printed = Asn1Time::from_unix(secs as i64).unwrap().to_string();
+        der(0x18, generalized_time(printed.as_str()).unwrap().as_bytes())
+    }
+
+    /// `good` response about the certificate issued by the CA, whose EC key signs it, expiring
+    /// after the validity
+    fn response_until(
+        leaf: &X509,
+        ca: &X509,
+        ca_key: &PKey<Private>,
+        validity: Duration,
+    ) -> Vec<u8> {
+        let ec = ca_key.ec_key().unwrap();
+        let mut ctx = BigNumContext::new().unwrap();
+        let key_hash = sha1(
+            &ec.public_key()
+                .to_bytes(ec.group(), PointConversionForm::UNCOMPRESSED, &mut ctx)
+                .unwrap(),
+        );
+        let mut serial = leaf.serial_number().to_bn().unwrap().to_vec();
+        if serial.first().is_none_or(|b| b & 0x80 != 0) {
+            serial.insert(0, 0);
+        }
+
+        let sha1_algorithm =
+            sequence(&[&[0x06, 0x05, 0x2b, 0x0e, 0x03, 0x02, 0x1a], &[0x05, 0x00]]);
+        let cert_id = sequence(&[
+            &sha1_algorithm,
+            &der(0x04, &sha1(&ca.subject_name().to_der().unwrap())),
+            &der(0x04, &key_hash),
+            &der(0x02, &serial),
+        ]);
+        let now = SystemTime::now();
+        let single = sequence(&[
+            &cert_id,
+            // good
+            &[0x80, 0x00],
+            &time(now - Duration::from_secs(60)),
+            &der(0xa0, &time(now + validity)),
+        ]);
+        let data = sequence(&[
+            &der(0xa2, &der(0x04, &key_hash)),
+            &time(now),
+            &sequence(&[&single]),
+        ]);
+
+        let mut signer = Signer::new(MessageDigest::sha256(), ca_key).unwrap();
+        signer.update(&data).unwrap();
+        let mut signature = vec![0];
+        signature.extend(signer.sign_to_vec().unwrap());
+        let ecdsa_with_sha256 = [0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+        let basic = sequence(&[
+            &data,
+            &sequence(&[&ecdsa_with_sha256]),
+            &der(0x03, &signature),
+        ]);
+
+        let id_pkix_ocsp_basic = [
+            0x06, 0x09, 0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01, 0x01,
+        ];
+        let bytes = sequence(&[&id_pkix_ocsp_basic, &der(0x04, &basic)]);
+        // successful
+        sequence(&[&[0x0a, 0x01, 0x00], &der(0xa0, &bytes)])
+    }
+
+    /// Responder answering every request with the response, recording the bodies of the requests
+    async fn start_responder(response: Vec<u8>) -> (SocketAddr, Arc<Mutex<Vec<Vec<u8>>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = Vec::new();
+                let mut buf = [0; 4096];
+                // The request fits in one segment, but the body may come after the header
+                let body = loop {
+                    let n = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                    let text = String::from_utf8_lossy(&request).to_ascii_lowercase();
+                    let Some(end) = text.find("\r\n\r\n") else {
+                        continue;
+                    };
+                    let length: usize = text
+                        .lines()
+                        .find_map(|l| l.strip_prefix("content-length: "))
+                        .unwrap()
+                        .trim()
+                        .parse()
+                        .unwrap();
+                    if request.len() >= end + 4 + length {
+                        break request[end + 4..].to_vec();
+                    }
+                };
+                recorded.lock().unwrap().push(body);
+
+                let header = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/ocsp-response\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    response.len()
+                );
+                stream.write_all(header.as_bytes()).await.unwrap();
+                stream.write_all(&response).await.unwrap();
+            }
+        });
+        (address, requests)
+    }
+
+    #[tokio::test]
+    async fn read_from_file() {
+        let staple = fixture_staple(OcspStapling::File {
+            path: fixture("response.der"),
+        });
+        staple.refresh(&Connector::new(None)).await;
+        assert_eq!(
+            staple.response().unwrap().as_slice(),
+            std::fs::read(fixture("response.der")).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn fetch_from_responder() {
+        let response = std::fs::read(fixture("response.der")).unwrap();
+        let (responder, requests) = start_responder(response.clone()).await;
+        let staple = fixture_staple(OcspStapling::Fetch {
+            responder: Some(format!("http://{responder}/ocsp")),
+        });
+        staple.refresh(&Connector::new(None)).await;
+
+        assert_eq!(staple.response().unwrap().as_slice(), response);
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        openssl::ocsp::OcspRequest::from_der(&requests[0]).unwrap();
+    }
+
+    #[tokio::test]
+    async fn reject_invalid_responses() {
+        let unsuccessful = OcspResponse::create(OcspResponseStatus::TRY_LATER, None)
+            .unwrap()
+            .to_der()
+            .unwrap();
+        let (responder, _) = start_responder(unsuccessful).await;
+        let staple = fixture_staple(OcspStapling::Fetch {
+            responder: Some(format!("http://{responder}/")),
+        });
+        staple.refresh(&Connector::new(None)).await;
+        assert!(staple.response().is_none());
+
+        let (responder, _) = start_responder(b"garbage".to_vec()).await;
+        let staple = fixture_staple(OcspStapling::Fetch {
+            responder: Some(format!("http://{responder}/")),
+        });
+        staple.refresh(&Connector::new(None)).await;
+        assert!(staple.response().is_none());
+    }
+
+    #[tokio::test]
+    async fn keep_valid_response_on_failure() {
+        let path = std::env::temp_dir().join(format!("pingress-{}-ocsp.der", std::process::id()));
+        std::fs::copy(fixture("response.der"), &path).unwrap();
+        let staple = fixture_staple(OcspStapling::File {
+            path: path.to_string_lossy().to_string(),
+        });
+        staple.refresh(&Connector::new(None)).await;
+        assert!(staple.response().is_some());
+
+        std::fs::remove_file(&path).unwrap();
+        staple.refresh(&Connector::new(None)).await;
+        assert!(staple.response().is_some());
+    }
+
+    #[tokio::test]
+    async fn expire_at_next_update() {
+        let ca_key = ec_key();
+        let ca = ca_certificate("Test CA", &ca_key);
+        let leaf = certificate(&["ocsp.example.com"], &ec_key(), Some((&ca, &ca_key)));
+        let response = response_until(&leaf, &ca, &ca_key, Duration::from_secs(2));
+        let (responder, _) = start_responder(response).await;
+        let config = OcspStapling::Fetch {
+            responder: Some(format!("http://{responder}/")),
+        };
+        let staple = OcspStaple::new("test", &config, &leaf, &[ca]).unwrap();
+
+        staple.refresh(&Connector::new(None)).await;
+        assert!(staple.response().is_some());
+        // Refreshed before the next update rather than after REFRESH
+        assert!(staple.due(Instant::now() + Duration::from_secs(2)));
+
+        // No longer stapled once expired, even without a refresh
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        assert!(staple.response().is_none());
+    }
+
+    #[test]
+    fn parse_printed_time() {
+        let cases = [
+            ("Oct 18 11:28:24 2026 GMT", Ok("20261018112824Z")),
+            ("Feb  3 01:02:03.25 2030 GMT", Ok("20300203010203Z")),
+            ("Foo 18 11:28:24 2026 GMT", Err(())),
+            ("20261018112824Z", Err(())),
+        ];
+        for (printed, expected) in cases {
+            let parsed = generalized_time(printed).map_err(|_| ());
+            assert_eq!(parsed, expected.map(str::to_string), "{printed}");
+        }
+    }
+
+    #[test]
+    fn parse_responder_url() {
+        let cases = [
+            ("http://ocsp.example.com", Ok(("ocsp.example.com", 80, "/"))),
+            (
+                "http://ocsp.example.com:8080/ocsp",
+                Ok(("ocsp.example.com", 8080, "/ocsp")),
+            ),
+            ("http://[::1]/", Ok(("::1", 80, "/"))),
+            ("http://[::1]:8080/", Ok(("::1", 8080, "/"))),
+            ("https://ocsp.example.com/", Err(())),
+            ("http://ocsp.example.com:http/", Err(())),
+            ("http:///", Err(())),
+        ];
+        for (url, expected) in cases {
+            let parsed = parse_http_url(url)
+                .map(|(_, host, port, path)| (host, port, path))
+                .map_err(|_| ());
+            assert_eq!(parsed, expected, "{url}");
+        }
+    }
+
+    #[test]
+    fn responder_of_the_certificate() {
+        let staple = fixture_staple(OcspStapling::Fetch { responder: None });
+        assert!(staple.source == super::Source::Responder("http://ocsp.invalid/".to_string()));
+    }
+}
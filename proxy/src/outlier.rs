@@ -0,0 +1,110 @@
+use pingress_config::OutlierDetection;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Passive health of one endpoint, observed from the proxied requests
+pub(crate) struct Outlier {
+    config: OutlierDetection,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    consecutive_errors: usize,
+    /// Ejections in a row, which double the ejection time
+    ejections: u32,
+    ejected_until: Option<Instant>,
+}
+
+impl Outlier {
+    pub(crate) fn new(config: OutlierDetection) -> Self {
+        Self {
+            config,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    pub(crate) fn config(&self) -> &OutlierDetection {
+        &self.config
+    }
+
+    pub(crate) fn is_ejected(&self, now: Instant) -> bool {
+        let state = self.state.lock().unwrap();
+        state.ejected_until.is_some_and(|until| until > now)
+    }
+
+    /// Record the outcome of a request. Returns the ejection time when the endpoint is ejected.
+    ///
+    /// After an ejection, the endpoint is on probation: a single error ejects it again for
+    /// twice as long, and a success clears the backoff.
+    pub(crate) fn observe(&self, success: bool, now: Instant) -> Option<Duration> {
+        let mut state = self.state.lock().unwrap();
+        if success {
+            *state = State::default();
+            return None;
+        }
+        if state.ejected_until.is_some_and(|until| until > now) {
+            // In-flight requests of an ejected endpoint
+            return None;
+        }
+
+        state.consecutive_errors += 1;
+        let on_probation = state.ejected_until.is_some();
+        if !on_probation && state.consecutive_errors < self.config.consecutive_errors {
+            return None;
+        }
+
+        let ejection = Duration::from_millis(
+            self.config
+                .base_ejection_ms
+                .saturating_mul(1 << state.ejections.min(32))
+                .min(self.config.max_ejection_ms),
+        );
+        state.consecutive_errors = 0;
+        state.ejections += 1;
+        state.ejected_until = Some(now + ejection);
+        Some(ejection)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::outlier::Outlier;
+    use pingress_config::OutlierDetection;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn eject_with_backoff() {
+        let outlier = Outlier::new(OutlierDetection {
+            consecutive_errors: 3,
+            base_ejection_ms: 1_000,
+            max_ejection_ms: 3_000,
+        });
+        let now = Instant::now();
+        let secs = |s| now + Duration::from_secs(s);
+
+        assert_eq!(outlier.observe(false, now), None);
+        assert_eq!(outlier.observe(true, now), None);
+        assert_eq!(outlier.observe(false, now), None);
+        assert_eq!(outlier.observe(false, now), None);
+        assert_eq!(outlier.observe(false, now), Some(Duration::from_secs(1)));
+        assert!(outlier.is_ejected(now));
+
+        // Reinstated on probation, the next error doubles the ejection
+        assert!(!outlier.is_ejected(secs(1)));
+        assert_eq!(
+            outlier.observe(false, secs(1)),
+            Some(Duration::from_secs(2))
+        );
+        assert!(outlier.is_ejected(secs(2)));
+        assert_eq!(
+            outlier.observe(false, secs(3)),
+            Some(Duration::from_secs(3))
+        );
+
+        // A success clears the backoff
+        assert_eq!(outlier.observe(true, secs(6)), None);
+        assert_eq!(outlier.observe(false, secs(6)), None);
+        assert!(!outlier.is_ejected(secs(6)));
+    }
+}
@@ -1,73 +1,250 @@
-use crate::proxy_map::detail::{ExactProxyEntry, RegexProxyEntry};
-use pingress_config::{Backend, HttpPath, PingressConfiguration, Port};
-use regex::Regex;
+use crate::error::ConfigError;
+use crate::host::{normalize_host, parse_host_pattern, wildcard_suffix, HostPattern};
+use crate::metrics::CONFIG_RULE_ERRORS;
+use crate::proxy_map::detail::PathTable;
+use crate::retry::Retry;
+use crate::static_response::StaticResponse;
+use crate::upstream::Upstream;
+use log::warn;
+use pingress_config::{Backend, ClientAuth, HttpPath, PingressConfiguration, Timeouts};
 use std::collections::HashMap;
+use std::sync::Arc;
 
-pub(crate) struct ProxyMap {
-    exact_proxy_entries: HashMap<String, ExactProxyEntry>,
-    regex_proxy_entries: Vec<RegexProxyEntry>,
+/// Route table of a configuration
+pub struct ProxyMap {
+    exact_proxy_entries: HashMap<String, PathTable>,
+    /// Wildcard rules keyed by the host suffix after `*.`
+    ///
+    /// A wildcard covers exactly one label, so the only candidate for a host is found by
+    /// stripping its first label.
+    wildcard_proxy_entries: HashMap<String, PathTable>,
+    /// Rules without a host, matching any host
+    any_host_proxy_entries: PathTable,
+    /// Every upstream keyed by [Upstream::key], shared by the rules routing to it
+    upstreams: HashMap<String, Arc<Upstream>>,
+    /// Route of the requests matching no rule
+    default_route: Option<Arc<Route>>,
+    /// Response to the requests matching no route, `None` for the default 404 response
+    not_found: Option<StaticResponse>,
 }
 
-impl ProxyMap {
-    pub(crate) fn get_backend(&self, host: &str, path: &str) -> Option<String> {
-        if let Some(entry) = self.exact_proxy_entries.get(host) {
-            if entry.path.is_match(path) {
-                return Some(entry.backend_host.clone());
-            }
+/// Destination and policies of the requests matching a path rule
+pub struct Route {
+    pub(crate) target: Target,
+    pub(crate) retry: Option<Retry>,
+    pub(crate) timeouts: Timeouts,
+    /// Client certificate authentication of the host of the rule
+    pub(crate) client_auth: Option<ClientAuth>,
+}
+
+pub(crate) enum Target {
+    Upstream(Arc<Upstream>),
+    /// Response served without proxying the request
+    Response(Box<StaticResponse>),
+}
+
+impl Route {
+    /// Upstream of the route, `None` when the proxy responds by itself
+    pub fn upstream(&self) -> Option<&Arc<Upstream>> {
+        match &self.target {
+            Target::Upstream(upstream) => Some(upstream),
+            Target::Response(_) => None,
         }
+    }
+}
 
-        for entry in &self.regex_proxy_entries {
-            if entry.pattern.is_match(host) && entry.path.is_match(path) {
-                return Some(entry.backend_host.clone());
-            }
+impl ProxyMap {
+    pub(crate) fn upstreams(&self) -> impl Iterator<Item = &Arc<Upstream>> {
+        self.upstreams.values()
+    }
+
+    /// Custom response to the requests matching no route
+    pub(crate) fn not_found(&self) -> Option<&StaticResponse> {
+        self.not_found.as_ref()
+    }
+
+    /// Route of the same host and path, to take over its state on a reload
+    fn same_route(&self, pattern: Option<&HostPattern>, path: &HttpPath) -> Option<&Arc<Route>> {
+        let paths = match pattern {
+            Some(HostPattern::Exact(host)) => self.exact_proxy_entries.get(*host)?,
+            Some(HostPattern::Wildcard(suffix)) => self.wildcard_proxy_entries.get(*suffix)?,
+            None => &self.any_host_proxy_entries,
+        };
+        paths.find(path)
+    }
+
+    /// Route of a request to the `host` (`Host` header or authority) and the `path`
+    pub fn get_route(&self, host: &str, path: &str) -> Option<Arc<Route>> {
+        let host = normalize_host(host);
+
+        if let Some(route) = self
+            .exact_proxy_entries
+            .get(host.as_str())
+            .and_then(|paths| paths.get(path))
+        {
+            return Some(route.clone());
         }
 
-        None
+        wildcard_suffix(host.as_str())
+            .and_then(|suffix| self.wildcard_proxy_entries.get(suffix))
+            .and_then(|paths| paths.get(path))
+            .or_else(|| self.any_host_proxy_entries.get(path))
+            .or(self.default_route.as_ref())
+            .cloned()
     }
 }
 
 impl From<PingressConfiguration> for ProxyMap {
     fn from(value: PingressConfiguration) -> Self {
-        let rules: Vec<(String, Backend, HttpPath)> = value
-            .rules
-            .into_iter()
-            .map(|r| (r.host.clone(), r.backend.clone(), r.path.clone()))
-            .collect();
-        let mut exact = HashMap::new();
-        let mut regex = Vec::new();
-
-        for (host, backend, path) in rules {
-            let backend_host = match backend {
-                Backend::Service {
-                    name,
-                    namespace,
-                    port,
-                } => format!(
-                    "{name}.{namespace}:{}",
-                    match port {
-                        Port::Number(n) => n,
-                    }
-                ),
+        Self::new(value, None)
+    }
+}
+
+impl ProxyMap {
+    /// Build the route table of the configuration.
+    ///
+    /// A rule that cannot be built is skipped, so that the other rules are still served.
+    /// Its requests fall back to the other rules of the host, the rules without a host, or the
+    /// default backend.
+    ///
+    /// The upstreams and the retry budgets take over the state of the same backends and routes
+    /// in the `previous` configuration, so that a reload resets no endpoint health, outlier
+    /// ejection or in-flight request count.
+    pub(crate) fn new(value: PingressConfiguration, previous: Option<&ProxyMap>) -> Self {
+        let mut exact: HashMap<String, PathTable> = HashMap::new();
+        let mut wildcard: HashMap<String, PathTable> = HashMap::new();
+        let mut any_host = PathTable::default();
+        let mut upstreams = HashMap::new();
+
+        for rule in value.rules {
+            let host = rule.host.as_deref().unwrap_or_default();
+            let pattern = match rule.host.as_deref().map(parse_host_pattern).transpose() {
+                Ok(pattern) => pattern,
+                Err(e) => {
+                    skip_rule(host, &ConfigError::InvalidHost(host.to_string(), e));
+                    continue;
+                }
+            };
+            let target = match target(&mut upstreams, rule.backend, previous) {
+                Ok(target) => target,
+                Err(e) => {
+                    skip_rule(host, &e);
+                    continue;
+                }
             };
 
-            if host.contains("*") {
-                regex.push(RegexProxyEntry {
-                    pattern: Regex::new(host.replace("*", ".+").as_str()).unwrap(),
-                    path,
-                    backend_host,
-                });
-            } else {
-                exact.insert(host, ExactProxyEntry { path, backend_host });
+            let mut retry = rule.retry.map(Retry::new);
+            let previous_retry = previous
+                .and_then(|p| p.same_route(pattern.as_ref(), &rule.path))
+                .and_then(|r| r.retry.as_ref());
+            if let (Some(retry), Some(previous)) = (retry.as_mut(), previous_retry) {
+                retry.inherit(previous);
             }
+
+            let entries = match pattern {
+                Some(HostPattern::Exact(host)) => exact.entry(host.to_string()).or_default(),
+                Some(HostPattern::Wildcard(suffix)) => {
+                    wildcard.entry(suffix.to_string()).or_default()
+                }
+                None => &mut any_host,
+            };
+            let route = Route {
+                target,
+                retry,
+                timeouts: rule.timeouts,
+                client_auth: rule.tls.and_then(|t| t.client_auth),
+            };
+            entries.insert(rule.path, Arc::new(route));
         }
 
+        let default_route = value.default_backend.and_then(|backend| {
+            match target(&mut upstreams, backend, previous) {
+                Ok(target) => Some(Arc::new(Route {
+                    target,
+                    retry: None,
+                    timeouts: Timeouts::default(),
+                    client_auth: None,
+                })),
+                Err(e) => {
+                    skip_rule("default", &e);
+                    None
+                }
+            }
+        });
+        let not_found = value.not_found_body.and_then(|body| {
+            match StaticResponse::new(404, None, None, body) {
+                Ok(response) => Some(response),
+                Err(e) => {
+                    skip_rule(
+                        "not-found",
+                        &ConfigError::InvalidResponse(404, e.to_string()),
+                    );
+                    None
+                }
+            }
+        });
+
         Self {
             exact_proxy_entries: exact,
-            regex_proxy_entries: regex,
+            wildcard_proxy_entries: wildcard,
+            any_host_proxy_entries: any_host,
+            upstreams: upstreams
+                .into_iter()
+                .map(|(key, (_, upstream))| (key, upstream))
+                .collect(),
+            default_route,
+            not_found,
+        }
+    }
+}
+
+fn skip_rule(host: &str, e: &ConfigError) {
+    warn!("Skip a rule of '{host}': {e}");
+    CONFIG_RULE_ERRORS.with_label_values(&[host]).inc();
+}
+
+fn target(
+    upstreams: &mut HashMap<String, (Backend, Arc<Upstream>)>,
+    backend: Backend,
+    previous: Option<&ProxyMap>,
+) -> Result<Target, ConfigError> {
+    match backend {
+        Backend::Response {
+            status,
+            location,
+            content_type,
+            body,
+        } => {
+            let response =
+                StaticResponse::new(status, location.as_deref(), content_type.as_deref(), body)
+                    .map_err(|e| ConfigError::InvalidResponse(status, e.to_string()))?;
+            Ok(Target::Response(Box::new(response)))
         }
+        backend => shared_upstream(upstreams, backend, previous).map(Target::Upstream),
     }
 }
 
+/// Upstream of the backend, shared with the rules already routing to it.
+///
+/// The settings of the backend in the first rule apply, those of the other rules are ignored.
+fn shared_upstream(
+    upstreams: &mut HashMap<String, (Backend, Arc<Upstream>)>,
+    backend: Backend,
+    previous: Option<&ProxyMap>,
+) -> Result<Arc<Upstream>, ConfigError> {
+    let key = Upstream::key(&backend);
+    if let Some((settings, upstream)) = upstreams.get(&key) {
+        if *settings != backend {
+            warn!("Ignore the settings of {key} differing from those of its first rule");
+        }
+        return Ok(upstream.clone());
+    }
+    let previous = previous.and_then(|p| p.upstreams.get(&key));
+    let upstream = Arc::new(Upstream::new(backend.clone(), previous.map(Arc::as_ref))?);
+    upstreams.insert(key, (backend, upstream.clone()));
+    Ok(upstream)
+}
+
 trait IsMatch {
     fn is_match(&self, haystack: &str) -> bool;
 }
@@ -75,24 +252,516 @@ trait IsMatch {
 impl IsMatch for HttpPath {
     fn is_match(&self, haystack: &str) -> bool {
         match self {
-            HttpPath::Prefix(prefix) => haystack.starts_with(prefix),
+            HttpPath::Prefix(prefix) => {
+                let mut haystack = path_elements(haystack);
+                path_elements(prefix).all(|e| haystack.next() == Some(e))
+            }
             HttpPath::Exact(exact) => haystack == exact,
+            HttpPath::ImplementationSpecific(prefix) => haystack.starts_with(prefix),
         }
     }
 }
 
+/// Elements of a path split by `/`. Empty elements, including a trailing slash, are ignored.
+fn path_elements(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|e| !e.is_empty())
+}
+
 mod detail {
+    use crate::proxy_map::{path_elements, IsMatch, Route};
     use pingress_config::HttpPath;
-    use regex::Regex;
+    use std::sync::Arc;
 
-    pub(super) struct RegexProxyEntry {
-        pub(super) pattern: Regex,
-        pub(super) path: HttpPath,
-        pub(super) backend_host: String,
+    /// Path rules of one host, kept in the order they are matched.
+    ///
+    /// Exact paths come first, then prefixes (`Prefix` and `ImplementationSpecific`)
+    /// from the longest. When a path is given twice with the same type, the earlier rule wins.
+    #[derive(Default)]
+    pub(super) struct PathTable {
+        entries: Vec<(HttpPath, Arc<Route>)>,
     }
 
-    pub(super) struct ExactProxyEntry {
-        pub(super) path: HttpPath,
-        pub(super) backend_host: String,
+    impl PathTable {
+        pub(super) fn insert(&mut self, path: HttpPath, route: Arc<Route>) {
+            if self.entries.iter().any(|(p, _)| is_same(p, &path)) {
+                return;
+            }
+            let position = self
+                .entries
+                .iter()
+                .position(|(p, _)| precedes(&path, p))
+                .unwrap_or(self.entries.len());
+            self.entries.insert(position, (path, route));
+        }
+
+        /// Route of the same path, as compared when inserting
+        pub(super) fn find(&self, path: &HttpPath) -> Option<&Arc<Route>> {
+            self.entries
+                .iter()
+                .find(|(p, _)| is_same(p, path))
+                .map(|(_, route)| route)
+        }
+
+        pub(super) fn get(&self, path: &str) -> Option<&Arc<Route>> {
+            self.entries
+                .iter()
+                .find(|(p, _)| p.is_match(path))
+                .map(|(_, route)| route)
+        }
+    }
+
+    fn is_same(a: &HttpPath, b: &HttpPath) -> bool {
+        match (a, b) {
+            (HttpPath::Prefix(a), HttpPath::Prefix(b)) => path_elements(a).eq(path_elements(b)),
+            _ => a == b,
+        }
+    }
+
+    fn precedes(a: &HttpPath, b: &HttpPath) -> bool {
+        match (prefix_length(a), prefix_length(b)) {
+            (None, Some(_)) => true,
+            (Some(a), Some(b)) => a > b,
+            _ => false,
+        }
+    }
+
+    /// Length of a prefix rule, `None` for an exact rule
+    fn prefix_length(path: &HttpPath) -> Option<usize> {
+        match path {
+            HttpPath::Exact(_) => None,
+            HttpPath::Prefix(prefix) => Some(prefix.trim_end_matches('/').len()),
+            HttpPath::ImplementationSpecific(prefix) => Some(prefix.len()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::metrics::CONFIG_RULE_ERRORS;
+    use crate::proxy_map::ProxyMap;
+    use pingora::http::Method;
+    use pingress_config::{
+        Backend, Endpoint, HttpPath, LoadBalancing, PathRule, PingressConfiguration, Port,
+        Protocol, RetryBudget, RetryOn, RetryPolicy, Timeouts, UpstreamTls,
+    };
+    use std::sync::Arc;
+
+    fn rule(host: &str, path: HttpPath, backend: &str) -> PathRule {
+        PathRule {
+            host: Some(host.to_string()),
+            tls: None,
+            path,
+            backend: Backend::Service {
+                name: backend.to_string(),
+                namespace: "default".to_string(),
+                port: Port::Number(80),
+                endpoints: Vec::new(),
+                load_balancing: LoadBalancing::default(),
+                health_check: None,
+                outlier_detection: None,
+                circuit_breaker: None,
+                protocol: Protocol::default(),
+                tls: None,
+            },
+            retry: None,
+            timeouts: Timeouts::default(),
+            http2: true,
+        }
+    }
+
+    fn configuration(rules: Vec<PathRule>) -> PingressConfiguration {
+        PingressConfiguration {
+            rules,
+            default_backend: None,
+            not_found_body: None,
+            default_tls: None,
+        }
+    }
+
+    fn prefix(path: &str) -> HttpPath {
+        HttpPath::Prefix(path.to_string())
+    }
+
+    fn exact(path: &str) -> HttpPath {
+        HttpPath::Exact(path.to_string())
+    }
+
+    fn implementation_specific(path: &str) -> HttpPath {
+        HttpPath::ImplementationSpecific(path.to_string())
+    }
+
+    fn assert_routes(rules: Vec<PathRule>, cases: &[(&str, &str, Option<&str>)]) {
+        let proxy_map = ProxyMap::from(configuration(rules));
+        for (host, path, expected) in cases {
+            assert_eq!(
+                proxy_map
+                    .get_route(host, path)
+                    .and_then(|r| r.upstream().map(|u| u.select(b"").address)),
+                expected.map(|b| format!("{b}.default:80")),
+                "{host}{path}"
+            );
+        }
+    }
+
+    #[test]
+    fn multiple_paths_per_host() {
+        assert_routes(
+            vec![
+                rule("foo.com", prefix("/api"), "api"),
+                rule("foo.com", prefix("/web"), "web"),
+            ],
+            &[
+                ("foo.com", "/api", Some("api")),
+                ("foo.com", "/api/v1", Some("api")),
+                ("foo.com", "/web", Some("web")),
+                ("foo.com", "/other", None),
+            ],
+        );
+    }
+
+    #[test]
+    fn path_precedence() {
+        // (rules, path, expected backend)
+        let cases = [
+            (
+                vec![
+                    rule("foo.com", prefix("/foo"), "prefix"),
+                    rule("foo.com", exact("/foo"), "exact"),
+                ],
+                "/foo",
+                Some("exact"),
+            ),
+            (
+                vec![
+                    rule("foo.com", prefix("/foo"), "prefix"),
+                    rule("foo.com", exact("/foo"), "exact"),
+                ],
+                "/foo/bar",
+                Some("prefix"),
+            ),
+            (
+                vec![
+                    rule("foo.com", prefix("/"), "root"),
+                    rule("foo.com", prefix("/foo"), "foo"),
+                    rule("foo.com", prefix("/foo/bar"), "foobar"),
+                ],
+                "/foo/bar/baz",
+                Some("foobar"),
+            ),
+            (
+                vec![
+                    rule("foo.com", prefix("/foo/bar"), "foobar"),
+                    rule("foo.com", prefix("/foo"), "foo"),
+                    rule("foo.com", prefix("/"), "root"),
+                ],
+                "/foo/baz",
+                Some("foo"),
+            ),
+            (
+                vec![
+                    rule("foo.com", prefix("/foo/bar"), "foobar"),
+                    rule("foo.com", prefix("/"), "root"),
+                ],
+                "/baz",
+                Some("root"),
+            ),
+            (
+                vec![
+                    rule("foo.com", exact("/foo"), "first"),
+                    rule("foo.com", exact("/foo"), "second"),
+                ],
+                "/foo",
+                Some("first"),
+            ),
+            (vec![rule("foo.com", exact("/foo"), "exact")], "/foo/", None),
+        ];
+
+        for (rules, path, expected) in cases {
+            assert_routes(rules, &[("foo.com", path, expected)]);
+        }
+    }
+
+    /// Examples of the Ingress v1 specification
+    #[test]
+    fn path_matching_conformance() {
+        // (rules, path, expected backend)
+        let cases = [
+            (vec![prefix("/")], "/", Some("0")),
+            (vec![prefix("/")], "/foo/bar", Some("0")),
+            (vec![exact("/foo")], "/foo", Some("0")),
+            (vec![exact("/foo")], "/bar", None),
+            (vec![exact("/foo")], "/foo/", None),
+            (vec![exact("/foo/")], "/foo", None),
+            (vec![prefix("/foo")], "/foo", Some("0")),
+            (vec![prefix("/foo")], "/foo/", Some("0")),
+            (vec![prefix("/foo/")], "/foo", Some("0")),
+            (vec![prefix("/foo/")], "/foo/", Some("0")),
+            (vec![prefix("/aaa/bb")], "/aaa/bbb", None),
+            (vec![prefix("/aaa/bbb")], "/aaa/bbb", Some("0")),
+            (vec![prefix("/aaa/bbb/")], "/aaa/bbb", Some("0")),
+            (vec![prefix("/aaa/bbb")], "/aaa/bbb/", Some("0")),
+            (vec![prefix("/aaa/bbb")], "/aaa/bbb/ccc", Some("0")),
+            (vec![prefix("/aaa/bbb")], "/aaa/bbbxyz", None),
+            (vec![prefix("/"), prefix("/aaa")], "/aaa/ccc", Some("1")),
+            (
+                vec![prefix("/"), prefix("/aaa"), prefix("/aaa/bbb")],
+                "/aaa/bbb",
+                Some("2"),
+            ),
+            (
+                vec![prefix("/"), prefix("/aaa"), prefix("/aaa/bbb")],
+                "/ccc",
+                Some("0"),
+            ),
+            (vec![prefix("/aaa")], "/ccc", None),
+            (vec![prefix("/foo"), exact("/foo")], "/foo", Some("1")),
+            (vec![implementation_specific("/foo")], "/foobar", Some("0")),
+            (vec![implementation_specific("/foo/")], "/foo", None),
+            (
+                vec![prefix("/foo"), implementation_specific("/foo/b")],
+                "/foo/bar",
+                Some("1"),
+            ),
+        ];
+
+        for (paths, path, expected) in cases {
+            let rules = paths
+                .into_iter()
+                .enumerate()
+                .map(|(i, p)| rule("foo.com", p, i.to_string().as_str()))
+                .collect();
+            assert_routes(rules, &[("foo.com", path, expected)]);
+        }
+    }
+
+    #[test]
+    fn wildcard_host() {
+        assert_routes(
+            vec![
+                rule("*.foo.com", prefix("/"), "wildcard"),
+                rule("bar.foo.com", prefix("/"), "exact"),
+            ],
+            &[
+                ("bar.foo.com", "/", Some("exact")),
+                ("baz.foo.com", "/", Some("wildcard")),
+                ("BAZ.foo.com:8080", "/", Some("wildcard")),
+                ("foo.com", "/", None),
+                ("baz.bar.foo.com", "/", None),
+                ("foo-foo.com", "/", None),
+                ("baz.foo.com.attacker.net", "/", None),
+            ],
+        );
+    }
+
+    #[test]
+    fn skip_invalid_rules() {
+        let errors = |host: &str| CONFIG_RULE_ERRORS.with_label_values(&[host]).get();
+        let invalid_hosts = ["*", "foo.*.com", "*foo.com", "foo_bar.com"];
+        let before: Vec<u64> = invalid_hosts.iter().map(|h| errors(h)).collect();
+
+        let mut unresolved = rule("unresolved.com", prefix("/"), "unresolved");
+        let Backend::Service { port, .. } = &mut unresolved.backend else {
+            unreachable!()
+        };
+        *port = Port::Name("http".to_string());
+        let mut invalid_response = rule("response.com", prefix("/"), "response");
+        invalid_response.backend = Backend::Response {
+            status: 1000,
+            location: None,
+            content_type: None,
+            body: String::new(),
+        };
+        let mut rules: Vec<PathRule> = invalid_hosts
+            .iter()
+            .map(|host| rule(host, prefix("/"), "backend"))
+            .collect();
+        rules.extend([
+            unresolved,
+            rule("unresolved.com", prefix("/"), "fallback"),
+            invalid_response,
+            rule("valid.com", prefix("/"), "valid"),
+        ]);
+        let proxy_map = ProxyMap::from(configuration(rules));
+
+        let address = |host| {
+            proxy_map
+                .get_route(host, "/")
+                .and_then(|r| r.upstream().map(|u| u.select(b"").address))
+        };
+        assert_eq!(address("valid.com").as_deref(), Some("valid.default:80"));
+        // The next rule of the same path takes over
+        assert_eq!(
+            address("unresolved.com").as_deref(),
+            Some("fallback.default:80")
+        );
+        assert!(proxy_map.get_route("response.com", "/").is_none());
+        for (host, before) in invalid_hosts.iter().zip(before) {
+            assert_eq!(errors(host), before + 1, "{host}");
+        }
+    }
+
+    #[test]
+    fn skip_upstream_with_invalid_tls() {
+        let mut missing_ca = rule("missing-ca.com", prefix("/"), "missing-ca");
+        let Backend::Service { protocol, tls, .. } = &mut missing_ca.backend else {
+            unreachable!()
+        };
+        *protocol = Protocol::Https;
+        *tls = Some(UpstreamTls {
+            ca: Some("/nonexistent/ca.crt".to_string()),
+            ..UpstreamTls::default()
+        });
+        let proxy_map = ProxyMap::from(configuration(vec![
+            missing_ca,
+            rule("valid.com", prefix("/"), "valid"),
+        ]));
+
+        assert!(proxy_map.get_route("missing-ca.com", "/").is_none());
+        assert!(proxy_map.get_route("valid.com", "/").is_some());
+        assert_eq!(proxy_map.upstreams().count(), 1);
+    }
+
+    #[test]
+    fn balance_over_endpoints() {
+        let mut rules = vec![rule("foo.com", prefix("/"), "backend")];
+        let Backend::Service { endpoints, .. } = &mut rules[0].backend else {
+            unreachable!()
+        };
+        for address in ["10.0.0.1", "10.0.0.2", "fd00::1"] {
+            endpoints.push(Endpoint {
+                address: address.to_string(),
+                port: 8080,
+                weight: 1,
+            });
+        }
+        let proxy_map = ProxyMap::from(configuration(rules));
+
+        let mut selected: Vec<String> = (0..6)
+            .filter_map(|_| proxy_map.get_route("foo.com", "/"))
+            .filter_map(|r| r.upstream().map(|u| u.select(b"").address))
+            .collect();
+        selected.sort();
+        selected.dedup();
+        assert_eq!(
+            selected,
+            vec!["10.0.0.1:8080", "10.0.0.2:8080", "[fd00::1]:8080"]
+        );
+    }
+
+    #[test]
+    fn multiple_paths_per_wildcard_host() {
+        assert_routes(
+            vec![
+                rule("*.foo.com", prefix("/"), "root"),
+                rule("*.foo.com", prefix("/api"), "api"),
+                rule("*.foo.com", exact("/api/health"), "health"),
+            ],
+            &[
+                ("bar.foo.com", "/", Some("root")),
+                ("bar.foo.com", "/api/users", Some("api")),
+                ("bar.foo.com", "/api/health", Some("health")),
+            ],
+        );
+    }
+
+    #[test]
+    fn default_backend() {
+        let mut configuration = configuration(vec![
+            rule("foo.com", prefix("/api"), "api"),
+            rule("*.bar.com", prefix("/"), "bar"),
+        ]);
+        configuration.default_backend = Some(rule("", prefix("/"), "default").backend);
+        let proxy_map = ProxyMap::from(configuration);
+
+        for (host, path, expected) in [
+            ("foo.com", "/api", "api"),
+            ("foo.com", "/web", "default"),
+            ("baz.bar.com", "/", "bar"),
+            ("baz.com", "/", "default"),
+        ] {
+            assert_eq!(
+                proxy_map
+                    .get_route(host, path)
+                    .and_then(|r| r.upstream().map(|u| u.select(b"").address)),
+                Some(format!("{expected}.default:80")),
+                "{host}{path}"
+            );
+        }
+    }
+
+    #[test]
+    fn any_host() {
+        let mut any_host = rule("", prefix("/"), "any");
+        any_host.host = None;
+        assert_routes(
+            vec![
+                rule("foo.com", prefix("/api"), "exact"),
+                rule("*.foo.com", prefix("/api"), "wildcard"),
+                any_host,
+            ],
+            &[
+                ("foo.com", "/api", Some("exact")),
+                ("foo.com", "/web", Some("any")),
+                ("bar.foo.com", "/api", Some("wildcard")),
+                ("bar.foo.com", "/web", Some("any")),
+                ("baz.com", "/", Some("any")),
+            ],
+        );
+    }
+
+    #[test]
+    fn inherit_retry_budget() {
+        let with_retry = |path| {
+            let mut rule = rule("foo.com", prefix(path), "foo");
+            rule.retry = Some(RetryPolicy {
+                retry_on: vec![RetryOn::ConnectFailure],
+                budget: RetryBudget {
+                    percent: 0,
+                    min_concurrency: 1,
+                },
+                ..RetryPolicy::default()
+            });
+            rule
+        };
+        let try_retry = |proxy_map: &ProxyMap, path| {
+            let route = proxy_map.get_route("foo.com", path).unwrap();
+            let retry = route.retry.as_ref().unwrap();
+            retry.try_retry(RetryOn::ConnectFailure, &Method::GET, 1)
+        };
+        let previous = ProxyMap::from(configuration(vec![with_retry("/api")]));
+        let in_flight = try_retry(&previous, "/api");
+        assert!(in_flight.is_some());
+
+        let reloaded = ProxyMap::new(
+            configuration(vec![with_retry("/api/"), with_retry("/web")]),
+            Some(&previous),
+        );
+        assert!(try_retry(&reloaded, "/api").is_none());
+        assert!(try_retry(&reloaded, "/web").is_some());
+        drop(in_flight);
+        assert!(try_retry(&reloaded, "/api").is_some());
+    }
+
+    #[test]
+    fn first_settings_of_shared_backend() {
+        let mut other = rule("bar.com", prefix("/"), "shared");
+        let Backend::Service { load_balancing, .. } = &mut other.backend else {
+            unreachable!()
+        };
+        *load_balancing = LoadBalancing::LeastConnections;
+        let proxy_map = ProxyMap::from(configuration(vec![
+            rule("foo.com", prefix("/"), "shared"),
+            other,
+        ]));
+
+        let upstream = |host| {
+            let route = proxy_map.get_route(host, "/").unwrap();
+            route.upstream().unwrap().clone()
+        };
+        assert!(Arc::ptr_eq(&upstream("foo.com"), &upstream("bar.com")));
+        assert_eq!(
+            upstream("bar.com").load_balancing(),
+            &LoadBalancing::RoundRobin
+        );
     }
 }
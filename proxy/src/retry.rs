@@ -0,0 +1,168 @@
+use pingora::http::Method;
+use pingress_config::{RetryOn, RetryPolicy};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Retry policy of a route, with the state of its budget
+pub(crate) struct Retry {
+    policy: RetryPolicy,
+    /// Concurrent requests of the route
+    requests: Arc<AtomicUsize>,
+    /// Concurrent retried requests of the route
+    retries: Arc<AtomicUsize>,
+}
+
+/// Counts a request or a retry as in flight until dropped
+pub(crate) struct InFlight(Arc<AtomicUsize>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+impl Retry {
+    pub(crate) fn new(policy: RetryPolicy) -> Self {
+        Self {
+            policy,
+            requests: Arc::new(AtomicUsize::new(0)),
+            retries: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub(crate) fn policy(&self) -> &RetryPolicy {
+        &self.policy
+    }
+
+    /// Count the requests and retries in flight of the same route in a previous configuration,
+    /// so that a reload does not reset the budget
+    pub(crate) fn inherit(&mut self, previous: &Retry) {
+        self.requests = previous.requests.clone();
+        self.retries = previous.retries.clone();
+    }
+
+    /// Count a request of the route, which raises the budget
+    pub(crate) fn start_request(&self) -> InFlight {
+        self.requests.fetch_add(1, Ordering::AcqRel);
+        InFlight(self.requests.clone())
+    }
+
+    /// Allow another attempt of a request after `attempts` failed on `condition`.
+    ///
+    /// The request counts against the budget until the returned value is dropped.
+    pub(crate) fn try_retry(
+        &self,
+        condition: RetryOn,
+        method: &Method,
+        attempts: usize,
+    ) -> Option<InFlight> {
+        if !self.policy.retry_on.contains(&condition) || attempts >= self.policy.attempts {
+            return None;
+        }
+        if !self.policy.non_idempotent && !is_idempotent(method) {
+            return None;
+        }
+
+        let budget = self
+            .policy
+            .budget
+            .min_concurrency
+            .max(self.requests.load(Ordering::Acquire) * self.policy.budget.percent / 100);
+        if self.retries.fetch_add(1, Ordering::AcqRel) >= budget {
+            self.retries.fetch_sub(1, Ordering::AcqRel);
+            return None;
+        }
+        Some(InFlight(self.retries.clone()))
+    }
+
+    /// Delay before the `retry`-th retry
+    pub(crate) fn backoff(&self, retry: usize) -> Duration {
+        let exponent = retry.saturating_sub(1).min(32) as u32;
+        Duration::from_millis(
+            self.policy
+                .backoff_ms
+                .saturating_mul(1 << exponent)
+                .min(self.policy.max_backoff_ms),
+        )
+    }
+}
+
+/// Methods that can be sent again without changing the outcome (RFC 9110)
+fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE | Method::PUT | Method::DELETE
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::retry::Retry;
+    use pingora::http::Method;
+    use pingress_config::{RetryBudget, RetryOn, RetryPolicy};
+    use std::time::Duration;
+
+    #[test]
+    fn retry_conditions() {
+        let retry = Retry::new(RetryPolicy {
+            retry_on: vec![RetryOn::ConnectFailure],
+            attempts: 2,
+            ..RetryPolicy::default()
+        });
+
+        assert!(retry
+            .try_retry(RetryOn::ConnectFailure, &Method::GET, 1)
+            .is_some());
+        assert!(retry
+            .try_retry(RetryOn::GatewayError, &Method::GET, 1)
+            .is_none());
+        assert!(retry
+            .try_retry(RetryOn::ConnectFailure, &Method::GET, 2)
+            .is_none());
+        assert!(retry
+            .try_retry(RetryOn::ConnectFailure, &Method::POST, 1)
+            .is_none());
+    }
+
+    #[test]
+    fn budget() {
+        let retry = Retry::new(RetryPolicy {
+            budget: RetryBudget {
+                percent: 50,
+                min_concurrency: 1,
+            },
+            ..RetryPolicy::default()
+        });
+        let requests: Vec<_> = (0..4).map(|_| retry.start_request()).collect();
+
+        let first = retry.try_retry(RetryOn::ConnectFailure, &Method::GET, 1);
+        let second = retry.try_retry(RetryOn::ConnectFailure, &Method::GET, 1);
+        assert!(first.is_some() && second.is_some());
+        assert!(retry
+            .try_retry(RetryOn::ConnectFailure, &Method::GET, 1)
+            .is_none());
+
+        drop(first);
+        assert!(retry
+            .try_retry(RetryOn::ConnectFailure, &Method::GET, 1)
+            .is_some());
+
+        // Only the minimum once the requests are over
+        drop(requests);
+        assert!(retry
+            .try_retry(RetryOn::ConnectFailure, &Method::GET, 1)
+            .is_none());
+    }
+
+    #[test]
+    fn backoff() {
+        let retry = Retry::new(RetryPolicy {
+            backoff_ms: 25,
+            max_backoff_ms: 80,
+            ..RetryPolicy::default()
+        });
+        let delays: Vec<_> = (1..=4).map(|r| retry.backoff(r)).collect();
+        assert_eq!(delays, [25, 50, 80, 80].map(Duration::from_millis).to_vec());
+    }
+}
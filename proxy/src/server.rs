@@ -0,0 +1,206 @@
+use crate::config::{load_configuration, LoadedConfiguration};
+use crate::health_check::HealthCheckService;
+use crate::http_proxy::PingressHttpProxy;
+use crate::ocsp::OcspService;
+use crate::proxy_map::ProxyMap;
+use crate::tls::{GetTls, TlsMap};
+use crate::tls_policy::ClientSubjects;
+use crate::watcher::run_reload;
+use arc_swap::ArcSwap;
+use async_trait::async_trait;
+use clap::Parser;
+use log::error;
+use pingora::apps::HttpServerOptions;
+use pingora::listeners::{TlsAccept, TlsSettings};
+use pingora::protocols::ssl::server::TlsAcceptCallbacks;
+use pingora::server::configuration::ServerConf;
+use pingora::server::Server;
+use pingora::services::background::background_service;
+use pingora::services::Service;
+use pingora::tls::ext::{ssl_add_chain_cert, ssl_use_certificate, ssl_use_private_key};
+use pingora::tls::ssl::{select_next_proto, AlpnError, NameType, SslRef};
+use std::path::Path;
+use std::sync::Arc;
+use std::thread::spawn;
+
+/// Command line arguments of the proxy server
+#[derive(Debug, Parser)]
+pub struct Args {
+    /// Listen host and port number
+    #[clap(long, default_value = "0.0.0.0:80")]
+    pub listen_http: String,
+
+    /// Listen host and port number
+    #[clap(long, default_value = "0.0.0.0:443")]
+    pub listen_https: String,
+
+    /// Serve HTTP/2 with prior knowledge (h2c) instead of HTTP/1.1 on the plain text listener
+    #[clap(long)]
+    pub h2c: bool,
+
+    /// Path to configuration file
+    #[clap(long)]
+    pub config: String,
+
+    /// Directories to watch for changes of configuration and certificates
+    /// (default: directory of --config)
+    #[clap(long, value_delimiter = ',', num_args = 0..)]
+    pub watch: Vec<String>,
+}
+
+/// Run the proxy server until it is shut down
+pub fn run(args: Args) {
+    let mut server = Server::new(None).unwrap();
+    server.bootstrap();
+
+    // The watcher loads the configuration once it is fixed
+    let config = load_configuration(args.config.as_str(), None).unwrap_or_else(|e| {
+        error!("Error: Start without routes: {e}");
+        LoadedConfiguration::empty()
+    });
+    let proxy_map = Arc::new(ArcSwap::from_pointee(config.proxy_map));
+    let tls = Arc::new(ArcSwap::from_pointee(config.tls));
+
+    let services: Vec<Box<dyn Service>> =
+        create_http_proxies(&server.configuration, &args, proxy_map.clone(), tls.clone());
+
+    let mut prometheus_service_http =
+        pingora::services::listening::Service::prometheus_http_service();
+    prometheus_service_http.add_tcp("127.0.0.1:9090");
+
+    server.add_service(prometheus_service_http);
+    server.add_service(background_service(
+        "health check",
+        HealthCheckService::new(proxy_map.clone()),
+    ));
+    server.add_service(background_service(
+        "OCSP stapling",
+        OcspService::new(tls.clone()),
+    ));
+    server.add_services(services);
+
+    let watch = if args.watch.is_empty() {
+        let dir = Path::new(args.config.as_str())
+            .parent()
+            .and_then(|p| p.to_str())
+            .filter(|p| !p.is_empty())
+            .unwrap_or(".");
+        vec![dir.to_string()]
+    } else {
+        args.watch.clone()
+    };
+    spawn(move || {
+        run_reload(watch.as_slice(), args.config.as_str(), &proxy_map, &tls);
+    });
+
+    server.run_forever();
+}
+
+/// Proxies of the plain text and TLS listeners.
+///
+/// They are separate services because h2c applies to every connection of a service.
+pub fn create_http_proxies(
+    conf: &Arc<ServerConf>,
+    args: &Args,
+    proxy_map: Arc<ArcSwap<ProxyMap>>,
+    tls: Arc<ArcSwap<TlsMap>>,
+) -> Vec<Box<dyn Service>> {
+    let subjects = Arc::new(ClientSubjects::default());
+    let mut http_proxy = pingora::proxy::http_proxy_service_with_name(
+        conf,
+        PingressHttpProxy::new(proxy_map.clone(), subjects.clone()),
+        "Pingress HTTP Proxy Service",
+    );
+    if args.h2c {
+        let mut options = HttpServerOptions::default();
+        options.h2c = true;
+        if let Some(app) = http_proxy.app_logic_mut() {
+            app.server_options = Some(options);
+        }
+    }
+    http_proxy.add_tcp(args.listen_http.as_str());
+
+    let mut https_proxy = pingora::proxy::http_proxy_service_with_name(
+        conf,
+        PingressHttpProxy::new(proxy_map, subjects.clone()),
+        "Pingress HTTPS Proxy Service",
+    );
+    let acceptor = TlsAcceptor::new(tls.clone(), subjects);
+    let mut settings = TlsSettings::with_callbacks(acceptor.into()).unwrap();
+    settings.set_alpn_select_callback(move |ssl, alpn_in| select_alpn(&tls, ssl, alpn_in));
+    // Staple the OCSP response set by the certificate callback, if any
+    settings.set_status_callback(|_| Ok(true)).unwrap();
+    https_proxy.add_tls_with_settings(args.listen_https.as_str(), None, settings);
+
+    vec![Box::new(http_proxy), Box::new(https_proxy)]
+}
+
+/// Prefer HTTP/2 unless it is disabled for the requested host
+fn select_alpn<'a>(
+    tls: &ArcSwap<TlsMap>,
+    ssl: &mut SslRef,
+    alpn_in: &'a [u8],
+) -> Result<&'a [u8], AlpnError> {
+    let http2 = ssl
+        .servername(NameType::HOST_NAME)
+        .is_none_or(|sni| tls.load().http2(sni));
+    let server: &[u8] = if http2 {
+        b"\x02h2\x08http/1.1"
+    } else {
+        b"\x08http/1.1"
+    };
+    // Clients without a common protocol fall back to HTTP/1.1
+    select_next_proto(server, alpn_in).ok_or(AlpnError::NOACK)
+}
+
+struct TlsAcceptor {
+    tls: Arc<ArcSwap<TlsMap>>,
+    /// Subjects of the verified client certificates, read by the proxies
+    subjects: Arc<ClientSubjects>,
+}
+
+impl TlsAcceptor {
+    fn new(tls: Arc<ArcSwap<TlsMap>>, subjects: Arc<ClientSubjects>) -> Self {
+        Self { tls, subjects }
+    }
+}
+
+impl From<TlsAcceptor> for TlsAcceptCallbacks {
+    fn from(value: TlsAcceptor) -> Self {
+        Box::new(value)
+    }
+}
+
+#[async_trait]
+impl TlsAccept for TlsAcceptor {
+    async fn certificate_callback(&self, ssl: &mut SslRef) -> () {
+        let certificate = self.tls.load().get_tls(ssl.servername(NameType::HOST_NAME));
+
+        if let Some((sni, certificate)) = certificate {
+            // Without a certificate, the handshake fails
+            if let Err(e) = certificate.policy.apply(ssl, &self.subjects) {
+                error!("Error: TLS policy of '{sni}': {e}");
+                return;
+            }
+            if let Err(e) = ssl_use_certificate(ssl, &certificate.leaf) {
+                error!("Error: Certificate for '{sni}': {e}");
+                return;
+            }
+            for cert in &certificate.chain {
+                if let Err(e) = ssl_add_chain_cert(ssl, cert) {
+                    error!("Error: Certificate chain for '{sni}': {e}");
+                    return;
+                }
+            }
+            if let Err(e) = ssl_use_private_key(ssl, &certificate.key) {
+                error!("Error: Private key for '{sni}': {e}");
+                return;
+            }
+            if let Some(response) = certificate.ocsp.as_ref().and_then(|o| o.response()) {
+                if let Err(e) = ssl.set_ocsp_status(response.as_slice()) {
+                    error!("Error: OCSP response for '{sni}': {e}");
+                }
+            }
+        }
+    }
+}
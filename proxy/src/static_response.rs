@@ -0,0 +1,49 @@
+use bytes::Bytes;
+use pingora::http::ResponseHeader;
+use pingora::proxy::Session;
+
+/// Response served by the proxy itself, without a backend
+pub(crate) struct StaticResponse {
+    header: ResponseHeader,
+    body: Bytes,
+}
+
+impl StaticResponse {
+    /// Response with an HTML body unless `content_type` is given
+    pub(crate) fn new(
+        status: u16,
+        location: Option<&str>,
+        content_type: Option<&str>,
+        body: String,
+    ) -> pingora::Result<Self> {
+        let mut header = ResponseHeader::build(status, Some(3))?;
+        if let Some(location) = location {
+            header.insert_header("Location", location)?;
+        }
+        if !body.is_empty() {
+            header.insert_header(
+                "Content-Type",
+                content_type.unwrap_or("text/html; charset=utf-8"),
+            )?;
+        }
+        header.insert_header("Content-Length", body.len())?;
+
+        Ok(Self {
+            header,
+            body: Bytes::from(body),
+        })
+    }
+
+    pub(crate) async fn respond(&self, session: &mut Session) -> pingora::Result<()> {
+        let end = self.body.is_empty();
+        session
+            .write_response_header(Box::new(self.header.clone()), end)
+            .await?;
+        if !end {
+            session
+                .write_response_body(Some(self.body.clone()), true)
+                .await?;
+        }
+        Ok(())
+    }
+}
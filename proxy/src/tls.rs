@@ -1,49 +1,613 @@
+use crate::host::{normalize_host, parse_host_pattern, wildcard_suffix, HostPattern};
+use crate::metrics::{TLS_CERTIFICATE_ERRORS, TLS_OCSP_ERRORS};
+use crate::ocsp::OcspStaple;
+use crate::tls_policy::TlsPolicy;
+use log::error;
+use pingora::tls::nid::Nid;
 use pingora::tls::pkey::{PKey, Private};
 use pingora::tls::x509::X509;
 use pingress_config::{PingressConfiguration, Tls};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs::read;
+use std::sync::Arc;
 
-pub(crate) struct TlsMap {
-    tls: HashMap<String, (PKey<Private>, X509)>,
+/// Private key and certificates served to the clients of a host
+pub struct Certificate {
+    pub(crate) key: PKey<Private>,
+    pub(crate) leaf: X509,
+    /// Intermediate certificates following the leaf in the file
+    pub(crate) chain: Vec<X509>,
+    pub(crate) ocsp: Option<OcspStaple>,
+    pub(crate) policy: TlsPolicy,
 }
 
-pub(crate) trait GetTls {
-    fn get_tls(&self, host: &str) -> Option<(String, PKey<Private>, X509)>;
+/// Certificates of a configuration, by the SNI they are served for
+pub struct TlsMap {
+    /// Certificates keyed by the hosts of the rules and the DNS names of their SAN entries
+    exact: HashMap<String, Arc<Certificate>>,
+    /// Certificates of wildcard hosts and SAN entries, keyed by the suffix after `*.`
+    wildcard: HashMap<String, Arc<Certificate>>,
+    /// Certificate of the clients whose SNI matches no certificate, or without SNI
+    default: Option<Arc<Certificate>>,
+    /// Whether HTTP/2 is advertised for the hosts of the rules, `false` when any rule of the
+    /// host disables it
+    http2_exact: HashMap<String, bool>,
+    /// Same for the wildcard hosts, keyed by the suffix after `*.`
+    http2_wildcard: HashMap<String, bool>,
+}
+
+pub trait GetTls {
+    /// Certificate for the SNI, with the name it is found by.
+    ///
+    /// An exact name is preferred over a wildcard, then the default certificate is used.
+    fn get_tls(&self, sni: Option<&str>) -> Option<(String, Arc<Certificate>)>;
+
+    /// Whether HTTP/2 may be negotiated with ALPN for the host.
+    ///
+    /// The host is matched as by the routes: an exact host is preferred over a wildcard.
+    fn http2(&self, host: &str) -> bool;
 }
 
 impl GetTls for TlsMap {
-    fn get_tls(&self, host: &str) -> Option<(String, PKey<Private>, X509)> {
-        let (pk, ct) = self.tls.get(host)?;
-        Some((host.to_string(), pk.clone(), ct.clone()))
+    fn get_tls(&self, sni: Option<&str>) -> Option<(String, Arc<Certificate>)> {
+        if let Some(sni) = sni.map(normalize_host) {
+            if let Some(certificate) = self.exact.get(sni.as_str()) {
+                return Some((sni, certificate.clone()));
+            }
+            if let Some(suffix) = wildcard_suffix(sni.as_str()) {
+                if let Some(certificate) = self.wildcard.get(suffix) {
+                    return Some((format!("*.{suffix}"), certificate.clone()));
+                }
+            }
+        }
+        let certificate = self.default.as_ref()?;
+        Some(("default".to_string(), certificate.clone()))
+    }
+
+    fn http2(&self, host: &str) -> bool {
+        let host = normalize_host(host);
+        if let Some(http2) = self.http2_exact.get(host.as_str()) {
+            return *http2;
+        }
+        wildcard_suffix(host.as_str())
+            .and_then(|suffix| self.http2_wildcard.get(suffix))
+            .copied()
+            .unwrap_or(true)
     }
 }
 
+/// A certificate that cannot be loaded is skipped, so that the other hosts are still served.
+/// Its clients get the wildcard or the default certificate instead, if any.
 impl From<PingressConfiguration> for TlsMap {
     fn from(value: PingressConfiguration) -> Self {
-        Self {
-            tls: value
-                .rules
-                .into_iter()
-                .filter_map(|r| r.tls.map(|t| (r.host, t)))
-                .map(|(host, tls)| (host, tls.into_key_cert()))
-                .collect(),
+        let mut http2_exact: HashMap<String, bool> = HashMap::new();
+        let mut http2_wildcard: HashMap<String, bool> = HashMap::new();
+        for rule in &value.rules {
+            let entry = match rule.host.as_deref().map(parse_host_pattern) {
+                Some(Ok(HostPattern::Exact(host))) => http2_exact.entry(host.to_string()),
+                Some(Ok(HostPattern::Wildcard(suffix))) => http2_wildcard.entry(suffix.to_string()),
+                // Rules without a host, and invalid hosts that are never routed
+                _ => continue,
+            };
+            *entry.or_insert(true) &= rule.http2;
+        }
+
+        let mut loaded: HashMap<Tls, Result<Arc<Certificate>, String>> = HashMap::new();
+        let mut certificates = Vec::new();
+        for (host, tls) in value.rules.into_iter().filter_map(|r| r.host.zip(r.tls)) {
+            match loaded
+                .entry(tls)
+                .or_insert_with_key(|tls| tls.load().map(Arc::new))
+            {
+                Ok(certificate) => certificates.push((host, certificate.clone())),
+                Err(e) => skip_certificate(host.as_str(), e),
+            }
+        }
+
+        let mut map = Self {
+            exact: HashMap::new(),
+            wildcard: HashMap::new(),
+            default: value.default_tls.and_then(|tls| match tls.load() {
+                Ok(certificate) => Some(Arc::new(certificate)),
+                Err(e) => {
+                    skip_certificate("default", e.as_str());
+                    None
+                }
+            }),
+            http2_exact,
+            http2_wildcard,
+        };
+        // The hosts of the rules take precedence over the names of other certificates
+        for (host, certificate) in &certificates {
+            map.insert(host, certificate);
+        }
+        for (_, certificate) in &certificates {
+            for name in dns_names(&certificate.leaf) {
+                map.insert(name.as_str(), certificate);
+            }
         }
+
+        map
     }
 }
 
-trait IntoKeyCert {
-    fn into_key_cert(self) -> (PKey<Private>, X509);
+impl TlsMap {
+    /// OCSP staples of the certificates, each once
+    pub(crate) fn ocsp_staples(&self) -> impl Iterator<Item = &OcspStaple> {
+        let mut seen = HashSet::new();
+        self.exact
+            .values()
+            .chain(self.wildcard.values())
+            .chain(self.default.iter())
+            .filter(move |c| seen.insert(Arc::as_ptr(c)))
+            .filter_map(|c| c.ocsp.as_ref())
+    }
+
+    /// Keep the OCSP responses of the certificates still served after a reload, so that
+    /// stapling is not interrupted until they are refreshed
+    pub(crate) fn inherit_ocsp(&self, previous: &TlsMap) {
+        for staple in self.ocsp_staples() {
+            for previous in previous.ocsp_staples() {
+                staple.inherit(previous);
+            }
+        }
+    }
+
+    /// Index the certificate by the host or DNS name, unless another certificate already is
+    fn insert(&mut self, name: &str, certificate: &Arc<Certificate>) {
+        let name = name.to_ascii_lowercase();
+        let entry = match parse_host_pattern(name.as_str()) {
+            Ok(HostPattern::Exact(host)) => self.exact.entry(host.to_string()),
+            Ok(HostPattern::Wildcard(suffix)) => self.wildcard.entry(suffix.to_string()),
+            // Names that no SNI can match (e.g. IP addresses)
+            Err(_) => return,
+        };
+        entry.or_insert_with(|| certificate.clone());
+    }
 }
 
-impl IntoKeyCert for Tls {
-    fn into_key_cert(self) -> (PKey<Private>, X509) {
-        let pk = read(self.key.as_str()).unwrap();
-        let ct = read(self.cert.as_str()).unwrap();
+fn skip_certificate(host: &str, e: &str) {
+    error!("Error: Skip the certificate of '{host}': {e}");
+    TLS_CERTIFICATE_ERRORS.with_label_values(&[host]).inc();
+}
 
-        (
-            PKey::private_key_from_pem(pk.as_slice()).unwrap(),
-            X509::from_pem(ct.as_slice()).unwrap(),
+/// DNS names of the SAN entries of the certificate, or its common name when it has none
+fn dns_names(cert: &X509) -> Vec<String> {
+    let sans: Vec<String> = cert
+        .subject_alt_names()
+        .iter()
+        .flatten()
+        .filter_map(|n| n.dnsname().map(str::to_string))
+        .collect();
+    if !sans.is_empty() {
+        return sans;
+    }
+    cert.subject_name()
+        .entries_by_nid(Nid::COMMONNAME)
+        .filter_map(|e| e.data().as_utf8().ok().map(|s| s.to_string()))
+        .collect()
+}
+
+trait LoadCertificate {
+    /// Read the PEM files of the private key and of the certificate followed by its chain.
+    ///
+    /// RSA and EC keys are read in PKCS#1 (or SEC1) and PKCS#8 forms. The CA bundle of the client
+    /// certificates is read too.
+    fn load(&self) -> Result<Certificate, String>;
+}
+
+impl LoadCertificate for Tls {
+    fn load(&self) -> Result<Certificate, String> {
+        let pk = read(self.key.as_str()).map_err(|e| format!("{}: {e}", self.key))?;
+        let ct = read(self.cert.as_str()).map_err(|e| format!("{}: {e}", self.cert))?;
+
+        let key =
+            PKey::private_key_from_pem(pk.as_slice()).map_err(|e| format!("{}: {e}", self.key))?;
+        let mut certs = X509::stack_from_pem(ct.as_slice())
+            .map_err(|e| format!("{}: {e}", self.cert))?
+            .into_iter();
+        let leaf = certs
+            .next()
+            .ok_or_else(|| format!("{}: no certificate", self.cert))?;
+        let public_key = leaf
+            .public_key()
+            .map_err(|e| format!("{}: {e}", self.cert))?;
+        if !public_key.public_eq(&key) {
+            return Err(format!(
+                "{}: the private key does not match the certificate {}",
+                self.key, self.cert
+            ));
+        }
+
+        let chain: Vec<X509> = certs.collect();
+        let policy = TlsPolicy::new(self)?;
+        let ocsp = self.ocsp.as_ref().and_then(|config| {
+            match OcspStaple::new(self.cert.as_str(), config, &leaf, chain.as_slice()) {
+                Ok(staple) => Some(staple),
+                Err(e) => {
+                    error!("Error: No OCSP stapling for '{}': {e}", self.cert);
+                    TLS_OCSP_ERRORS
+                        .with_label_values(&[self.cert.as_str()])
+                        .inc();
+                    None
+                }
+            }
+        });
+
+        Ok(Certificate {
+            key,
+            leaf,
+            chain,
+            ocsp,
+            policy,
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use crate::metrics::TLS_CERTIFICATE_ERRORS;
+    use crate::tls::{GetTls, TlsMap};
+    use openssl::asn1::Asn1Time;
+    use openssl::bn::BigNum;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::hash::MessageDigest;
+    use openssl::nid::Nid;
+    use openssl::pkey::{PKey, Private};
+    use openssl::rsa::Rsa;
+    use openssl::x509::extension::{BasicConstraints, SubjectAlternativeName};
+    use openssl::x509::{X509Builder, X509NameBuilder, X509};
+    use pingress_config::{
+        Backend, HttpPath, LoadBalancing, PathRule, PingressConfiguration, Port, Protocol,
+        Timeouts, Tls,
+    };
+
+    pub(crate) fn ec_key() -> PKey<Private> {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap()
+    }
+
+    /// Certificate of the DNS names, the first one as its common name, signed by the issuer or
+    /// self-signed
+    pub(crate) fn certificate(
+        names: &[&str],
+        key: &PKey<Private>,
+        issuer: Option<(&X509, &PKey<Private>)>,
+    ) -> X509 {
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", names[0]).unwrap();
+        let name = name.build();
+
+        let serial = BigNum::from_u32(1000).unwrap().to_asn1_integer().unwrap();
+
+        let mut cert = X509Builder::new().unwrap();
+        cert.set_version(2).unwrap();
+        cert.set_serial_number(&serial).unwrap();
+        cert.set_subject_name(&name).unwrap();
+        match issuer {
+            Some((issuer, _)) => cert.set_issuer_name(issuer.subject_name()).unwrap(),
+            None => cert.set_issuer_name(&name).unwrap(),
+        }
+        cert.set_pubkey(key).unwrap();
+        cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        cert.set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        let mut san = SubjectAlternativeName::new();
+        for name in names {
+            san.dns(name);
+        }
+        let san = san
+            .build(&cert.x509v3_context(issuer.map(|(i, _)| i.as_ref()), None))
+            .unwrap();
+        cert.append_extension(san).unwrap();
+        let signer = issuer.map_or(key, |(_, k)| k);
+        cert.sign(signer, MessageDigest::sha256()).unwrap();
+        cert.build()
+    }
+
+    /// Self-signed CA certificate, which can verify the certificates it issues
+    pub(crate) fn ca_certificate(name: &str, key: &PKey<Private>) -> X509 {
+        let mut subject = X509NameBuilder::new().unwrap();
+        subject.append_entry_by_text("CN", name).unwrap();
+        let subject = subject.build();
+
+        let mut cert = X509Builder::new().unwrap();
+        cert.set_version(2).unwrap();
+        cert.set_subject_name(&subject).unwrap();
+        cert.set_issuer_name(&subject).unwrap();
+        cert.set_pubkey(key).unwrap();
+        cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        cert.set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        cert.append_extension(BasicConstraints::new().critical().ca().build().unwrap())
+            .unwrap();
+        cert.sign(key, MessageDigest::sha256()).unwrap();
+        cert.build()
+    }
+
+    /// Write the PEM files of the key and of the certificates to a temporary directory
+    pub(crate) fn write_tls(label: &str, key: &[u8], certs: &[&X509]) -> Tls {
+        let dir = std::env::temp_dir().join(format!("pingress-{}-{label}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let tls = Tls::new(
+            dir.join("tls.key").to_string_lossy().to_string(),
+            dir.join("tls.crt").to_string_lossy().to_string(),
+        );
+        let pem: Vec<u8> = certs.iter().flat_map(|c| c.to_pem().unwrap()).collect();
+        std::fs::write(&tls.key, key).unwrap();
+        std::fs::write(&tls.cert, pem).unwrap();
+        tls
+    }
+
+    /// Self-signed certificate of the DNS names, the first one as its common name, written to a
+    /// temporary directory
+    pub(crate) fn self_signed(names: &[&str]) -> Tls {
+        let key = ec_key();
+        let cert = certificate(names, &key, None);
+        write_tls(
+            names.join(",").as_str(),
+            key.private_key_to_pem_pkcs8().unwrap().as_slice(),
+            &[&cert],
         )
     }
+
+    fn rule(host: &str, tls: Tls) -> PathRule {
+        PathRule {
+            host: Some(host.to_string()),
+            tls: Some(tls),
+            path: HttpPath::Prefix("/".to_string()),
+            backend: Backend::Service {
+                name: "backend".to_string(),
+                namespace: "default".to_string(),
+                port: Port::Number(80),
+                endpoints: Vec::new(),
+                load_balancing: LoadBalancing::default(),
+                health_check: None,
+                outlier_detection: None,
+                circuit_breaker: None,
+                protocol: Protocol::default(),
+                tls: None,
+            },
+            retry: None,
+            timeouts: Timeouts::default(),
+            http2: true,
+        }
+    }
+
+    fn tls_map(rules: Vec<PathRule>, default_tls: Option<Tls>) -> TlsMap {
+        TlsMap::from(PingressConfiguration {
+            rules,
+            default_backend: None,
+            not_found_body: None,
+            default_tls,
+        })
+    }
+
+    #[test]
+    fn select_certificate() {
+        let tls = tls_map(
+            vec![
+                rule(
+                    "foo.example.com",
+                    self_signed(&["foo.example.com", "api.example.com"]),
+                ),
+                rule("*.example.net", self_signed(&["*.example.net"])),
+            ],
+            Some(self_signed(&["default.example.org"])),
+        );
+
+        // (SNI, name the certificate is found by)
+        let cases = [
+            (Some("foo.example.com"), "foo.example.com"),
+            (Some("FOO.example.com."), "foo.example.com"),
+            (Some("api.example.com"), "api.example.com"),
+            (Some("bar.example.net"), "*.example.net"),
+            (Some("bar.baz.example.net"), "default"),
+            (Some("example.net"), "default"),
+            (None, "default"),
+        ];
+        for (sni, expected) in cases {
+            let (name, _) = tls.get_tls(sni).unwrap();
+            assert_eq!(name, expected, "{sni:?}");
+        }
+
+        let (_, foo) = tls.get_tls(Some("foo.example.com")).unwrap();
+        let (_, api) = tls.get_tls(Some("api.example.com")).unwrap();
+        assert_eq!(foo.leaf.to_der().unwrap(), api.leaf.to_der().unwrap());
+    }
+
+    #[test]
+    fn without_default_certificate() {
+        let tls = tls_map(
+            vec![rule("foo.example.com", self_signed(&["foo.example.com"]))],
+            None,
+        );
+        assert!(tls.get_tls(Some("bar.example.com")).is_none());
+        assert!(tls.get_tls(None).is_none());
+    }
+
+    #[test]
+    fn rule_host_takes_precedence() {
+        // The certificate of bar.example.com also covers it by a wildcard SAN entry
+        let tls = tls_map(
+            vec![
+                rule("foo.example.com", self_signed(&["*.example.com"])),
+                rule("bar.example.com", self_signed(&["bar.example.com"])),
+            ],
+            None,
+        );
+        let (_, bar) = tls.get_tls(Some("bar.example.com")).unwrap();
+        let (_, baz) = tls.get_tls(Some("baz.example.com")).unwrap();
+        assert_ne!(bar.leaf.to_der().unwrap(), baz.leaf.to_der().unwrap());
+    }
+
+    #[test]
+    fn http2_by_host() {
+        let http1 = |host| PathRule {
+            http2: false,
+            ..rule(host, self_signed(&[host]))
+        };
+        let tls = tls_map(
+            vec![
+                http1("foo.example.com"),
+                http1("*.example.net"),
+                rule("api.example.net", self_signed(&["api.example.net"])),
+                // Another rule of the host disables it
+                rule("*.example.org", self_signed(&["*.example.org"])),
+                http1("*.example.org"),
+            ],
+            None,
+        );
+
+        let cases = [
+            ("foo.example.com", false),
+            ("FOO.example.com.", false),
+            ("bar.example.com", true),
+            ("bar.example.net", false),
+            ("Bar.Example.NET", false),
+            ("api.example.net", true),
+            ("bar.baz.example.net", true),
+            ("bar.example.org", false),
+        ];
+        for (host, expected) in cases {
+            assert_eq!(tls.http2(host), expected, "{host}");
+        }
+    }
+
+    #[test]
+    fn skip_invalid_certificates() {
+        let missing = Tls::new(
+            "/nonexistent/tls.key".to_string(),
+            "/nonexistent/tls.crt".to_string(),
+        );
+        let garbage = write_tls("garbage", b"not a key", &[]);
+        let mismatched = {
+            let cert = certificate(&["mismatched.example.com"], &ec_key(), None);
+            write_tls(
+                "mismatched",
+                ec_key().private_key_to_pem_pkcs8().unwrap().as_slice(),
+                &[&cert],
+            )
+        };
+        let errors = |host: &str| TLS_CERTIFICATE_ERRORS.with_label_values(&[host]).get();
+        let before = [
+            errors("missing.example.com"),
+            errors("garbage.example.com"),
+            errors("mismatched.example.com"),
+        ];
+
+        let tls = tls_map(
+            vec![
+                rule("missing.example.com", missing),
+                rule("garbage.example.com", garbage),
+                rule("mismatched.example.com", mismatched),
+                rule("valid.example.com", self_signed(&["valid.example.com"])),
+            ],
+            Some(self_signed(&["fallback.example.org"])),
+        );
+
+        let (name, _) = tls.get_tls(Some("valid.example.com")).unwrap();
+        assert_eq!(name, "valid.example.com");
+        for host in [
+            "missing.example.com",
+            "garbage.example.com",
+            "mismatched.example.com",
+        ] {
+            let (name, _) = tls.get_tls(Some(host)).unwrap();
+            assert_eq!(name, "default", "{host}");
+        }
+        let after = [
+            errors("missing.example.com"),
+            errors("garbage.example.com"),
+            errors("mismatched.example.com"),
+        ];
+        for (before, after) in before.iter().zip(after) {
+            assert_eq!(after, before + 1);
+        }
+    }
+
+    #[test]
+    fn private_key_formats() {
+        let rsa = Rsa::generate(2048).unwrap();
+        let ec =
+            EcKey::generate(&EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap()).unwrap();
+        // (host, key, PEM of the key)
+        let keys = [
+            (
+                "rsa-pkcs1.example.com",
+                PKey::from_rsa(rsa.clone()).unwrap(),
+                rsa.private_key_to_pem().unwrap(),
+            ),
+            (
+                "rsa-pkcs8.example.com",
+                PKey::from_rsa(rsa.clone()).unwrap(),
+                PKey::from_rsa(rsa)
+                    .unwrap()
+                    .private_key_to_pem_pkcs8()
+                    .unwrap(),
+            ),
+            (
+                "ec-sec1.example.com",
+                PKey::from_ec_key(ec.clone()).unwrap(),
+                ec.private_key_to_pem().unwrap(),
+            ),
+            (
+                "ec-pkcs8.example.com",
+                PKey::from_ec_key(ec.clone()).unwrap(),
+                PKey::from_ec_key(ec)
+                    .unwrap()
+                    .private_key_to_pem_pkcs8()
+                    .unwrap(),
+            ),
+        ];
+        let rules = keys
+            .iter()
+            .map(|(host, key, pem)| {
+                let cert = certificate(&[host], key, None);
+                rule(host, write_tls(host, pem.as_slice(), &[&cert]))
+            })
+            .collect();
+
+        let tls = tls_map(rules, None);
+        for (host, _, _) in keys {
+            let (name, _) = tls.get_tls(Some(host)).unwrap();
+            assert_eq!(name, host);
+        }
+    }
+
+    #[test]
+    fn certificate_chain() {
+        let root_key = ec_key();
+        let root = certificate(&["Root CA"], &root_key, None);
+        let intermediate_key = ec_key();
+        let intermediate = certificate(
+            &["Intermediate CA"],
+            &intermediate_key,
+            Some((&root, &root_key)),
+        );
+        let key = ec_key();
+        let leaf = certificate(
+            &["chain.example.com"],
+            &key,
+            Some((&intermediate, &intermediate_key)),
+        );
+        let tls = write_tls(
+            "chain",
+            key.private_key_to_pem_pkcs8().unwrap().as_slice(),
+            &[&leaf, &intermediate],
+        );
+
+        let tls = tls_map(vec![rule("chain.example.com", tls)], None);
+        let (_, certificate) = tls.get_tls(Some("chain.example.com")).unwrap();
+        assert_eq!(certificate.leaf.to_der().unwrap(), leaf.to_der().unwrap());
+        assert_eq!(certificate.chain.len(), 1);
+        assert_eq!(
+            certificate.chain[0].to_der().unwrap(),
+            intermediate.to_der().unwrap()
+        );
+        // Only the DNS names of the leaf are indexed
+        assert!(tls.get_tls(Some("Intermediate CA")).is_none());
+    }
 }
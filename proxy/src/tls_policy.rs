@@ -0,0 +1,394 @@
+use openssl::stack::Stack;
+use pingora::tls::ext::{ssl_set_groups_list, ssl_set_verify_cert_store};
+use pingora::tls::hash::MessageDigest;
+use pingora::tls::nid::Nid;
+use pingora::tls::ssl::{SslContextBuilder, SslMethod, SslRef, SslVerifyMode, SslVersion};
+use pingora::tls::x509::store::{X509Store, X509StoreBuilder};
+use pingora::tls::x509::{X509NameRef, X509Ref, X509};
+use pingress_config::{ClientAuthMode, Tls, TlsVersion};
+use std::collections::HashMap;
+use std::fs::read;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Subjects kept before those of the expired sessions are removed
+const MIN_CLIENT_SUBJECTS: usize = 1_000;
+
+/// Lifetime of the TLS sessions and tickets, the default of OpenSSL
+const SESSION_TIMEOUT: Duration = Duration::from_secs(7200);
+
+/// Protocol, cipher and client certificate settings of the handshakes of a certificate
+#[derive(Default)]
+pub(crate) struct TlsPolicy {
+    min_version: Option<TlsVersion>,
+    ciphers: Option<String>,
+    curves: Option<String>,
+    client_auth: Option<ClientVerifier>,
+}
+
+/// CAs verifying the client certificates
+struct ClientVerifier {
+    /// Path of the CA bundle, identifying the verifier in [ClientSubjects]
+    ca: String,
+    store: X509Store,
+    certs: Vec<X509>,
+    required: bool,
+}
+
+impl TlsPolicy {
+    /// Read the CA bundle of the client certificates, and check the cipher and curve lists
+    pub(crate) fn new(tls: &Tls) -> Result<Self, String> {
+        // Invalid lists would only fail the handshakes
+        let mut check =
+            SslContextBuilder::new(SslMethod::tls_server()).map_err(|e| e.to_string())?;
+        if let Some(ciphers) = tls.ciphers.as_deref() {
+            check
+                .set_cipher_list(ciphers)
+                .map_err(|e| format!("ciphers '{ciphers}': {e}"))?;
+        }
+        if let Some(curves) = tls.curves.as_deref() {
+            if curves.contains('\0') {
+                return Err(format!("curves '{curves}': invalid"));
+            }
+            check
+                .set_groups_list(curves)
+                .map_err(|e| format!("curves '{curves}': {e}"))?;
+        }
+
+        let client_auth = match tls.client_auth.as_ref() {
+            Some(client_auth) => {
+                let pem = read(client_auth.ca.as_str())
+                    .map_err(|e| format!("{}: {e}", client_auth.ca))?;
+                let certs = X509::stack_from_pem(pem.as_slice())
+                    .map_err(|e| format!("{}: {e}", client_auth.ca))?;
+                if certs.is_empty() {
+                    return Err(format!("{}: no certificate", client_auth.ca));
+                }
+                let mut store = X509StoreBuilder::new().map_err(|e| e.to_string())?;
+                for cert in &certs {
+                    store
+                        .add_cert(cert.clone())
+                        .map_err(|e| format!("{}: {e}", client_auth.ca))?;
+                }
+                Some(ClientVerifier {
+                    ca: client_auth.ca.clone(),
+                    store: store.build(),
+                    certs,
+                    required: client_auth.mode == ClientAuthMode::Required,
+                })
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            min_version: tls.min_version,
+            ciphers: tls.ciphers.clone(),
+            curves: tls.curves.clone(),
+            client_auth,
+        })
+    }
+
+    /// Apply the policy to a handshake from the certificate callback.
+    ///
+    /// The protocol version, and the cipher suite and key share of TLS 1.3, are negotiated
+    /// before, so a version below the minimum fails the handshake. The ciphers and curves of
+    /// TLS 1.2 and below and the request of the client certificate are still to come.
+    pub(crate) fn apply(
+        &self,
+        ssl: &mut SslRef,
+        subjects: &Arc<ClientSubjects>,
+    ) -> Result<(), String> {
+        if let Some(min_version) = self.min_version {
+            let version = ssl.version2().and_then(tls_version);
+            if version.is_none_or(|v| v < min_version) {
+                return Err(format!("protocol {} is not allowed", ssl.version_str()));
+            }
+        }
+        if let Some(ciphers) = self.ciphers.as_deref() {
+            ssl.set_cipher_list(ciphers).map_err(|e| e.to_string())?;
+        }
+        if let Some(curves) = self.curves.as_deref() {
+            ssl_set_groups_list(ssl, curves).map_err(|e| e.to_string())?;
+        }
+
+        let Some(verifier) = self.client_auth.as_ref() else {
+            return Ok(());
+        };
+        ssl_set_verify_cert_store(ssl, &verifier.store).map_err(|e| e.to_string())?;
+        let mut names = Stack::new().map_err(|e| e.to_string())?;
+        for cert in &verifier.certs {
+            let name = cert.subject_name().to_owned().map_err(|e| e.to_string())?;
+            names.push(name).map_err(|e| e.to_string())?;
+        }
+        ssl.set_client_ca_list(names);
+
+        let mode = if verifier.required {
+            SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT
+        } else {
+            SslVerifyMode::PEER
+        };
+        let ca = verifier.ca.clone();
+        let subjects = subjects.clone();
+        // Keeps the subject while the connection is open
+        let held = Mutex::new(None);
+        ssl.set_verify_callback(mode, move |verified, ctx| {
+            // The client certificate comes last, once its issuers are verified
+            if verified && ctx.error_depth() == 0 {
+                if let Some(cert) = ctx.current_cert() {
+                    *held.lock().unwrap() = subjects.insert(ca.as_str(), cert);
+                }
+            }
+            verified
+        });
+        Ok(())
+    }
+}
+
+fn tls_version(version: SslVersion) -> Option<TlsVersion> {
+    match version {
+        SslVersion::TLS1 => Some(TlsVersion::Tls10),
+        SslVersion::TLS1_1 => Some(TlsVersion::Tls11),
+        SslVersion::TLS1_2 => Some(TlsVersion::Tls12),
+        SslVersion::TLS1_3 => Some(TlsVersion::Tls13),
+        _ => None,
+    }
+}
+
+/// Subjects of the client certificates verified during the handshakes.
+///
+/// Requests only carry the digest of the client certificate, so its subject is kept by the
+/// digest and the CA bundle that verified it. Resumed sessions do not verify the certificate
+/// again, so the subject is kept while the connections presenting the certificate are open,
+/// then as long as their sessions can be resumed.
+#[derive(Default)]
+pub(crate) struct ClientSubjects {
+    subjects: Mutex<Subjects>,
+}
+
+#[derive(Default)]
+struct Subjects {
+    by_certificate: HashMap<(String, Vec<u8>), Subject>,
+    /// Number of subjects at which those of the expired sessions are removed
+    prune_at: usize,
+}
+
+struct Subject {
+    /// Also held by the open connections
+    name: Arc<String>,
+    /// Last handshake or request with the certificate
+    used_at: Instant,
+}
+
+impl Subjects {
+    /// Remove the subjects without open connections, unused for longer than the sessions last
+    fn prune(&mut self, now: Instant) {
+        self.by_certificate.retain(|_, s| {
+            Arc::strong_count(&s.name) > 1 || now.duration_since(s.used_at) < SESSION_TIMEOUT
+        });
+        self.prune_at = MIN_CLIENT_SUBJECTS.max(self.by_certificate.len() * 2);
+    }
+}
+
+impl ClientSubjects {
+    /// Record the subject of a verified certificate, returned for the connection to hold
+    fn insert(&self, ca: &str, cert: &X509Ref) -> Option<Arc<String>> {
+        let digest = cert.digest(MessageDigest::sha256()).ok()?;
+        let key = (ca.to_string(), digest.to_vec());
+        let now = Instant::now();
+        let mut subjects = self.subjects.lock().unwrap();
+        if let Some(subject) = subjects.by_certificate.get_mut(&key) {
+            subject.used_at = now;
+            return Some(subject.name.clone());
+        }
+
+        if subjects.by_certificate.len() >= subjects.prune_at {
+            subjects.prune(now);
+        }
+        let name = Arc::new(subject_name(cert.subject_name()));
+        subjects.by_certificate.insert(
+            key,
+            Subject {
+                name: name.clone(),
+                used_at: now,
+            },
+        );
+        Some(name)
+    }
+
+    /// Subject of the client certificate of the digest, if verified by the CA bundle
+    pub(crate) fn get(&self, ca: &str, digest: &[u8]) -> Option<String> {
+        let mut subjects = self.subjects.lock().unwrap();
+        let subject = subjects
+            .by_certificate
+            .get_mut(&(ca.to_string(), digest.to_vec()))?;
+        // Resumed sessions get new tickets
+        subject.used_at = Instant::now();
+        Some(subject.name.to_string())
+    }
+}
+
+/// Distinguished name in the string form of RFC 4514 (e.g. `CN=client,O=Example`).
+///
+/// Bytes outside of printable ASCII are escaped in hex, so that the name is a valid header value.
+fn subject_name(name: &X509NameRef) -> String {
+    let mut rdns: Vec<String> = name
+        .entries()
+        .map(|entry| {
+            let nid = entry.object().nid();
+            let attribute = match nid.short_name() {
+                Ok(short_name) if nid != Nid::UNDEF => short_name.to_string(),
+                _ => entry.object().to_string(),
+            };
+            let value = entry
+                .data()
+                .as_utf8()
+                .map(|s| s.to_string())
+                .unwrap_or_default();
+            format!("{attribute}={}", escape_value(value.as_str()))
+        })
+        .collect();
+    // The most specific attribute comes first
+    rdns.reverse();
+    rdns.join(",")
+}
+
+fn escape_value(value: &str) -> String {
+    let last = value.len().saturating_sub(1);
+    let mut escaped = String::new();
+    for (i, b) in value.bytes().enumerate() {
+        match b {
+            b'"' | b'+' | b',' | b';' | b'<' | b'>' | b'\\' => {
+                escaped.push('\\');
+                escaped.push(b as char);
+            }
+            b'#' if i == 0 => escaped.push_str("\\#"),
+            b' ' if i == 0 || i == last => escaped.push_str("\\ "),
+            0x21..=0x7e | b' ' => escaped.push(b as char),
+            _ => escaped.push_str(format!("\\{b:02X}").as_str()),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tls::tests::{certificate, ec_key, write_tls};
+    use crate::tls_policy::{
+        subject_name, ClientSubjects, TlsPolicy, MIN_CLIENT_SUBJECTS, SESSION_TIMEOUT,
+    };
+    use openssl::x509::X509NameBuilder;
+    use pingress_config::{ClientAuth, ClientAuthMode, Tls, DEFAULT_SUBJECT_HEADER};
+    use std::time::Instant;
+
+    #[test]
+    fn subject_in_rfc4514_form() {
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("C", "JP").unwrap();
+        name.append_entry_by_text("O", "Example, Inc.").unwrap();
+        name.append_entry_by_text("L", "#1").unwrap();
+        name.append_entry_by_text("OU", " #team ").unwrap();
+        name.append_entry_by_text("CN", "client\u{e9}").unwrap();
+        let name = name.build();
+
+        assert_eq!(
+            subject_name(&name),
+            "CN=client\\C3\\A9,OU=\\ #team\\ ,L=\\#1,O=Example\\, Inc.,C=JP"
+        );
+    }
+
+    fn client_auth(ca: &str) -> Tls {
+        Tls {
+            client_auth: Some(ClientAuth {
+                ca: ca.to_string(),
+                mode: ClientAuthMode::Required,
+                subject_header: DEFAULT_SUBJECT_HEADER.to_string(),
+            }),
+            ..Tls::new(String::new(), String::new())
+        }
+    }
+
+    #[test]
+    fn reject_invalid_policies() {
+        let key = ec_key();
+        let ca = certificate(&["Client CA"], &key, None);
+        let pem = key.private_key_to_pem_pkcs8().unwrap();
+        let bundle = write_tls("client-ca", pem.as_slice(), &[&ca]);
+        let empty = write_tls("empty-client-ca", pem.as_slice(), &[]);
+
+        let valid = Tls {
+            ciphers: Some("ECDHE+AESGCM".to_string()),
+            curves: Some("X25519:P-256".to_string()),
+            ..client_auth(bundle.cert.as_str())
+        };
+        assert!(TlsPolicy::new(&valid).is_ok());
+
+        let invalid = [
+            Tls {
+                ciphers: Some("NO-SUCH-CIPHER".to_string()),
+                ..Tls::new(String::new(), String::new())
+            },
+            Tls {
+                curves: Some("no-such-curve".to_string()),
+                ..Tls::new(String::new(), String::new())
+            },
+            client_auth("/nonexistent/ca.crt"),
+            client_auth(empty.cert.as_str()),
+        ];
+        for tls in invalid {
+            assert!(TlsPolicy::new(&tls).is_err(), "{tls:?}");
+        }
+    }
+
+    #[test]
+    fn subjects_by_ca() {
+        let key = ec_key();
+        let cert = certificate(&["client"], &key, None);
+        let digest = cert.digest(openssl::hash::MessageDigest::sha256()).unwrap();
+
+        let subjects = ClientSubjects::default();
+        let _held = subjects.insert("/ca/a.crt", &cert);
+        assert_eq!(
+            subjects.get("/ca/a.crt", &digest).as_deref(),
+            Some("CN=client")
+        );
+        assert_eq!(subjects.get("/ca/b.crt", &digest), None);
+        assert_eq!(subjects.get("/ca/a.crt", b"other"), None);
+    }
+
+    #[test]
+    fn subjects_kept_while_connected_or_resumable() {
+        let key = ec_key();
+        let cert = certificate(&["client"], &key, None);
+        let digest = cert.digest(openssl::hash::MessageDigest::sha256()).unwrap();
+
+        let subjects = ClientSubjects::default();
+        let held = subjects.insert("/ca/live.crt", &cert);
+        let second = subjects.insert("/ca/live.crt", &cert);
+        drop(second);
+        // Many short connections come and go, whose sessions may be resumed
+        for i in 0..3 * MIN_CLIENT_SUBJECTS {
+            subjects.insert(format!("/ca/{i}.crt").as_str(), &cert);
+        }
+        assert_eq!(
+            subjects.get("/ca/0.crt", &digest).as_deref(),
+            Some("CN=client")
+        );
+
+        // Once their sessions expire, only the subjects of the open connections are kept
+        let expired = Instant::now() + SESSION_TIMEOUT;
+        subjects.subjects.lock().unwrap().prune(expired);
+        assert_eq!(
+            subjects.get("/ca/live.crt", &digest).as_deref(),
+            Some("CN=client")
+        );
+        assert_eq!(subjects.get("/ca/0.crt", &digest), None);
+
+        drop(held);
+        subjects
+            .subjects
+            .lock()
+            .unwrap()
+            .prune(expired + SESSION_TIMEOUT);
+        assert_eq!(subjects.get("/ca/live.crt", &digest), None);
+    }
+}
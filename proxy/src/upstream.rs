@@ -0,0 +1,979 @@
+use crate::circuit_breaker::CircuitBreaker;
+use crate::error::ConfigError;
+use crate::host::normalize_host;
+use crate::outlier::Outlier;
+use crate::upstream_tls::PeerTls;
+use futures::future::join_all;
+use futures::FutureExt;
+use log::{info, warn};
+use pingora::http::{RequestHeader, ResponseHeader};
+use pingora::lb::discovery::Static;
+use pingora::lb::health_check::{self, HttpHealthCheck, TcpHealthCheck};
+use pingora::lb::selection::{BackendIter, BackendSelection, Consistent, RoundRobin};
+use pingora::lb::{Backend as Endpoint, Backends, LoadBalancer};
+use pingora::prelude::HttpPeer;
+use pingora::protocols::l4::socket::SocketAddr as PingoraSocketAddr;
+use pingora::protocols::ALPN;
+use pingora::ErrorType;
+use pingress_config::{
+    Backend, HealthCheck, LoadBalancing, OutlierDetection, Port, Probe, Protocol,
+};
+use rand::seq::SliceRandom;
+use std::collections::{BTreeSet, HashMap};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Destination of requests for one backend
+pub struct Upstream {
+    /// Address reached when no endpoint is known: the cluster DNS name and port of the Service
+    /// (e.g. `name.namespace:80`), or the DNS name and port of an ExternalName Service
+    host: String,
+    /// DNS name of an ExternalName Service, sent as the Host and the SNI of the requests
+    external_name: Option<String>,
+    load_balancing: LoadBalancing,
+    /// Balancer over the Service endpoints, `None` when no endpoint is published
+    balancer: Option<Balancer>,
+    endpoints: HashMap<Endpoint, Arc<EndpointState>>,
+    next: AtomicUsize,
+    /// `None` when the backend has no health check
+    health_check: Option<ActiveCheck>,
+    circuit_breaker: Option<CircuitBreaker>,
+    protocol: Protocol,
+    /// `None` when the endpoints speak plain text
+    tls: Option<PeerTls>,
+}
+
+/// State of an endpoint, carried over reloads as long as its checks are unchanged
+struct EndpointState {
+    /// Active requests
+    active: AtomicUsize,
+    /// `None` when the backend has no health check
+    health: Option<Health>,
+    /// `None` when the backend has no outlier detection
+    outlier: Option<Outlier>,
+}
+
+impl EndpointState {
+    fn new(health_check: Option<&HealthCheck>, outlier: Option<&OutlierDetection>) -> Self {
+        Self {
+            active: AtomicUsize::new(0),
+            health: health_check.map(Health::new),
+            outlier: outlier.cloned().map(Outlier::new),
+        }
+    }
+
+    fn is_healthy(&self) -> bool {
+        self.health.as_ref().is_none_or(Health::is_healthy)
+    }
+
+    fn is_ejected(&self, now: Instant) -> bool {
+        self.outlier.as_ref().is_some_and(|o| o.is_ejected(now))
+    }
+}
+
+/// Result of the active health checks of an endpoint, which starts healthy
+struct Health {
+    healthy_threshold: usize,
+    unhealthy_threshold: usize,
+    /// Whether the endpoint is healthy, and the consecutive results to the contrary
+    state: Mutex<(bool, usize)>,
+}
+
+impl Health {
+    fn new(config: &HealthCheck) -> Self {
+        Self {
+            healthy_threshold: config.healthy_threshold,
+            unhealthy_threshold: config.unhealthy_threshold,
+            state: Mutex::new((true, 0)),
+        }
+    }
+
+    fn is_healthy(&self) -> bool {
+        self.state.lock().unwrap().0
+    }
+
+    /// Record the result of a check. Returns whether the health flipped.
+    fn observe(&self, success: bool) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let (healthy, contrary) = &mut *state;
+        if success == *healthy {
+            *contrary = 0;
+            return false;
+        }
+
+        *contrary += 1;
+        let threshold = if success {
+            self.healthy_threshold
+        } else {
+            self.unhealthy_threshold
+        };
+        if *contrary < threshold {
+            return false;
+        }
+        *healthy = success;
+        *contrary = 0;
+        true
+    }
+}
+
+/// Active health check of the endpoints of a backend
+struct ActiveCheck {
+    config: HealthCheck,
+    check: Box<dyn health_check::HealthCheck + Send + Sync>,
+    /// Time of the next check
+    next: Mutex<Instant>,
+}
+
+/// State of an endpoint, as exported to metrics
+pub(crate) struct EndpointStatus {
+    pub(crate) address: String,
+    /// `None` when the backend has no health check
+    pub(crate) healthy: Option<bool>,
+    /// `None` when the backend has no outlier detection
+    pub(crate) ejected: Option<bool>,
+}
+
+enum Balancer {
+    RoundRobin(LoadBalancer<RoundRobin>),
+    Consistent(LoadBalancer<Consistent>),
+}
+
+impl Balancer {
+    fn backends(&self) -> &Backends {
+        match self {
+            Balancer::RoundRobin(lb) => lb.backends(),
+            Balancer::Consistent(lb) => lb.backends(),
+        }
+    }
+}
+
+/// Endpoint chosen for a request
+pub struct Selected {
+    pub address: String,
+    active: Option<ActiveRequest>,
+}
+
+impl Selected {
+    /// Feed the outcome of the request to the outlier detection of the endpoint.
+    ///
+    /// A failure is a 5xx response or a failure to connect.
+    pub(crate) fn observe(&self, success: bool) {
+        let Some(outlier) = self.active.as_ref().and_then(|a| a.0.outlier.as_ref()) else {
+            return;
+        };
+        if let Some(ejection) = outlier.observe(success, Instant::now()) {
+            warn!("{} is ejected for {ejection:?}", self.address);
+        }
+    }
+}
+
+/// Counts a request as active on its endpoint until dropped
+struct ActiveRequest(Arc<EndpointState>);
+
+impl Drop for ActiveRequest {
+    fn drop(&mut self) {
+        self.0.active.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Upstream {
+    /// Key identifying the backend, shared by every rule routing to it
+    pub(crate) fn key(backend: &Backend) -> String {
+        match backend {
+            Backend::Service {
+                name,
+                namespace,
+                port: Port::Number(port),
+                ..
+            } => format!("{name}.{namespace}:{port}"),
+            Backend::Service {
+                name,
+                namespace,
+                port: Port::Name(port),
+                ..
+            } => format!("{name}.{namespace}:{port}"),
+            Backend::ExternalName {
+                name,
+                namespace,
+                port,
+                ..
+            } => format!("{name}.{namespace}:{port}"),
+            Backend::Resource {
+                kind,
+                name,
+                namespace,
+                ..
+            } => format!("{kind} {namespace}/{name}"),
+            Backend::Response { status, .. } => format!("response {status}"),
+        }
+    }
+
+    /// Cluster DNS name and port of the Service, or the external name and port of an
+    /// ExternalName Service
+    pub(crate) fn name(&self) -> &str {
+        self.host.as_str()
+    }
+
+    /// DNS name of an ExternalName Service, `None` for other Services
+    pub(crate) fn external_name(&self) -> Option<&str> {
+        self.external_name.as_deref()
+    }
+
+    pub(crate) fn load_balancing(&self) -> &LoadBalancing {
+        &self.load_balancing
+    }
+
+    /// Peer for a request to `host` on the endpoint at `address`, resolved by [resolve]
+    pub(crate) fn peer(&self, address: SocketAddr, host: &str) -> HttpPeer {
+        let host = self.external_name().unwrap_or(host);
+        let mut peer = match &self.tls {
+            Some(tls) => {
+                let sni = tls.sni(normalize_host(host).as_str()).to_string();
+                let mut peer = HttpPeer::new(address, true, sni);
+                tls.apply(&mut peer);
+                peer
+            }
+            None => HttpPeer::new(address, false, host.to_string()),
+        };
+        peer.options.alpn = alpn(self.protocol);
+        peer
+    }
+
+    /// Whether the health check is due at `now`. The next one is scheduled when it is.
+    pub(crate) fn health_check_due(&self, now: Instant) -> bool {
+        let Some(health_check) = &self.health_check else {
+            return false;
+        };
+
+        let mut next = health_check.next.lock().unwrap();
+        if *next > now {
+            return false;
+        }
+        *next = now + Duration::from_millis(health_check.config.interval_ms.max(1));
+        true
+    }
+
+    /// Check every endpoint in parallel.
+    ///
+    /// An endpoint is taken out of rotation after `unhealthy_threshold` consecutive failures
+    /// and put back after `healthy_threshold` consecutive successes.
+    pub(crate) async fn run_health_check(&self) {
+        let Some(health_check) = &self.health_check else {
+            return;
+        };
+        let checks = self.endpoints.iter().map(|(endpoint, state)| async move {
+            let result = health_check.check.check(endpoint).await;
+            let Some(health) = &state.health else {
+                return;
+            };
+            if health.observe(result.is_ok()) {
+                match result {
+                    Ok(()) => info!("{} of {} becomes healthy", endpoint.addr, self.host),
+                    Err(e) => warn!("{} of {} becomes unhealthy: {e}", endpoint.addr, self.host),
+                }
+            }
+        });
+        join_all(checks).await;
+    }
+
+    pub(crate) fn circuit_breaker(&self) -> Option<&CircuitBreaker> {
+        self.circuit_breaker.as_ref()
+    }
+
+    pub(crate) fn endpoint_status(&self) -> Vec<EndpointStatus> {
+        let Some(balancer) = &self.balancer else {
+            return Vec::new();
+        };
+        let backends = balancer.backends();
+        let now = Instant::now();
+        backends
+            .get_backend()
+            .iter()
+            .map(|e| {
+                let state = self.endpoints.get(e);
+                EndpointStatus {
+                    address: e.addr.to_string(),
+                    healthy: state
+                        .and_then(|s| s.health.as_ref())
+                        .map(Health::is_healthy),
+                    ejected: state
+                        .filter(|s| s.outlier.is_some())
+                        .map(|s| s.is_ejected(now)),
+                }
+            })
+            .collect()
+    }
+
+    /// Choose the endpoint for a request.
+    ///
+    /// `key` is used by consistent hashing only. Ejected endpoints are skipped unless every
+    /// healthy endpoint is ejected. The Service is reached through its DNS name when no endpoint
+    /// is known or every endpoint fails its health check.
+    pub fn select(&self, key: &[u8]) -> Selected {
+        self.select_excluding(key, &[])
+    }
+
+    /// Choose the endpoint for a retried request, avoiding the endpoints already `tried`
+    /// unless none is left.
+    pub(crate) fn select_excluding(&self, key: &[u8], tried: &[String]) -> Selected {
+        let now = Instant::now();
+        let not_ejected = |e: &Endpoint| !self.is_ejected(e, now);
+        let not_tried = |e: &Endpoint| {
+            not_ejected(e) && (tried.is_empty() || !tried.contains(&e.addr.to_string()))
+        };
+        let endpoint = if self.has_available_endpoint(not_tried) {
+            self.select_endpoint(key, not_tried)
+        } else if self.has_available_endpoint(not_ejected) {
+            self.select_endpoint(key, not_ejected)
+        } else {
+            self.select_endpoint(key, |_| true)
+        };
+
+        match endpoint {
+            Some(endpoint) => {
+                let active = self.endpoints.get(&endpoint).map(|s| {
+                    s.active.fetch_add(1, Ordering::Relaxed);
+                    ActiveRequest(s.clone())
+                });
+                Selected {
+                    address: endpoint.addr.to_string(),
+                    active,
+                }
+            }
+            None => Selected {
+                address: self.host.clone(),
+                active: None,
+            },
+        }
+    }
+
+    /// Choose a healthy endpoint among the accepted ones
+    fn select_endpoint(&self, key: &[u8], accept: impl Fn(&Endpoint) -> bool) -> Option<Endpoint> {
+        match (&self.balancer, &self.load_balancing) {
+            (None, _) => None,
+            (Some(Balancer::Consistent(lb)), _) => {
+                lb.select_with(key, 256, |e, _| self.is_ready(e) && accept(e))
+            }
+            (Some(Balancer::RoundRobin(lb)), LoadBalancing::LeastConnections) => {
+                self.least_connections(self.ready_endpoints(lb, accept))
+            }
+            (Some(Balancer::RoundRobin(lb)), LoadBalancing::RandomTwoChoices) => {
+                self.random_two_choices(self.ready_endpoints(lb, accept))
+            }
+            (Some(Balancer::RoundRobin(lb)), _) => {
+                lb.select_with(b"", 256, |e, _| self.is_ready(e) && accept(e))
+            }
+        }
+    }
+
+    /// Whether a healthy endpoint is accepted
+    fn has_available_endpoint(&self, accept: impl Fn(&Endpoint) -> bool) -> bool {
+        let Some(balancer) = &self.balancer else {
+            return false;
+        };
+        balancer
+            .backends()
+            .get_backend()
+            .iter()
+            .any(|e| self.is_ready(e) && accept(e))
+    }
+
+    /// Whether the endpoint passes its health check, when it has one
+    fn is_ready(&self, endpoint: &Endpoint) -> bool {
+        self.endpoints.get(endpoint).is_none_or(|s| s.is_healthy())
+    }
+
+    fn is_ejected(&self, endpoint: &Endpoint, now: Instant) -> bool {
+        self.endpoints
+            .get(endpoint)
+            .is_some_and(|s| s.is_ejected(now))
+    }
+
+    fn active_requests(&self, endpoint: &Endpoint) -> usize {
+        self.endpoints
+            .get(endpoint)
+            .map(|s| s.active.load(Ordering::Relaxed))
+            .unwrap_or_default()
+    }
+
+    fn ready_endpoints<S>(
+        &self,
+        lb: &LoadBalancer<S>,
+        accept: impl Fn(&Endpoint) -> bool,
+    ) -> Vec<Endpoint>
+    where
+        S: BackendSelection + 'static,
+        S::Iter: BackendIter,
+    {
+        lb.backends()
+            .get_backend()
+            .iter()
+            .filter(|e| self.is_ready(e) && accept(e))
+            .cloned()
+            .collect()
+    }
+
+    fn least_connections(&self, endpoints: Vec<Endpoint>) -> Option<Endpoint> {
+        if endpoints.is_empty() {
+            return None;
+        }
+
+        // Start from a rotating offset so that ties are spread evenly
+        let start = self.next.fetch_add(1, Ordering::Relaxed) % endpoints.len();
+        endpoints
+            .iter()
+            .cycle()
+            .skip(start)
+            .take(endpoints.len())
+            .min_by_key(|e| self.active_requests(e))
+            .cloned()
+    }
+
+    fn random_two_choices(&self, endpoints: Vec<Endpoint>) -> Option<Endpoint> {
+        endpoints
+            .choose_multiple(&mut rand::thread_rng(), 2)
+            .min_by_key(|e| self.active_requests(e))
+            .cloned()
+    }
+}
+
+impl TryFrom<Backend> for Upstream {
+    type Error = ConfigError;
+
+    fn try_from(value: Backend) -> Result<Self, Self::Error> {
+        Self::new(value, None)
+    }
+}
+
+impl Upstream {
+    /// Upstream of the backend, taking over the state of the `previous` upstream of the same
+    /// backend: the health and outlier state of the endpoints still published, and the
+    /// requests counted by the circuit breaker.
+    pub(crate) fn new(value: Backend, previous: Option<&Upstream>) -> Result<Self, ConfigError> {
+        let host = Upstream::key(&value);
+        let Backend::Service {
+            port,
+            endpoints,
+            load_balancing,
+            health_check,
+            outlier_detection,
+            circuit_breaker,
+            protocol,
+            tls,
+            ..
+        } = value
+        else {
+            return Self::without_endpoints(host, value, previous);
+        };
+        // The controller resolves named ports, there would be no port to reach the Service by
+        if let Port::Name(_) = port {
+            return Err(ConfigError::UnresolvedPort(host));
+        }
+        let tls = PeerTls::load(host.as_str(), protocol, tls)?;
+
+        let weighted = load_balancing == LoadBalancing::WeightedRoundRobin;
+        let endpoints = endpoints
+            .into_iter()
+            .map(|e| {
+                let ip: IpAddr = e
+                    .address
+                    .parse()
+                    .map_err(|_| ConfigError::InvalidEndpoint(host.clone(), e.address.clone()))?;
+                Ok(Endpoint {
+                    addr: PingoraSocketAddr::Inet(SocketAddr::new(ip, e.port)),
+                    weight: if weighted {
+                        e.weight.max(1) as usize
+                    } else {
+                        1
+                    },
+                })
+            })
+            .collect::<Result<BTreeSet<_>, ConfigError>>()?;
+
+        // Endpoints still published keep their state, unless they are checked differently now
+        let previous_states: HashMap<_, _> = previous
+            .filter(|p| p.health_check.as_ref().map(|c| &c.config) == health_check.as_ref())
+            .map(|p| {
+                p.endpoints
+                    .iter()
+                    .filter(|(_, s)| {
+                        s.outlier.as_ref().map(Outlier::config) == outlier_detection.as_ref()
+                    })
+                    .map(|(e, s)| (&e.addr, s))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let states = endpoints
+            .iter()
+            .map(|e| {
+                let state = match previous_states.get(&e.addr) {
+                    Some(state) => Arc::clone(state),
+                    None => Arc::new(EndpointState::new(
+                        health_check.as_ref(),
+                        outlier_detection.as_ref(),
+                    )),
+                };
+                (e.clone(), state)
+            })
+            .collect();
+        let health_check = health_check
+            .map(|config| {
+                let check = health_checker(host.as_str(), &config, protocol, tls.as_ref())?;
+                Ok(ActiveCheck {
+                    config,
+                    check,
+                    next: Mutex::new(Instant::now()),
+                })
+            })
+            .transpose()?;
+        let balancer = if endpoints.is_empty() {
+            None
+        } else if matches!(load_balancing, LoadBalancing::ConsistentHash { .. }) {
+            Some(Balancer::Consistent(load_balancer(endpoints)))
+        } else {
+            Some(Balancer::RoundRobin(load_balancer(endpoints)))
+        };
+
+        Ok(Self {
+            host,
+            external_name: None,
+            load_balancing,
+            balancer,
+            endpoints: states,
+            next: AtomicUsize::new(0),
+            health_check,
+            circuit_breaker: inherit_circuit_breaker(circuit_breaker, previous),
+            protocol,
+            tls,
+        })
+    }
+
+    /// Upstream of a backend without endpoints, reached through its DNS name
+    fn without_endpoints(
+        key: String,
+        backend: Backend,
+        previous: Option<&Upstream>,
+    ) -> Result<Self, ConfigError> {
+        match backend {
+            Backend::ExternalName {
+                external_name,
+                port,
+                circuit_breaker,
+                protocol,
+                tls,
+                ..
+            } => Ok(Self {
+                host: format!("{external_name}:{port}"),
+                tls: PeerTls::load(key.as_str(), protocol, tls)?,
+                external_name: Some(external_name),
+                load_balancing: LoadBalancing::default(),
+                balancer: None,
+                endpoints: HashMap::new(),
+                next: AtomicUsize::new(0),
+                health_check: None,
+                circuit_breaker: inherit_circuit_breaker(circuit_breaker, previous),
+                protocol,
+            }),
+            Backend::Resource { .. } => Err(ConfigError::UnresolvedResource(key)),
+            Backend::Service { .. } | Backend::Response { .. } => {
+                Err(ConfigError::NotUpstream(key))
+            }
+        }
+    }
+}
+
+fn inherit_circuit_breaker(
+    config: Option<pingress_config::CircuitBreaker>,
+    previous: Option<&Upstream>,
+) -> Option<CircuitBreaker> {
+    let mut circuit_breaker = CircuitBreaker::new(config?);
+    if let Some(previous) = previous.and_then(|p| p.circuit_breaker.as_ref()) {
+        circuit_breaker.inherit(previous);
+    }
+    Some(circuit_breaker)
+}
+
+type HealthChecker = Box<dyn health_check::HealthCheck + Send + Sync>;
+
+/// Pingora health check of the backend.
+///
+/// TLS backends are checked over TLS, with the Service name as server name unless overridden.
+/// TCP probes of backends requiring a client certificate stop at the TCP connection.
+fn health_checker(
+    host: &str,
+    config: &HealthCheck,
+    protocol: Protocol,
+    tls: Option<&PeerTls>,
+) -> Result<HealthChecker, ConfigError> {
+    let timeout = Duration::from_millis(config.timeout_ms);
+    let server = normalize_host(host);
+    let checker: HealthChecker = match &config.probe {
+        Probe::Tcp => {
+            let mut check = match tls.filter(|t| !t.is_mutual()) {
+                Some(tls) => {
+                    let mut check = TcpHealthCheck::new_tls(tls.sni(server.as_str()));
+                    tls.apply_options(&mut check.peer_template.options);
+                    check
+                }
+                None => TcpHealthCheck::new(),
+            };
+            check.consecutive_success = config.healthy_threshold;
+            check.consecutive_failure = config.unhealthy_threshold;
+            check.peer_template.options.connection_timeout = Some(timeout);
+            check
+        }
+        Probe::Http { path } => {
+            let mut check = HttpHealthCheck::new(host, tls.is_some());
+            if let Some(tls) = tls {
+                check.peer_template.sni = tls.sni(server.as_str()).to_string();
+                tls.apply(&mut check.peer_template);
+            }
+            check.peer_template.options.alpn = alpn(protocol);
+            check.consecutive_success = config.healthy_threshold;
+            check.consecutive_failure = config.unhealthy_threshold;
+            check.peer_template.options.connection_timeout = Some(timeout);
+            check.peer_template.options.read_timeout = Some(timeout);
+            check.req = RequestHeader::build("GET", path.as_bytes(), None)
+                .and_then(|mut req| req.append_header("Host", host).map(|_| req))
+                .map_err(|e| ConfigError::InvalidHealthCheck(host.to_string(), e.to_string()))?;
+            check.validator = Some(Box::new(|resp: &ResponseHeader| {
+                if resp.status.is_success() {
+                    Ok(())
+                } else {
+                    pingora::Error::e_explain(
+                        ErrorType::CustomCode("non 2xx code", resp.status.as_u16()),
+                        "during http healthcheck",
+                    )
+                }
+            }));
+            Box::new(check)
+        }
+    };
+    Ok(checker)
+}
+
+/// HTTP versions offered to the endpoints. HTTP/2 over plain text needs prior knowledge.
+fn alpn(protocol: Protocol) -> ALPN {
+    if protocol.is_http2() {
+        ALPN::H2
+    } else {
+        ALPN::H1
+    }
+}
+
+/// Socket address of a selected endpoint.
+///
+/// The DNS names of ExternalName Services and of Services without known endpoints are resolved
+/// without blocking the runtime. A name that does not resolve fails the request with 502.
+pub(crate) async fn resolve(address: &str) -> pingora::Result<SocketAddr> {
+    if let Ok(address) = address.parse() {
+        return Ok(address);
+    }
+    let mut addresses = tokio::net::lookup_host(address).await.or_else(|e| {
+        pingora::Error::e_explain(
+            ErrorType::HTTPStatus(502),
+            format!("failed to resolve {address}: {e}"),
+        )
+    })?;
+    match addresses.next() {
+        Some(address) => Ok(address),
+        None => pingora::Error::e_explain(
+            ErrorType::HTTPStatus(502),
+            format!("{address} has no address"),
+        ),
+    }
+}
+
+fn load_balancer<S>(endpoints: BTreeSet<Endpoint>) -> LoadBalancer<S>
+where
+    S: BackendSelection + 'static,
+    S::Iter: BackendIter,
+{
+    let lb = LoadBalancer::from_backends(Backends::new(Static::new(endpoints)));
+    lb.update()
+        .now_or_never()
+        .expect("static discovery never blocks")
+        .expect("static discovery never fails");
+    lb
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::upstream::{resolve, Upstream};
+    use pingress_config::{
+        Backend, CircuitBreaker, Endpoint, HashKey, HealthCheck, LoadBalancing, OutlierDetection,
+        Port, Probe, Protocol,
+    };
+    use std::collections::HashMap;
+    use std::time::{Duration, Instant};
+    use tokio::net::TcpListener;
+
+    fn backend(load_balancing: LoadBalancing, weights: &[u16]) -> Backend {
+        Backend::Service {
+            name: "backend".to_string(),
+            namespace: "default".to_string(),
+            port: Port::Number(80),
+            endpoints: weights
+                .iter()
+                .enumerate()
+                .map(|(i, weight)| Endpoint {
+                    address: format!("10.0.0.{}", i + 1),
+                    port: 8080,
+                    weight: *weight,
+                })
+                .collect(),
+            load_balancing,
+            health_check: None,
+            outlier_detection: None,
+            circuit_breaker: None,
+            protocol: Protocol::default(),
+            tls: None,
+        }
+    }
+
+    fn upstream(load_balancing: LoadBalancing, weights: &[u16]) -> Upstream {
+        Upstream::try_from(backend(load_balancing, weights)).unwrap()
+    }
+
+    fn count(upstream: &Upstream, n: usize) -> HashMap<String, usize> {
+        let mut counts = HashMap::new();
+        for _ in 0..n {
+            *counts.entry(upstream.select(b"").address).or_default() += 1;
+        }
+        counts
+    }
+
+    #[test]
+    fn unresolved_port() {
+        let mut backend = backend(LoadBalancing::RoundRobin, &[1]);
+        let Backend::Service { port, .. } = &mut backend else {
+            unreachable!()
+        };
+        *port = Port::Name("http".to_string());
+        assert!(Upstream::try_from(backend).is_err());
+    }
+
+    #[test]
+    fn round_robin_ignores_weights() {
+        let counts = count(&upstream(LoadBalancing::RoundRobin, &[1, 3]), 400);
+        assert_eq!(counts["10.0.0.1:8080"], 200);
+        assert_eq!(counts["10.0.0.2:8080"], 200);
+    }
+
+    #[test]
+    fn weighted_round_robin() {
+        let counts = count(&upstream(LoadBalancing::WeightedRoundRobin, &[1, 3]), 400);
+        assert_eq!(counts["10.0.0.1:8080"], 100);
+        assert_eq!(counts["10.0.0.2:8080"], 300);
+    }
+
+    #[test]
+    fn least_connections() {
+        let upstream = upstream(LoadBalancing::LeastConnections, &[1, 1, 1]);
+        let first = upstream.select(b"");
+        let second = upstream.select(b"");
+        let third = upstream.select(b"");
+        let mut busy = vec![&first.address, &second.address, &third.address];
+        busy.sort();
+        busy.dedup();
+        assert_eq!(busy.len(), 3);
+
+        // Only the released endpoint has no active request
+        let released = second.address.clone();
+        drop(second);
+        assert_eq!(upstream.select(b"").address, released);
+    }
+
+    #[test]
+    fn random_two_choices() {
+        let upstream = upstream(LoadBalancing::RandomTwoChoices, &[1, 1]);
+        let first = upstream.select(b"");
+        // Both endpoints are always compared, so the idle one wins
+        for _ in 0..10 {
+            assert_ne!(upstream.select(b"").address, first.address);
+        }
+    }
+
+    #[test]
+    fn consistent_hash() {
+        let upstream = upstream(
+            LoadBalancing::ConsistentHash {
+                key: HashKey::ClientIp,
+            },
+            &[1, 1, 1, 1],
+        );
+        for key in ["a", "b", "c", "d", "e"] {
+            let address = upstream.select(key.as_bytes()).address;
+            for _ in 0..10 {
+                assert_eq!(upstream.select(key.as_bytes()).address, address);
+            }
+        }
+    }
+
+    #[test]
+    fn without_endpoints() {
+        let upstream = upstream(LoadBalancing::LeastConnections, &[]);
+        assert_eq!(upstream.select(b"").address, "backend.default:80");
+    }
+
+    #[tokio::test]
+    async fn unhealthy_endpoint_out_of_rotation() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let healthy = listener.local_addr().unwrap();
+        let closed = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let unhealthy = closed.local_addr().unwrap();
+        drop(closed);
+
+        let mut health_check = HealthCheck::new(Probe::Tcp);
+        health_check.unhealthy_threshold = 1;
+        let backend = Backend::Service {
+            name: "backend".to_string(),
+            namespace: "default".to_string(),
+            port: Port::Number(80),
+            endpoints: [healthy, unhealthy]
+                .iter()
+                .map(|a| Endpoint {
+                    address: a.ip().to_string(),
+                    port: a.port(),
+                    weight: 1,
+                })
+                .collect(),
+            load_balancing: LoadBalancing::RoundRobin,
+            health_check: Some(health_check),
+            outlier_detection: None,
+            circuit_breaker: None,
+            protocol: Protocol::default(),
+            tls: None,
+        };
+        let upstream = Upstream::try_from(backend.clone()).unwrap();
+
+        let now = Instant::now();
+        assert!(upstream.health_check_due(now));
+        assert!(!upstream.health_check_due(now + Duration::from_secs(1)));
+        assert!(upstream.health_check_due(now + Duration::from_secs(10)));
+
+        upstream.run_health_check().await;
+        let mut health: Vec<_> = upstream
+            .endpoint_status()
+            .into_iter()
+            .map(|s| (s.address, s.healthy))
+            .collect();
+        health.sort();
+        let mut expected = vec![
+            (healthy.to_string(), Some(true)),
+            (unhealthy.to_string(), Some(false)),
+        ];
+        expected.sort();
+        assert_eq!(health, expected);
+
+        for _ in 0..4 {
+            assert_eq!(upstream.select(b"").address, healthy.to_string());
+        }
+
+        // Still out of rotation after a reload, until checked again
+        let reloaded = Upstream::new(backend, Some(&upstream)).unwrap();
+        for _ in 0..4 {
+            assert_eq!(reloaded.select(b"").address, healthy.to_string());
+        }
+    }
+
+    #[test]
+    fn ejected_endpoint_out_of_rotation() {
+        let mut backend = backend(LoadBalancing::RoundRobin, &[1, 1]);
+        let Backend::Service {
+            outlier_detection, ..
+        } = &mut backend
+        else {
+            unreachable!()
+        };
+        *outlier_detection = Some(OutlierDetection {
+            consecutive_errors: 1,
+            ..OutlierDetection::default()
+        });
+        let upstream = Upstream::try_from(backend).unwrap();
+
+        let failed = upstream.select(b"");
+        failed.observe(false);
+        for _ in 0..4 {
+            assert_ne!(upstream.select(b"").address, failed.address);
+        }
+
+        // Every endpoint ejected: fall back to all of them
+        upstream.select(b"").observe(false);
+        let mut selected: Vec<_> = (0..4).map(|_| upstream.select(b"").address).collect();
+        selected.sort();
+        selected.dedup();
+        assert_eq!(selected.len(), 2);
+    }
+
+    #[test]
+    fn inherit_state_on_reload() {
+        let with_settings = |weights: &[u16], consecutive_errors| {
+            let mut backend = backend(LoadBalancing::RoundRobin, weights);
+            let Backend::Service {
+                outlier_detection,
+                circuit_breaker,
+                ..
+            } = &mut backend
+            else {
+                unreachable!()
+            };
+            *outlier_detection = Some(OutlierDetection {
+                consecutive_errors,
+                ..OutlierDetection::default()
+            });
+            *circuit_breaker = Some(CircuitBreaker {
+                max_requests: 1,
+                ..CircuitBreaker::default()
+            });
+            backend
+        };
+        let previous = Upstream::try_from(with_settings(&[1, 1], 1)).unwrap();
+        let failed = previous.select(b"");
+        failed.observe(false);
+        let permit = previous.circuit_breaker().unwrap().try_acquire();
+        assert!(permit.is_some());
+
+        // A new endpoint is published
+        let reloaded = Upstream::new(with_settings(&[1, 1, 1], 1), Some(&previous)).unwrap();
+        for _ in 0..6 {
+            assert_ne!(reloaded.select(b"").address, failed.address);
+        }
+        let circuit_breaker = reloaded.circuit_breaker().unwrap();
+        assert!(circuit_breaker.try_acquire().is_none());
+        drop(permit);
+        assert!(circuit_breaker.try_acquire().is_some());
+
+        // The ejection does not hold under other settings
+        let changed = Upstream::new(with_settings(&[1, 1, 1], 2), Some(&previous)).unwrap();
+        assert!((0..6).any(|_| changed.select(b"").address == failed.address));
+    }
+
+    #[test]
+    fn select_excluding_tried() {
+        let upstream = upstream(LoadBalancing::RoundRobin, &[1, 1, 1]);
+        let tried = vec!["10.0.0.1:8080".to_string(), "10.0.0.3:8080".to_string()];
+        for _ in 0..4 {
+            let selected = upstream.select_excluding(b"", &tried);
+            assert_eq!(selected.address, "10.0.0.2:8080");
+        }
+
+        // Every endpoint tried: any of them
+        let tried: Vec<_> = (1..=3).map(|i| format!("10.0.0.{i}:8080")).collect();
+        assert!(tried.contains(&upstream.select_excluding(b"", &tried).address));
+    }
+
+    #[tokio::test]
+    async fn resolve_addresses() {
+        assert_eq!(
+            resolve("10.0.0.1:8080").await.unwrap(),
+            "10.0.0.1:8080".parse().unwrap()
+        );
+        assert!(resolve("localhost:80").await.unwrap().ip().is_loopback());
+
+        let e = resolve("backend.invalid:80").await.unwrap_err();
+        assert_eq!(e.etype(), &pingora::ErrorType::HTTPStatus(502));
+    }
+}
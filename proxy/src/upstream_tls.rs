@@ -0,0 +1,146 @@
+use crate::error::ConfigError;
+use pingora::prelude::HttpPeer;
+use pingora::tls::pkey::PKey;
+use pingora::tls::x509::X509;
+use pingora::upstreams::peer::PeerOptions;
+use pingora::utils::CertKey;
+use pingress_config::{Protocol, Tls, UpstreamTls};
+use std::fs::read;
+use std::sync::Arc;
+
+/// TLS to the endpoints of a backend, loaded once per configuration
+pub(crate) struct PeerTls {
+    /// Server name overriding the host of the request
+    sni: Option<String>,
+    /// CAs verifying the endpoints, which are not verified when `None`
+    ca: Option<Arc<Box<[X509]>>>,
+    /// Client certificate for mutual TLS
+    client_cert: Option<Arc<CertKey>>,
+}
+
+impl PeerTls {
+    /// TLS of the backend, `None` when its protocol is plain text
+    pub(crate) fn load(
+        backend: &str,
+        protocol: Protocol,
+        tls: Option<UpstreamTls>,
+    ) -> Result<Option<Self>, ConfigError> {
+        if !protocol.is_tls() {
+            return Ok(None);
+        }
+        let tls = tls.unwrap_or_default();
+        let invalid = |e| ConfigError::InvalidTls(backend.to_string(), e);
+
+        let ca = tls
+            .ca
+            .as_deref()
+            .map(load_ca)
+            .transpose()
+            .map_err(invalid)?;
+        let client_cert = tls
+            .client_cert
+            .as_ref()
+            .map(load_cert_key)
+            .transpose()
+            .map_err(invalid)?;
+
+        Ok(Some(Self {
+            sni: tls.sni,
+            ca,
+            client_cert,
+        }))
+    }
+
+    /// Server name of the endpoints, or `host` when it is not overridden
+    pub(crate) fn sni<'a>(&'a self, host: &'a str) -> &'a str {
+        self.sni.as_deref().unwrap_or(host)
+    }
+
+    /// Whether the endpoints require a client certificate
+    pub(crate) fn is_mutual(&self) -> bool {
+        self.client_cert.is_some()
+    }
+
+    /// Set the TLS options of a peer
+    pub(crate) fn apply(&self, peer: &mut HttpPeer) {
+        self.apply_options(&mut peer.options);
+        peer.client_cert_key.clone_from(&self.client_cert);
+    }
+
+    /// Set the TLS options of a peer without client certificate
+    pub(crate) fn apply_options(&self, options: &mut PeerOptions) {
+        options.verify_cert = self.ca.is_some();
+        options.verify_hostname = self.ca.is_some();
+        options.ca.clone_from(&self.ca);
+    }
+}
+
+fn load_ca(path: &str) -> Result<Arc<Box<[X509]>>, String> {
+    let pem = read(path).map_err(|e| format!("{path}: {e}"))?;
+    let certs = X509::stack_from_pem(pem.as_slice()).map_err(|e| format!("{path}: {e}"))?;
+    if certs.is_empty() {
+        return Err(format!("{path}: no certificate"));
+    }
+    Ok(Arc::new(certs.into_boxed_slice()))
+}
+
+fn load_cert_key(tls: &Tls) -> Result<Arc<CertKey>, String> {
+    let pk = read(tls.key.as_str()).map_err(|e| format!("{}: {e}", tls.key))?;
+    let ct = read(tls.cert.as_str()).map_err(|e| format!("{}: {e}", tls.cert))?;
+
+    let key = PKey::private_key_from_pem(pk.as_slice()).map_err(|e| format!("{}: {e}", tls.key))?;
+    let certs = X509::stack_from_pem(ct.as_slice()).map_err(|e| format!("{}: {e}", tls.cert))?;
+    if certs.is_empty() {
+        return Err(format!("{}: no certificate", tls.cert));
+    }
+    Ok(Arc::new(CertKey::new(certs, key)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::upstream_tls::PeerTls;
+    use pingora::prelude::HttpPeer;
+    use pingress_config::{Protocol, UpstreamTls};
+
+    #[test]
+    fn plain_text_backend() {
+        let tls = UpstreamTls {
+            sni: Some("backend.internal".to_string()),
+            ..UpstreamTls::default()
+        };
+        assert!(PeerTls::load("backend", Protocol::Http, Some(tls))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn tls_options() {
+        let tls = UpstreamTls {
+            sni: Some("backend.internal".to_string()),
+            ..UpstreamTls::default()
+        };
+        let peer_tls = PeerTls::load("backend", Protocol::Grpcs, Some(tls))
+            .unwrap()
+            .unwrap();
+        assert_eq!(peer_tls.sni("example.com"), "backend.internal");
+
+        let mut peer = HttpPeer::new("127.0.0.1:8443", true, String::new());
+        peer_tls.apply(&mut peer);
+        assert!(!peer.options.verify_cert);
+        assert!(peer.client_cert_key.is_none());
+
+        let peer_tls = PeerTls::load("backend", Protocol::Https, None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(peer_tls.sni("example.com"), "example.com");
+    }
+
+    #[test]
+    fn missing_ca() {
+        let tls = UpstreamTls {
+            ca: Some("/nonexistent/ca.crt".to_string()),
+            ..UpstreamTls::default()
+        };
+        assert!(PeerTls::load("backend", Protocol::Https, Some(tls)).is_err());
+    }
+}
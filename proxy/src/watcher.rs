@@ -1,53 +1,112 @@
+use crate::config::load_configuration;
+use crate::proxy_map::ProxyMap;
 use crate::tls::TlsMap;
-use log::error;
-use nix::sys::signal::Signal;
-use notify::{recommended_watcher, RecursiveMode, Watcher};
-use pingress_config::PingressConfiguration;
-use std::fs::File;
+use arc_swap::ArcSwap;
+use log::{error, info};
+use notify::{recommended_watcher, EventKind, RecursiveMode, Watcher};
 use std::path::PathBuf;
 use std::str::FromStr;
-use std::sync::mpsc::channel;
-use std::sync::{Arc, RwLock};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::sync::Arc;
+use std::time::Duration;
 
-pub(crate) fn run_reload(watch: &str, config: &str, tls: &Arc<RwLock<TlsMap>>) {
+/// Period to wait for a burst of file system events to settle before reloading.
+///
+/// Kubernetes updates a mounted ConfigMap or Secret by writing a new timestamped directory
+/// and swapping the `..data` symlink, which produces several create/remove events at once.
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+pub(crate) fn run_reload(
+    watch: &[String],
+    config: &str,
+    proxy_map: &ArcSwap<ProxyMap>,
+    tls: &ArcSwap<TlsMap>,
+) {
     let (tx, rx) = channel();
     let mut watcher = recommended_watcher(tx).unwrap();
-    let path = PathBuf::from_str(watch).unwrap();
-    watcher
-        .watch(path.as_path(), RecursiveMode::Recursive)
-        .unwrap();
-
-    for event in rx {
-        match event {
-            Ok(e) => {
-                if e.kind.is_modify() {
-                    reload_tls_map(&tls, config);
-
-                    let my_pid = nix::unistd::Pid::this();
-                    if let Err(e) = nix::sys::signal::kill(my_pid, Signal::SIGTERM) {
-                        error!("Cannot send signal {} to {my_pid}: {e}", Signal::SIGTERM);
-                    }
-                }
-            }
-            Err(e) => {
-                error!("Event error: {e}");
+    for w in watch {
+        let path = PathBuf::from_str(w).unwrap();
+        watcher
+            .watch(path.as_path(), RecursiveMode::Recursive)
+            .unwrap();
+    }
+
+    while let Ok(event) = rx.recv() {
+        if !is_change(event) {
+            continue;
+        }
+
+        // Drain the rest of the burst
+        loop {
+            match rx.recv_timeout(DEBOUNCE) {
+                Ok(_) => continue,
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => return,
             }
         }
+
+        reload(proxy_map, tls, config);
     }
 }
 
-fn reload_tls_map(tls: &Arc<RwLock<TlsMap>>, config: &str) {
-    let config: PingressConfiguration = {
-        let file = File::open(config).unwrap();
-        serde_json::from_reader(file).unwrap()
-    };
+fn is_change(event: notify::Result<notify::Event>) -> bool {
+    match event {
+        Ok(e) => matches!(
+            e.kind,
+            EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+        ),
+        Err(e) => {
+            error!("Event error: {e}");
+            false
+        }
+    }
+}
 
-    match tls.write() {
-        Ok(mut t) => {
-            *t = config.into();
+fn reload(proxy_map: &ArcSwap<ProxyMap>, tls: &ArcSwap<TlsMap>, config: &str) {
+    match load_configuration(config, Some(&proxy_map.load())) {
+        Ok(loaded) => {
+            loaded.tls.inherit_ocsp(&tls.load());
+            proxy_map.store(Arc::new(loaded.proxy_map));
+            tls.store(Arc::new(loaded.tls));
+            info!("Reloaded configuration");
         }
         Err(e) => {
-            error!("Error: Cannot lock tls map: {e}");
+            error!("Error: Keep the current configuration: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::proxy_map::ProxyMap;
+    use crate::tls::TlsMap;
+    use crate::watcher::reload;
+    use arc_swap::ArcSwap;
+    use pingress_config::PingressConfiguration;
+    use std::sync::Arc;
+
+    fn empty() -> PingressConfiguration {
+        PingressConfiguration {
+            rules: vec![],
+            default_backend: None,
+            not_found_body: None,
+            default_tls: None,
         }
     }
+
+    #[test]
+    fn keep_last_good_configuration() {
+        let proxy_map = ArcSwap::from_pointee(ProxyMap::from(empty()));
+        let tls = ArcSwap::from_pointee(TlsMap::from(empty()));
+        let before = proxy_map.load_full();
+
+        let path = std::env::temp_dir().join("pingress-watcher-invalid.json");
+        std::fs::write(&path, "{ invalid").unwrap();
+        reload(&proxy_map, &tls, path.to_str().unwrap());
+        assert!(Arc::ptr_eq(&before, &proxy_map.load_full()));
+
+        std::fs::write(&path, serde_json::to_string(&empty()).unwrap()).unwrap();
+        reload(&proxy_map, &tls, path.to_str().unwrap());
+        assert!(!Arc::ptr_eq(&before, &proxy_map.load_full()));
+    }
 }